@@ -0,0 +1,33 @@
+-- Mailing lists and per-list subscription status
+CREATE TABLE lists(
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    name TEXT NOT NULL UNIQUE,
+    description TEXT NOT NULL DEFAULT '',
+    created_at timestamptz NOT NULL
+);
+
+CREATE TABLE list_memberships(
+    list_id uuid NOT NULL REFERENCES lists (id) ON DELETE CASCADE,
+    subscriber_uuid uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    status TEXT NOT NULL,
+    subscribed_at timestamptz NOT NULL,
+    PRIMARY KEY (list_id, subscriber_uuid)
+);
+
+-- a token now confirms (or unsubscribes) a subscriber for one list
+ALTER TABLE subscription_tokens
+    ADD COLUMN list_id uuid NULL REFERENCES lists (id) ON DELETE CASCADE;
+
+-- Move existing subscribers into the default list
+INSERT INTO lists (id, name, description, created_at)
+VALUES (gen_random_uuid(), 'default', 'Default newsletter', now());
+
+INSERT INTO list_memberships (list_id, subscriber_uuid, status, subscribed_at)
+SELECT l.id, s.id, s.status, s.subscribed_at
+FROM subscriptions s
+CROSS JOIN lists l
+WHERE l.name = 'default';
+
+UPDATE subscription_tokens
+SET list_id = (SELECT id FROM lists WHERE name = 'default');
@@ -0,0 +1,2 @@
+-- How often a subscriber wants to receive mail (see handlers::preferences::DigestFrequency)
+ALTER TABLE subscriptions ADD COLUMN digest_frequency TEXT NOT NULL DEFAULT 'immediate';
@@ -0,0 +1,10 @@
+-- Free-form per-subscriber data such as plan tier, signup source or locale
+ALTER TABLE subscriptions ADD COLUMN attributes JSONB NOT NULL DEFAULT '{}'::jsonb;
+
+CREATE TABLE subscriber_tags(
+    subscriber_uuid uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    tag TEXT NOT NULL,
+    created_at timestamptz NOT NULL,
+    PRIMARY KEY (subscriber_uuid, tag)
+);
+CREATE INDEX subscriber_tags_tag_idx ON subscriber_tags (tag);
@@ -0,0 +1,9 @@
+-- Named subscriber filters written in the segment expression language (see src/segment.rs)
+CREATE TABLE segments(
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    name TEXT NOT NULL UNIQUE,
+    expression TEXT NOT NULL,
+    created_by TEXT NOT NULL,
+    created_at timestamptz NOT NULL
+);
@@ -0,0 +1,2 @@
+-- Supports keyset pagination over (subscribed_at, id) in GET /admin/subscribers
+CREATE INDEX subscriptions_subscribed_at_id_idx ON subscriptions (subscribed_at, id);
@@ -0,0 +1,8 @@
+-- Tombstones of subscribers erased on request. Only a hash of the email is kept,
+-- enough to refuse re-importing the address but not to recover it.
+CREATE TABLE erased_subscribers(
+    email_hash TEXT NOT NULL,
+    PRIMARY KEY (email_hash),
+    erased_by TEXT NOT NULL,
+    erased_at timestamptz NOT NULL
+);
@@ -0,0 +1,14 @@
+-- Proof of double opt-in: one row when the form is submitted and one when the link is followed
+CREATE TABLE consent_events(
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    subscriber_uuid uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    list_id uuid REFERENCES lists (id) ON DELETE SET NULL,
+    event TEXT NOT NULL,
+    ip_address TEXT,
+    user_agent TEXT,
+    source TEXT,
+    consent_text_version TEXT,
+    occurred_at timestamptz NOT NULL
+);
+CREATE INDEX consent_events_subscriber_uuid_idx ON consent_events (subscriber_uuid);
@@ -0,0 +1,4 @@
+-- Tokens are stored as the hex SHA-256 of the value sent in links (see src/token.rs).
+-- Hashing the existing ones keeps links already in inboxes working.
+UPDATE subscription_tokens
+SET subscription_token = encode(sha256(convert_to(subscription_token, 'UTF8')), 'hex');
@@ -0,0 +1,22 @@
+-- Editable email templates. A template named after an email kind (see src/templates.rs)
+-- replaces the file template of that kind while it has an active version.
+CREATE TABLE email_templates(
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    name TEXT NOT NULL UNIQUE,
+    active_version INTEGER,
+    created_at timestamptz NOT NULL,
+    updated_at timestamptz NOT NULL
+);
+
+-- Versions are never updated, so any previous one can be made active again
+CREATE TABLE email_template_versions(
+    template_id uuid NOT NULL REFERENCES email_templates (id) ON DELETE CASCADE,
+    version INTEGER NOT NULL,
+    PRIMARY KEY (template_id, version),
+    subject TEXT NOT NULL,
+    html TEXT NOT NULL,
+    text TEXT NOT NULL,
+    created_by TEXT NOT NULL,
+    created_at timestamptz NOT NULL
+);
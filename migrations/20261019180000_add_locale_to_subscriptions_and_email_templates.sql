@@ -0,0 +1,8 @@
+-- NULL means the configured default locale
+ALTER TABLE subscriptions ADD COLUMN locale TEXT;
+
+-- A stored template can be specific to one locale; the one without a locale is the fallback
+ALTER TABLE email_templates ADD COLUMN locale TEXT;
+ALTER TABLE email_templates DROP CONSTRAINT email_templates_name_key;
+ALTER TABLE email_templates
+    ADD CONSTRAINT email_templates_name_locale_key UNIQUE NULLS NOT DISTINCT (name, locale);
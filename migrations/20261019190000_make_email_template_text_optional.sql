@@ -0,0 +1,2 @@
+-- without a text part the plain-text alternative is derived from the HTML
+ALTER TABLE email_template_versions ALTER COLUMN text DROP NOT NULL;
@@ -0,0 +1,38 @@
+-- Newsletters stored to be sent later, see src/scheduler.rs.
+-- status: scheduled -> sending -> sent, or scheduled -> cancelled
+CREATE TABLE newsletter_issues(
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    -- the body of POST /admin/newsletters: a NewsletterSource and its Defaults
+    content JSONB NOT NULL,
+    defaults JSONB NOT NULL,
+    -- a list with issues cannot be deleted, so an issue never widens to every subscriber
+    list_id uuid REFERENCES lists (id),
+    status TEXT NOT NULL,
+    scheduled_at timestamptz NOT NULL,
+    -- IANA name the editor scheduled in, kept to show the local time back
+    timezone TEXT NOT NULL,
+    created_by TEXT NOT NULL,
+    created_at timestamptz NOT NULL,
+    updated_at timestamptz NOT NULL,
+    started_at timestamptz,
+    completed_at timestamptz
+);
+CREATE INDEX newsletter_issues_due_idx ON newsletter_issues (scheduled_at) WHERE status = 'scheduled';
+
+-- One row per recipient, written when the issue starts sending.
+-- status: queued -> sent | failed | skipped
+CREATE TABLE issue_deliveries(
+    id uuid NOT NULL DEFAULT gen_random_uuid(),
+    PRIMARY KEY (id),
+    issue_id uuid NOT NULL REFERENCES newsletter_issues (id) ON DELETE CASCADE,
+    -- set to NULL on erasure so the issue's counts stay right without the subscriber
+    subscriber_uuid uuid REFERENCES subscriptions (id) ON DELETE SET NULL,
+    status TEXT NOT NULL,
+    error TEXT,
+    queued_at timestamptz NOT NULL,
+    sent_at timestamptz,
+    UNIQUE (issue_id, subscriber_uuid)
+);
+CREATE INDEX issue_deliveries_queued_idx ON issue_deliveries (queued_at) WHERE status = 'queued';
+CREATE INDEX issue_deliveries_subscriber_uuid_idx ON issue_deliveries (subscriber_uuid);
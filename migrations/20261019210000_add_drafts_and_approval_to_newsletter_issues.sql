@@ -0,0 +1,14 @@
+-- Issues start as drafts and go out only after an admin other than their authors approves them.
+-- status: draft -> approved -> sending -> sent, or draft | approved -> cancelled.
+-- Editing an approved issue makes it a draft again.
+ALTER TABLE newsletter_issues ALTER COLUMN scheduled_at DROP NOT NULL;
+ALTER TABLE newsletter_issues ADD COLUMN updated_by TEXT;
+UPDATE newsletter_issues SET updated_by = created_by;
+ALTER TABLE newsletter_issues ALTER COLUMN updated_by SET NOT NULL;
+ALTER TABLE newsletter_issues ADD COLUMN approved_by TEXT;
+ALTER TABLE newsletter_issues ADD COLUMN approved_at timestamptz;
+
+-- issues scheduled before approvals existed still go out as planned
+UPDATE newsletter_issues SET status = 'approved' WHERE status = 'scheduled';
+DROP INDEX newsletter_issues_due_idx;
+CREATE INDEX newsletter_issues_due_idx ON newsletter_issues (scheduled_at) WHERE status = 'approved';
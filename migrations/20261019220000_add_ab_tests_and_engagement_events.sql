@@ -0,0 +1,22 @@
+-- Subject A/B tests, see src/ab_test.rs.
+-- status: approved -> testing -> sending while the sample is sent and measured
+ALTER TABLE newsletter_issues ADD COLUMN ab_test JSONB;
+-- per-variant counts at the moment the winner was picked
+ALTER TABLE newsletter_issues ADD COLUMN ab_results JSONB;
+ALTER TABLE newsletter_issues ADD COLUMN ab_winner INTEGER;
+ALTER TABLE newsletter_issues ADD COLUMN ab_decided_at timestamptz;
+
+-- held: waits for the winner of the issue's A/B test
+ALTER TABLE issue_deliveries ADD COLUMN variant INTEGER;
+ALTER TABLE issue_deliveries ADD COLUMN in_sample BOOLEAN NOT NULL DEFAULT false;
+
+-- Opens and clicks of a delivery. Removed with it; the subject is only known through it.
+CREATE TABLE engagement_events(
+    id uuid NOT NULL DEFAULT gen_random_uuid(),
+    PRIMARY KEY (id),
+    delivery_id uuid NOT NULL REFERENCES issue_deliveries (id) ON DELETE CASCADE,
+    -- open | click
+    kind TEXT NOT NULL,
+    occurred_at timestamptz NOT NULL
+);
+CREATE INDEX engagement_events_delivery_id_idx ON engagement_events (delivery_id, kind);
@@ -0,0 +1,7 @@
+-- Open tracking, see src/tracking.rs
+ALTER TABLE engagement_events ADD COLUMN user_agent TEXT;
+-- why an event looks automated (apple_mpp, bot or prefetch), NULL when a person caused it
+ALTER TABLE engagement_events ADD COLUMN machine_reason TEXT;
+-- at most one open by a person and one automated open per delivery
+CREATE UNIQUE INDEX engagement_events_open_idx ON engagement_events (delivery_id, (machine_reason IS NULL))
+    WHERE kind = 'open';
@@ -0,0 +1,2 @@
+-- Click tracking, see src/tracking.rs: the destination as linked in the email, without UTM parameters
+ALTER TABLE engagement_events ADD COLUMN url TEXT;
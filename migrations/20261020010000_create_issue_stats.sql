@@ -0,0 +1,106 @@
+-- Per-issue counts behind GET /admin/issues/{id}/stats, see src/stats.rs. Triggers keep them
+-- up to date so reports never scan deliveries or events.
+
+-- engagement_events.kind is now also unsubscribe (through the issue's link), bounce or
+-- complaint (reported by the email API)
+
+-- deliveries per current status
+CREATE TABLE issue_delivery_counts(
+    issue_id uuid NOT NULL REFERENCES newsletter_issues (id) ON DELETE CASCADE,
+    status TEXT NOT NULL,
+    count BIGINT NOT NULL,
+    PRIMARY KEY (issue_id, status)
+);
+
+-- events per hour. sent and failed count deliveries reaching that status; the other metrics
+-- count deliveries with a first event of the kind by a person
+CREATE TABLE issue_event_counts(
+    issue_id uuid NOT NULL REFERENCES newsletter_issues (id) ON DELETE CASCADE,
+    -- sent | failed | opened | clicked | unsubscribed | bounced | complained
+    metric TEXT NOT NULL,
+    bucket timestamptz NOT NULL,
+    count BIGINT NOT NULL,
+    PRIMARY KEY (issue_id, metric, bucket)
+);
+
+CREATE FUNCTION count_inserted_deliveries() RETURNS trigger LANGUAGE plpgsql AS $$
+BEGIN
+    INSERT INTO issue_delivery_counts (issue_id, status, count)
+    SELECT issue_id, status, COUNT(*) FROM inserted_deliveries GROUP BY issue_id, status
+    ON CONFLICT (issue_id, status) DO UPDATE SET count = issue_delivery_counts.count + EXCLUDED.count;
+    RETURN NULL;
+END $$;
+
+CREATE FUNCTION count_updated_deliveries() RETURNS trigger LANGUAGE plpgsql AS $$
+BEGIN
+    INSERT INTO issue_delivery_counts (issue_id, status, count)
+    SELECT issue_id, status, SUM(change) FROM (
+        SELECT issue_id, status, 1 AS change FROM new_deliveries
+        UNION ALL
+        SELECT issue_id, status, -1 FROM old_deliveries
+    ) changes
+    GROUP BY issue_id, status
+    HAVING SUM(change) <> 0
+    ON CONFLICT (issue_id, status) DO UPDATE SET count = issue_delivery_counts.count + EXCLUDED.count;
+
+    -- failed deliveries have no sent_at
+    INSERT INTO issue_event_counts (issue_id, metric, bucket, count)
+    SELECT n.issue_id, n.status, date_trunc('hour', COALESCE(n.sent_at, now())), COUNT(*)
+    FROM new_deliveries n JOIN old_deliveries o ON o.id = n.id
+    WHERE n.status IN ('sent', 'failed') AND o.status <> n.status
+    GROUP BY 1, 2, 3
+    ON CONFLICT (issue_id, metric, bucket) DO UPDATE SET count = issue_event_counts.count + EXCLUDED.count;
+    RETURN NULL;
+END $$;
+
+CREATE FUNCTION count_engagement_event() RETURNS trigger LANGUAGE plpgsql AS $$
+BEGIN
+    IF NEW.machine_reason IS NOT NULL THEN
+        RETURN NULL;
+    END IF;
+    -- a concurrent first event of the same delivery waits until this one is committed
+    PERFORM pg_advisory_xact_lock(hashtextextended(NEW.delivery_id::text, 0));
+    IF EXISTS (
+        SELECT 1 FROM engagement_events e
+        WHERE e.delivery_id = NEW.delivery_id AND e.kind = NEW.kind
+        AND e.machine_reason IS NULL AND e.id <> NEW.id
+    ) THEN
+        RETURN NULL;
+    END IF;
+    INSERT INTO issue_event_counts (issue_id, metric, bucket, count)
+    SELECT issue_id,
+        CASE NEW.kind
+            WHEN 'open' THEN 'opened' WHEN 'click' THEN 'clicked' WHEN 'unsubscribe' THEN 'unsubscribed'
+            WHEN 'bounce' THEN 'bounced' WHEN 'complaint' THEN 'complained'
+        END,
+        date_trunc('hour', NEW.occurred_at),
+        1
+    FROM issue_deliveries WHERE id = NEW.delivery_id
+    ON CONFLICT (issue_id, metric, bucket) DO UPDATE SET count = issue_event_counts.count + 1;
+    RETURN NULL;
+END $$;
+
+-- deliveries are only removed with their issue, which removes its counts too
+CREATE TRIGGER issue_deliveries_inserted AFTER INSERT ON issue_deliveries
+    REFERENCING NEW TABLE AS inserted_deliveries
+    FOR EACH STATEMENT EXECUTE FUNCTION count_inserted_deliveries();
+CREATE TRIGGER issue_deliveries_updated AFTER UPDATE ON issue_deliveries
+    REFERENCING OLD TABLE AS old_deliveries NEW TABLE AS new_deliveries
+    FOR EACH STATEMENT EXECUTE FUNCTION count_updated_deliveries();
+CREATE TRIGGER engagement_events_inserted AFTER INSERT ON engagement_events
+    FOR EACH ROW EXECUTE FUNCTION count_engagement_event();
+
+INSERT INTO issue_delivery_counts (issue_id, status, count)
+SELECT issue_id, status, COUNT(*) FROM issue_deliveries GROUP BY issue_id, status;
+INSERT INTO issue_event_counts (issue_id, metric, bucket, count)
+SELECT issue_id, status, date_trunc('hour', COALESCE(sent_at, queued_at)), COUNT(*)
+FROM issue_deliveries WHERE status IN ('sent', 'failed')
+GROUP BY 1, 2, 3;
+INSERT INTO issue_event_counts (issue_id, metric, bucket, count)
+SELECT d.issue_id, CASE e.kind WHEN 'open' THEN 'opened' ELSE 'clicked' END,
+    date_trunc('hour', e.first_at), COUNT(*)
+FROM (
+    SELECT delivery_id, kind, MIN(occurred_at) AS first_at FROM engagement_events
+    WHERE machine_reason IS NULL GROUP BY delivery_id, kind
+) e JOIN issue_deliveries d ON d.id = e.delivery_id
+GROUP BY 1, 2, 3;
@@ -0,0 +1,77 @@
+-- issue_event_counts buckets become quarters of an hour instead of UTC hours. Every timezone
+-- offset in use is a multiple of 15 minutes, so hours and days of any timezone (Asia/Kolkata,
+-- Asia/Kathmandu...) are made of whole buckets.
+
+CREATE OR REPLACE FUNCTION count_updated_deliveries() RETURNS trigger LANGUAGE plpgsql AS $$
+BEGIN
+    INSERT INTO issue_delivery_counts (issue_id, status, count)
+    SELECT issue_id, status, SUM(change) FROM (
+        SELECT issue_id, status, 1 AS change FROM new_deliveries
+        UNION ALL
+        SELECT issue_id, status, -1 FROM old_deliveries
+    ) changes
+    GROUP BY issue_id, status
+    HAVING SUM(change) <> 0
+    ON CONFLICT (issue_id, status) DO UPDATE SET count = issue_delivery_counts.count + EXCLUDED.count;
+
+    -- failed deliveries have no sent_at
+    INSERT INTO issue_event_counts (issue_id, metric, bucket, count)
+    SELECT n.issue_id, n.status,
+        date_bin('15 minutes', COALESCE(n.sent_at, now()), TIMESTAMPTZ '2000-01-01 00:00:00+00'),
+        COUNT(*)
+    FROM new_deliveries n JOIN old_deliveries o ON o.id = n.id
+    WHERE n.status IN ('sent', 'failed') AND o.status <> n.status
+    GROUP BY 1, 2, 3
+    ON CONFLICT (issue_id, metric, bucket) DO UPDATE SET count = issue_event_counts.count + EXCLUDED.count;
+    RETURN NULL;
+END $$;
+
+CREATE OR REPLACE FUNCTION count_engagement_event() RETURNS trigger LANGUAGE plpgsql AS $$
+BEGIN
+    IF NEW.machine_reason IS NOT NULL THEN
+        RETURN NULL;
+    END IF;
+    -- a concurrent first event of the same delivery waits until this one is committed
+    PERFORM pg_advisory_xact_lock(hashtextextended(NEW.delivery_id::text, 0));
+    IF EXISTS (
+        SELECT 1 FROM engagement_events e
+        WHERE e.delivery_id = NEW.delivery_id AND e.kind = NEW.kind
+        AND e.machine_reason IS NULL AND e.id <> NEW.id
+    ) THEN
+        RETURN NULL;
+    END IF;
+    INSERT INTO issue_event_counts (issue_id, metric, bucket, count)
+    SELECT issue_id,
+        CASE NEW.kind
+            WHEN 'open' THEN 'opened' WHEN 'click' THEN 'clicked' WHEN 'unsubscribe' THEN 'unsubscribed'
+            WHEN 'bounce' THEN 'bounced' WHEN 'complaint' THEN 'complained'
+        END,
+        date_bin('15 minutes', NEW.occurred_at, TIMESTAMPTZ '2000-01-01 00:00:00+00'),
+        1
+    FROM issue_deliveries WHERE id = NEW.delivery_id
+    ON CONFLICT (issue_id, metric, bucket) DO UPDATE SET count = issue_event_counts.count + 1;
+    RETURN NULL;
+END $$;
+
+-- hour buckets cannot be split, so the counts are rebuilt from deliveries and events
+LOCK TABLE issue_deliveries, engagement_events IN SHARE MODE;
+DELETE FROM issue_event_counts;
+INSERT INTO issue_event_counts (issue_id, metric, bucket, count)
+SELECT issue_id, status,
+    date_bin('15 minutes', COALESCE(sent_at, queued_at), TIMESTAMPTZ '2000-01-01 00:00:00+00'),
+    COUNT(*)
+FROM issue_deliveries WHERE status IN ('sent', 'failed')
+GROUP BY 1, 2, 3;
+INSERT INTO issue_event_counts (issue_id, metric, bucket, count)
+SELECT d.issue_id,
+    CASE e.kind
+        WHEN 'open' THEN 'opened' WHEN 'click' THEN 'clicked' WHEN 'unsubscribe' THEN 'unsubscribed'
+        WHEN 'bounce' THEN 'bounced' WHEN 'complaint' THEN 'complained'
+    END,
+    date_bin('15 minutes', e.first_at, TIMESTAMPTZ '2000-01-01 00:00:00+00'),
+    COUNT(*)
+FROM (
+    SELECT delivery_id, kind, MIN(occurred_at) AS first_at FROM engagement_events
+    WHERE machine_reason IS NULL GROUP BY delivery_id, kind
+) e JOIN issue_deliveries d ON d.id = e.delivery_id
+GROUP BY 1, 2, 3;
@@ -0,0 +1,20 @@
+-- A token is only accepted for the action it was mailed for (confirm, unsubscribe,
+-- preferences or data, see src/signed_link.rs), and only until it expires.
+ALTER TABLE subscription_tokens
+    ADD COLUMN action TEXT NULL,
+    ADD COLUMN expires_at timestamptz NULL;
+
+-- Existing tokens were mailed in confirmation links, or in the unsubscribe links of newsletters,
+-- which only go to confirmed members. They get the expiries of config/base.yaml from now.
+UPDATE subscription_tokens t
+SET action = CASE WHEN COALESCE(
+        (SELECT m.status FROM list_memberships m
+        WHERE m.list_id = t.list_id AND m.subscriber_uuid = t.subscriber_uuid),
+        (SELECT s.status FROM subscriptions s WHERE s.id = t.subscriber_uuid)
+    ) = 'not-confirmed' THEN 'confirm' ELSE 'unsubscribe' END;
+UPDATE subscription_tokens
+SET expires_at = now() + CASE action WHEN 'confirm' THEN interval '72 hours' ELSE interval '365 days' END;
+
+ALTER TABLE subscription_tokens
+    ALTER COLUMN action SET NOT NULL,
+    ALTER COLUMN expires_at SET NOT NULL;
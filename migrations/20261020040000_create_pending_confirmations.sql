@@ -0,0 +1,9 @@
+-- Pending members brought in by an import, waiting for the scheduler to mail their
+-- confirmation link, see src/import.rs.
+CREATE TABLE pending_confirmations(
+    subscriber_uuid uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    list_id uuid NOT NULL REFERENCES lists (id) ON DELETE CASCADE,
+    queued_at timestamptz NOT NULL,
+    PRIMARY KEY (subscriber_uuid, list_id)
+);
+CREATE INDEX pending_confirmations_queued_at ON pending_confirmations (queued_at);
@@ -0,0 +1,3 @@
+-- An issue can go to the confirmed subscribers matching a saved segment (see src/segment.rs),
+-- selected when the scheduler starts it. A segment used by an issue cannot be deleted.
+ALTER TABLE newsletter_issues ADD COLUMN segment_id uuid NULL REFERENCES segments (id);
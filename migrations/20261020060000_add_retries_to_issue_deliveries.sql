@@ -0,0 +1,5 @@
+-- A delivery the email API failed to take for a transient reason stays queued and is tried
+-- again at next_attempt_at, see src/scheduler.rs.
+ALTER TABLE issue_deliveries
+    ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN next_attempt_at timestamptz NULL;
@@ -0,0 +1,6 @@
+-- A confirmation email the email API failed to take for a transient reason stays queued and is
+-- tried again at next_attempt_at, see src/import.rs.
+ALTER TABLE pending_confirmations
+    ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN next_attempt_at timestamptz NULL,
+    ADD COLUMN last_error TEXT NULL;
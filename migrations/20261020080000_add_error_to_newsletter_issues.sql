@@ -0,0 +1,4 @@
+-- An approved issue the scheduler cannot start, e.g. because its segment no longer parses, is
+-- marked failed with the reason instead of holding up the other due issues, see src/scheduler.rs.
+-- status: approved -> failed
+ALTER TABLE newsletter_issues ADD COLUMN error TEXT NULL;
@@ -0,0 +1,3 @@
+-- When a subscriber was last mailed links to manage their subscription, so
+-- `POST /preferences` mails an address at most once per `links.request_interval_minutes`.
+ALTER TABLE subscriptions ADD COLUMN links_requested_at timestamptz NULL;
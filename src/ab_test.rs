@@ -0,0 +1,271 @@
+//! Subject A/B tests of newsletter issues.
+//!
+//! When an issue with an [`AbTest`] becomes due, every recipient gets a delivery as usual,
+//! but only a random `sample_percent` of them are queued, spread evenly over the variant
+//! subjects; the others are `held` and the issue is `testing`. Once the sample has been sent
+//! and `window_minutes` have passed since the issue started, [`decide_due_tests`] counts the
+//! sampled deliveries opened or clicked by a person (see [`crate::tracking`]), records the
+//! counts and the winner on the issue, and queues the held deliveries with the winning subject.
+//!
+//! The variant each sampled recipient got stays on their delivery (`variant`, `in_sample`).
+//! A test can only be set up while its metric is tracked (`tracking.opens` or
+//! `tracking.clicks`); otherwise every rate would be 0.
+
+use crate::configuration::TrackingSettings;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+pub const MAX_VARIANTS: usize = 5;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AbTest {
+    /// one per variant; merge tags work as in the newsletter's own subject, which is not used
+    pub subjects: Vec<String>,
+    /// share of the recipients, in percent, who get one of the variants
+    pub sample_percent: u8,
+    pub metric: AbMetric,
+    /// how long after the issue starts sending the winner is picked
+    pub window_minutes: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AbMetric {
+    Opens,
+    Clicks,
+}
+
+impl AbMetric {
+    /// `engagement_events.kind` counted by this metric
+    pub fn event_kind(self) -> &'static str {
+        match self {
+            AbMetric::Opens => "open",
+            AbMetric::Clicks => "click",
+        }
+    }
+
+    /// whether `tracking` records the events of this metric
+    pub fn is_tracked(self, tracking: &TrackingSettings) -> bool {
+        match self {
+            AbMetric::Opens => tracking.opens,
+            AbMetric::Clicks => tracking.clicks,
+        }
+    }
+}
+
+/// Measured performance of one variant, as stored in `newsletter_issues.ab_results`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct VariantResult {
+    pub variant: usize,
+    pub subject: String,
+    pub recipients: i64,
+    pub sent: i64,
+    /// sent deliveries with at least one event of the test's metric
+    pub engaged: i64,
+    /// `engaged / sent`, 0 when nothing was sent
+    pub rate: f64,
+}
+
+impl AbTest {
+    /// Why the settings cannot be used with `tracking`, if they cannot.
+    pub fn problem(&self, tracking: &TrackingSettings) -> Option<String> {
+        if !(2..=MAX_VARIANTS).contains(&self.subjects.len()) {
+            return Some(format!("an A/B test needs 2 to {MAX_VARIANTS} subjects"));
+        }
+        if !(1..=99).contains(&self.sample_percent) {
+            return Some("sample_percent must be between 1 and 99".to_string());
+        }
+        if self.window_minutes == 0 {
+            return Some("window_minutes must be at least 1".to_string());
+        }
+        if !self.metric.is_tracked(tracking) {
+            // every rate would be 0 and the first variant would always win
+            let kind = self.metric.event_kind();
+            return Some(format!("tracking.{kind}s must be enabled to test on {kind}s"));
+        }
+        None
+    }
+}
+
+/// The variant with the best rate; the first of them on a tie.
+pub fn pick_winner(results: &[VariantResult]) -> Option<usize> {
+    results
+        .iter()
+        .fold(None, |best: Option<&VariantResult>, r| match best {
+            Some(b) if b.rate >= r.rate => Some(b),
+            _ => Some(r),
+        })
+        .map(|r| r.variant)
+}
+
+/// Queues the sample of a due issue whose deliveries were all written as `held`.
+pub async fn start_sample(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+    ab_test: &AbTest,
+) -> anyhow::Result<()> {
+    let total = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM issue_deliveries WHERE issue_id = $1"#,
+        issue_id
+    )
+    .fetch_one(&mut **transaction)
+    .await
+    .context("error counting recipients")?;
+    let variants = ab_test.subjects.len() as i64;
+    // at least one recipient per variant while there are enough of them
+    let size = ((total * ab_test.sample_percent as i64 + 99) / 100)
+        .max(variants)
+        .min(total);
+    sqlx::query!(
+        r#"WITH sample AS (
+            SELECT id, row_number() OVER (ORDER BY random()) - 1 AS n
+            FROM issue_deliveries WHERE issue_id = $1
+        )
+        UPDATE issue_deliveries d
+        SET status = 'queued', in_sample = true, variant = (s.n % $2)::integer
+        FROM sample s
+        WHERE d.id = s.id AND s.n < $3"#,
+        issue_id,
+        variants,
+        size,
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("error queueing the A/B sample")?;
+    Ok(())
+}
+
+/// Picks the winners of the tests whose sample is sent and whose window is over, and queues
+/// the rest of their recipients. Returns the issues decided.
+pub async fn decide_due_tests(pool: &PgPool, now: DateTime<Utc>) -> anyhow::Result<Vec<Uuid>> {
+    let mut transaction = pool.begin().await.context("error starting transaction")?;
+    let due = sqlx::query!(
+        r#"SELECT i.id, i.ab_test AS "ab_test!" FROM newsletter_issues i
+        WHERE i.status = 'testing'
+        AND i.started_at + make_interval(mins => (i.ab_test->>'window_minutes')::integer) <= $1
+        AND NOT EXISTS (
+            SELECT 1 FROM issue_deliveries d WHERE d.issue_id = i.id AND d.status = 'queued'
+        )
+        FOR UPDATE SKIP LOCKED"#,
+        now
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("error fetching A/B tests to decide")?;
+
+    let mut decided = Vec::with_capacity(due.len());
+    for issue in due {
+        let ab_test: AbTest =
+            serde_json::from_value(issue.ab_test).context("error reading A/B test settings")?;
+        let counts = sqlx::query!(
+            r#"SELECT d.variant AS "variant!",
+                COUNT(*) AS "recipients!",
+                COUNT(*) FILTER (WHERE d.status = 'sent') AS "sent!",
+                COUNT(*) FILTER (WHERE d.status = 'sent' AND EXISTS (
+                    SELECT 1 FROM engagement_events e
+                    WHERE e.delivery_id = d.id AND e.kind = $2 AND e.occurred_at <= $3
+                    AND e.machine_reason IS NULL
+                )) AS "engaged!"
+            FROM issue_deliveries d
+            WHERE d.issue_id = $1 AND d.in_sample
+            GROUP BY d.variant"#,
+            issue.id,
+            ab_test.metric.event_kind(),
+            now,
+        )
+        .fetch_all(&mut *transaction)
+        .await
+        .context("error measuring A/B variants")?;
+        let results: Vec<VariantResult> = ab_test
+            .subjects
+            .iter()
+            .enumerate()
+            .map(|(variant, subject)| {
+                let count = counts.iter().find(|c| c.variant as usize == variant);
+                let (recipients, sent, engaged) =
+                    count.map_or((0, 0, 0), |c| (c.recipients, c.sent, c.engaged));
+                VariantResult {
+                    variant,
+                    subject: subject.clone(),
+                    recipients,
+                    sent,
+                    engaged,
+                    rate: if sent > 0 { engaged as f64 / sent as f64 } else { 0.0 },
+                }
+            })
+            .collect();
+        let winner = pick_winner(&results).expect("an A/B test has variants") as i32;
+
+        sqlx::query!(
+            r#"UPDATE newsletter_issues
+            SET status = 'sending', ab_results = $2, ab_winner = $3, ab_decided_at = $4, updated_at = $4
+            WHERE id = $1"#,
+            issue.id,
+            serde_json::to_value(&results).context("error serializing A/B results")?,
+            winner,
+            now,
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("error recording A/B winner")?;
+        sqlx::query!(
+            r#"UPDATE issue_deliveries SET status = 'queued', variant = $2, queued_at = $3
+            WHERE issue_id = $1 AND status = 'held'"#,
+            issue.id,
+            winner,
+            now,
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("error queueing the remaining deliveries")?;
+        decided.push(issue.id);
+    }
+    transaction.commit().await.context("error commiting transaction")?;
+    Ok(decided)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(variant: usize, rate: f64) -> VariantResult {
+        VariantResult {
+            variant,
+            subject: format!("subject {variant}"),
+            recipients: 10,
+            sent: 10,
+            engaged: (rate * 10.0) as i64,
+            rate,
+        }
+    }
+
+    #[test]
+    fn the_best_rate_wins_and_ties_go_to_the_first_variant() {
+        assert_eq!(pick_winner(&[result(0, 0.1), result(1, 0.3), result(2, 0.2)]), Some(1));
+        assert_eq!(pick_winner(&[result(0, 0.2), result(1, 0.2)]), Some(0));
+        assert_eq!(pick_winner(&[]), None);
+    }
+
+    #[test]
+    fn settings_are_checked() {
+        let ab_test = AbTest {
+            subjects: vec!["a".to_string(), "b".to_string()],
+            sample_percent: 20,
+            metric: AbMetric::Opens,
+            window_minutes: 60,
+        };
+        let tracking = TrackingSettings { opens: true, clicks: false, utm: None };
+        assert_eq!(ab_test.problem(&tracking), None);
+        for broken in [
+            AbTest { subjects: vec!["a".to_string()], ..ab_test.clone() },
+            AbTest { sample_percent: 100, ..ab_test.clone() },
+            AbTest { window_minutes: 0, ..ab_test.clone() },
+            AbTest { metric: AbMetric::Clicks, ..ab_test.clone() },
+        ] {
+            assert!(broken.problem(&tracking).is_some(), "{broken:?}");
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use crate::AppState;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::{StatusCode, header};
+use axum::response::{IntoResponse, Response};
+
+/// An admin authenticated with `Authorization: Bearer <token>`.
+/// Tokens are configured under `admin.api_tokens`.
+#[derive(Debug, Clone)]
+pub struct AdminUser {
+    pub name: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuthError {
+    #[error("missing bearer token")]
+    MissingToken,
+
+    #[error("invalid bearer token")]
+    InvalidToken,
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+            self.to_string(),
+        )
+            .into_response()
+    }
+}
+
+impl FromRequestParts<AppState> for AdminUser {
+    type Rejection = AuthError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .ok_or(AuthError::MissingToken)?;
+        let name = state
+            .conf
+            .admin
+            .find_admin(token.trim())
+            .ok_or(AuthError::InvalidToken)?;
+        Ok(AdminUser {
+            name: name.to_string(),
+        })
+    }
+}
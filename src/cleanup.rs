@@ -0,0 +1,68 @@
+//! Periodic removal of subscribers who never confirmed.
+//!
+//! Every replica runs the loop; a transaction-scoped advisory lock makes the others skip a
+//! round while one of them is deleting.
+
+use sqlx::PgPool;
+use chrono::{Duration, Utc};
+use std::sync::Arc;
+
+/// advisory lock key held while deleting pending subscribers
+pub const PENDING_CLEANUP_LOCK: i64 = 0x656d_6169_6c01;
+
+/// Runs [`delete_stale_pending`] every `interval`. Never returns.
+pub async fn run_pending_cleanup(pool: Arc<PgPool>, ttl: Duration, interval: std::time::Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        match delete_stale_pending(&pool, ttl).await {
+            Ok(Some(removed)) => tracing::info!(removed, "removed never-confirmed subscribers"),
+            Ok(None) => tracing::debug!("pending cleanup is running on another replica"),
+            Err(e) => tracing::error!(error = %e, "error removing never-confirmed subscribers"),
+        }
+    }
+}
+
+/// Deletes `not-confirmed` subscribers who subscribed more than `ttl` ago, with their tokens.
+/// A subscriber who confirmed a membership of any list, asked for a new confirmation link
+/// within `ttl`, or whose confirmation email from an import is still queued, is kept.
+///
+/// Returns `None` without deleting anything when another process holds the cleanup lock.
+pub async fn delete_stale_pending(pool: &PgPool, ttl: Duration) -> anyhow::Result<Option<u64>> {
+    let mut transaction = pool.begin().await?;
+    let locked = sqlx::query_scalar!("SELECT pg_try_advisory_xact_lock($1)", PENDING_CLEANUP_LOCK)
+        .fetch_one(&mut *transaction)
+        .await?;
+    if locked != Some(true) {
+        return Ok(None);
+    }
+
+    let cutoff = Utc::now() - ttl;
+    let stale: Vec<_> = sqlx::query!(
+        r#"SELECT s.id FROM subscriptions s
+        WHERE s.status = 'not-confirmed' AND s.subscribed_at < $1
+            AND NOT EXISTS (
+                SELECT 1 FROM list_memberships m
+                WHERE m.subscriber_uuid = s.id
+                    AND (m.subscribed_at >= $1 OR m.status = 'confirmed')
+            )
+            AND NOT EXISTS (SELECT 1 FROM pending_confirmations p WHERE p.subscriber_uuid = s.id)
+        FOR UPDATE SKIP LOCKED"#,
+        cutoff
+    )
+    .fetch_all(&mut *transaction)
+    .await?
+    .into_iter()
+    .map(|r| r.id)
+    .collect();
+
+    sqlx::query!("DELETE FROM subscription_tokens WHERE subscriber_uuid = ANY($1)", &stale)
+        .execute(&mut *transaction)
+        .await?;
+    let removed = sqlx::query!("DELETE FROM subscriptions WHERE id = ANY($1)", &stale)
+        .execute(&mut *transaction)
+        .await?
+        .rows_affected();
+    transaction.commit().await?;
+    Ok(Some(removed))
+}
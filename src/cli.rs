@@ -0,0 +1,54 @@
+//! Command line subcommands besides running the server.
+
+use crate::configuration::get_configuration;
+use crate::errors::AppError;
+use crate::import::{ImportStatus, import_subscribers};
+use sqlx::postgres::PgPoolOptions;
+use uuid::Uuid;
+
+pub const USAGE: &str = "usage:
+  email_sender [serve]
+  email_sender import <file.csv> --status confirmed|pending [--list <list id>]";
+
+/// `email_sender import`: streams a CSV file into `subscriptions` and prints the report as JSON.
+pub async fn import(args: &[String]) -> Result<(), AppError> {
+    let usage = |msg: &str| AppError::UsageError(format!("{msg}\n{USAGE}"));
+    let mut path = None;
+    let mut status = None;
+    let mut list_id = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--status" => {
+                let value = args.next().ok_or_else(|| usage("--status needs a value"))?;
+                status = Some(ImportStatus::try_from(value.as_str()).map_err(|e| usage(&e.to_string()))?);
+            }
+            "--list" => {
+                let value = args.next().ok_or_else(|| usage("--list needs a value"))?;
+                list_id = Some(Uuid::parse_str(value).map_err(|e| usage(&e.to_string()))?);
+            }
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(usage(&format!("unexpected argument `{arg}`"))),
+        }
+    }
+    let path = path.ok_or_else(|| usage("missing CSV file"))?;
+    let status = status.ok_or_else(|| usage("missing --status"))?;
+
+    let conf = get_configuration()?;
+    let pool = PgPoolOptions::new()
+        .connect_with(conf.database.connection_options())
+        .await
+        .map_err(|e| AppError::DbError(e.to_string()))?;
+    let file = tokio::fs::File::open(path)
+        .await
+        .map_err(|e| usage(&format!("cannot open {path}: {e}")))?;
+
+    let report = import_subscribers(&pool, file, status, list_id)
+        .await
+        .map_err(|e| AppError::Unexpected(e.into()))?;
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&report).map_err(|e| AppError::Unexpected(e.into()))?
+    );
+    Ok(())
+}
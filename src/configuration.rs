@@ -1,13 +1,169 @@
+use crate::signed_link::LinkAction;
 use crate::{errors::AppError, validation::ValidatedEmail};
 use config::{Config, File, FileFormat};
 use serde_aux::field_attributes::deserialize_number_from_string;
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
+use std::collections::HashMap;
+use subtle::ConstantTimeEq;
 
 #[derive(serde::Deserialize, Debug)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub admin: AdminSettings,
+    pub cleanup: CleanupSettings,
+    pub links: LinkSettings,
+    pub scheduler: SchedulerSettings,
+    pub tracking: TrackingSettings,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct AdminSettings {
+    /// admin name -> api token. the name is used to tell admins apart (e.g. in audit columns).
+    pub api_tokens: HashMap<String, String>,
+    /// domains test sends of an issue may go to, so drafts never reach subscribers
+    pub internal_domains: Vec<String>,
+}
+
+impl AdminSettings {
+    /// Whether `email` is at one of `internal_domains`.
+    pub fn is_internal(&self, email: &str) -> bool {
+        email.rsplit_once('@').is_some_and(|(_, domain)| {
+            self.internal_domains
+                .iter()
+                .any(|d| d.eq_ignore_ascii_case(domain.trim()))
+        })
+    }
+
+    /// Returns the name of the admin owning `token`, if any. Tokens are compared in constant
+    /// time, so response times do not tell how much of a guess was right.
+    pub fn find_admin(&self, token: &str) -> Option<&str> {
+        self.api_tokens
+            .iter()
+            .find(|(_, t)| t.as_bytes().ct_eq(token.as_bytes()).into())
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct CleanupSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub pending_ttl_hours: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub interval_seconds: u64,
+}
+
+impl CleanupSettings {
+    pub fn pending_ttl(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.pending_ttl_hours as i64)
+    }
+
+    pub fn interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.interval_seconds)
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct SchedulerSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub interval_seconds: u64,
+    /// deliveries sent per round before checking for due issues again
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub batch_size: usize,
+    /// tries of a delivery failing for a transient reason before it is marked `failed`
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts: i32,
+    /// wait before the second try, doubled for each one after it
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub retry_backoff_seconds: i64,
+}
+
+impl SchedulerSettings {
+    pub fn interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.interval_seconds)
+    }
+
+    /// How long to wait after the `attempt`th try (counting from 1) failed.
+    pub fn retry_backoff(&self, attempt: i32) -> chrono::Duration {
+        chrono::Duration::seconds(self.retry_backoff_seconds << (attempt - 1).clamp(0, 16))
+    }
+}
+
+/// Engagement tracking of scheduled issues, see [`crate::tracking`]. Everything is off unless
+/// enabled here.
+#[derive(serde::Deserialize, Debug)]
+pub struct TrackingSettings {
+    /// add a pixel to HTML bodies and record when it is loaded
+    pub opens: bool,
+    /// send links in HTML bodies through a redirect that records clicks
+    pub clicks: bool,
+    /// parameters added to the destination of tracked links
+    #[serde(default)]
+    pub utm: Option<UtmSettings>,
+}
+
+/// `utm_*` parameters for analytics of the linked sites. Links that already have a parameter
+/// keep their own value.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct UtmSettings {
+    pub source: String,
+    pub medium: String,
+    /// the issue id when not set
+    pub campaign: Option<String>,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkMode {
+    /// links carry a random token stored in `subscription_tokens`
+    Token,
+    /// links carry an HMAC signature, see [`crate::signed_link`]
+    Signed,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct LinkSettings {
+    pub mode: LinkMode,
+    /// id of the key in `keys` that signs new links
+    pub signing_key: String,
+    /// key id -> secret. all of them are accepted when verifying a link.
+    pub keys: HashMap<String, String>,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub expiry_hours: u64,
+    /// signed unsubscribe links in newsletters stay valid much longer than confirmations
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub unsubscribe_expiry_days: u64,
+    /// an address asking for its preference center links again sooner gets no new mail
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub request_interval_minutes: u64,
+}
+
+impl LinkSettings {
+    pub fn expiry(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.expiry_hours as i64)
+    }
+
+    pub fn unsubscribe_expiry(&self) -> chrono::Duration {
+        chrono::Duration::days(self.unsubscribe_expiry_days as i64)
+    }
+
+    pub fn request_interval(&self) -> chrono::Duration {
+        chrono::Duration::minutes(self.request_interval_minutes as i64)
+    }
+
+    /// How long a link for `action` stays valid: unsubscribe links in newsletters last
+    /// `unsubscribe_expiry_days`, the others `expiry_hours`.
+    pub fn expiry_of(&self, action: LinkAction) -> chrono::Duration {
+        match action {
+            LinkAction::Unsubscribe => self.unsubscribe_expiry(),
+            LinkAction::Confirm | LinkAction::Preferences | LinkAction::Data => self.expiry(),
+        }
+    }
+
+    pub fn signing_secret(&self) -> Option<&str> {
+        self.keys.get(&self.signing_key).map(String::as_str)
+    }
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -15,7 +171,9 @@ pub struct EmailClientSettings {
     pub email_server_url: String,
     pub sender_email: String,
     pub authorization_token: String,
-    pub timeout_seconds: u64
+    pub timeout_seconds: u64,
+    /// bearer token the email API sends with its webhooks
+    pub webhook_token: String,
 }
 
 impl EmailClientSettings {
@@ -44,7 +202,27 @@ pub struct ApplicationSettings {
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
     pub host: String,
-    // pub base_url: String,
+    pub templates_dir: String,
+    /// one of `locales`, used for subscribers without a locale of their own
+    pub default_locale: String,
+    pub locales: Vec<String>,
+    /// relative URLs in outgoing emails are resolved against this
+    pub base_url: String,
+}
+
+impl ApplicationSettings {
+    /// The configured spelling of `locale`, if it is one of `locales`.
+    pub fn supported_locale(&self, locale: &str) -> Option<&str> {
+        self.locales
+            .iter()
+            .find(|l| l.eq_ignore_ascii_case(locale))
+            .map(String::as_str)
+    }
+
+    /// `locale`, or `default_locale` when the subscriber has none.
+    pub fn locale_or_default<'a>(&'a self, locale: Option<&'a str>) -> &'a str {
+        locale.unwrap_or(&self.default_locale)
+    }
 }
 
 impl DatabaseSettings {
@@ -54,14 +232,13 @@ impl DatabaseSettings {
         } else {
             PgSslMode::Prefer
         };
-        let opt = PgConnectOptions::new()
+        PgConnectOptions::new()
             .host(&self.host)
             .username(&self.username)
             .password(&self.password)
             .database(&self.database_name)
             .port(self.port)
-            .ssl_mode(ssl_mode);
-        opt
+            .ssl_mode(ssl_mode)
     }
 }
 
@@ -90,9 +267,27 @@ pub fn get_configuration() -> Result<Settings, AppError> {
         )
         .build()
         .map_err(|e| AppError::ConfigError(e.to_string()))?;
-    settings
+    let settings = settings
         .try_deserialize::<Settings>()
-        .map_err(|e| AppError::ConfigError(e.to_string()))
+        .map_err(|e| AppError::ConfigError(e.to_string()))?;
+    if settings.application.supported_locale(&settings.application.default_locale).is_none() {
+        return Err(AppError::ConfigError(format!(
+            "application.default_locale `{}` is not one of application.locales",
+            settings.application.default_locale
+        )));
+    }
+    if settings.email_client.webhook_token.trim().is_empty() {
+        return Err(AppError::ConfigError(
+            "email_client.webhook_token must not be empty".to_string(),
+        ));
+    }
+    if settings.links.mode == LinkMode::Signed && settings.links.signing_secret().is_none() {
+        return Err(AppError::ConfigError(format!(
+            "links.signing_key `{}` is not one of links.keys",
+            settings.links.signing_key
+        )));
+    }
+    Ok(settings)
 }
 
 pub enum Env {
@@ -0,0 +1,126 @@
+//! Consent records proving how and when each subscriber opted in.
+
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::header;
+use axum::http::request::Parts;
+use serde::Serialize;
+use sqlx::PgExecutor;
+use sqlx::types::chrono::{DateTime, Utc};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use uuid::Uuid;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConsentEventKind {
+    /// the subscription form was submitted
+    Subscribed,
+    /// the confirmation link was followed
+    Confirmed,
+}
+
+impl ConsentEventKind {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ConsentEventKind::Subscribed => "subscribed",
+            ConsentEventKind::Confirmed => "confirmed",
+        }
+    }
+}
+
+/// Where a request came from. Fields are `None` when the server runs without connect info
+/// or the client does not send the header.
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    /// not part of the consent record; used to pick the subscriber's locale
+    pub accept_language: Option<String>,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientInfo {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let ip_address = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string());
+        let header = |name| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+        Ok(ClientInfo {
+            ip_address,
+            user_agent: header(header::USER_AGENT),
+            accept_language: header(header::ACCEPT_LANGUAGE),
+        })
+    }
+}
+
+/// What the subscriber agreed to, as submitted with the form.
+#[derive(Debug, Clone, Default)]
+pub struct ConsentDetails<'a> {
+    /// the form or page the subscription came from
+    pub source: Option<&'a str>,
+    pub consent_text_version: Option<&'a str>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ConsentEvent {
+    pub id: Uuid,
+    pub list_id: Option<Uuid>,
+    pub event: String,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub source: Option<String>,
+    pub consent_text_version: Option<String>,
+    pub occurred_at: DateTime<Utc>,
+}
+
+pub async fn record(
+    executor: impl PgExecutor<'_>,
+    subscriber_uuid: Uuid,
+    list_id: Option<Uuid>,
+    kind: ConsentEventKind,
+    client: &ClientInfo,
+    details: &ConsentDetails<'_>,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"INSERT INTO consent_events
+        (id, subscriber_uuid, list_id, event, ip_address, user_agent, source, consent_text_version, occurred_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)"#,
+        Uuid::new_v4(),
+        subscriber_uuid,
+        list_id,
+        kind.as_str(),
+        client.ip_address,
+        client.user_agent,
+        details.source,
+        details.consent_text_version,
+        Utc::now(),
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// Consent history of a subscriber, oldest first.
+pub async fn events(
+    executor: impl PgExecutor<'_>,
+    subscriber_uuid: Uuid,
+) -> anyhow::Result<Vec<ConsentEvent>> {
+    let events = sqlx::query_as!(
+        ConsentEvent,
+        r#"SELECT id, list_id, event, ip_address, user_agent, source, consent_text_version, occurred_at
+        FROM consent_events WHERE subscriber_uuid = $1
+        ORDER BY occurred_at"#,
+        subscriber_uuid
+    )
+    .fetch_all(executor)
+    .await?;
+    Ok(events)
+}
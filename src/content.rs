@@ -0,0 +1,190 @@
+//! Email content written in Markdown.
+//!
+//! A document starts with front matter between `---` lines giving the `subject` and an
+//! optional `preheader`, one `key: value` per line:
+//!
+//! ```text
+//! ---
+//! subject: October news
+//! preheader: What changed this month
+//! ---
+//! # Hello
+//! ```
+//!
+//! The body is rendered to HTML, sanitized so raw HTML in the Markdown cannot add scripts or
+//! unsafe links, and wrapped in the layout of [`EmailTemplates`]. The plain-text part is
+//! derived from the rendered body, without the layout.
+
+use crate::templates::{EmailTemplates, LayoutContext, RenderedEmail, TemplateError};
+use crate::plain_text;
+use pulldown_cmark::{Options, Parser};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Document {
+    pub subject: String,
+    pub preheader: Option<String>,
+    /// the Markdown after the front matter
+    pub body: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ContentError {
+    #[error("the content must start with front matter between `---` lines")]
+    MissingFrontMatter,
+
+    #[error("invalid front matter on line {0}: expected `key: value`")]
+    InvalidFrontMatter(usize),
+
+    #[error("unknown front matter field: {0}")]
+    UnknownField(String),
+
+    #[error("the front matter has no subject")]
+    MissingSubject,
+
+    #[error(transparent)]
+    Template(#[from] TemplateError),
+}
+
+impl Document {
+    pub fn parse(source: &str) -> Result<Self, ContentError> {
+        let source = source.strip_prefix('\u{feff}').unwrap_or(source);
+        let rest = source
+            .strip_prefix("---")
+            .and_then(|r| r.strip_prefix("\r\n").or_else(|| r.strip_prefix('\n')))
+            .ok_or(ContentError::MissingFrontMatter)?;
+
+        let (mut subject, mut preheader) = (None, None);
+        let mut offset = 0;
+        let mut body = None;
+        // line 1 is the opening `---`
+        for (line, number) in rest.split_inclusive('\n').zip(2..) {
+            offset += line.len();
+            let line = line.trim();
+            if line == "---" {
+                body = Some(&rest[offset..]);
+                break;
+            }
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once(':')
+                .ok_or(ContentError::InvalidFrontMatter(number))?;
+            let value = unquote(value.trim()).to_string();
+            match key.trim() {
+                "subject" => subject = Some(value),
+                "preheader" => preheader = Some(value).filter(|p| !p.is_empty()),
+                other => return Err(ContentError::UnknownField(other.to_string())),
+            }
+        }
+
+        let body = body.ok_or(ContentError::MissingFrontMatter)?;
+        let subject = subject
+            .filter(|s| !s.is_empty())
+            .ok_or(ContentError::MissingSubject)?;
+        Ok(Document {
+            subject,
+            preheader,
+            body: body.to_string(),
+        })
+    }
+
+    /// Renders the body into the layout and derives the plain-text part.
+    pub fn render(&self, templates: &EmailTemplates) -> Result<RenderedEmail, TemplateError> {
+        let content = to_html(&self.body);
+        let text = plain_text::from_html(&content);
+        let html = templates.render_layout(&LayoutContext {
+            subject: &self.subject,
+            preheader: self.preheader.as_deref(),
+            content: &content,
+        })?;
+        Ok(RenderedEmail {
+            subject: self.subject.clone(),
+            html,
+            text: Some(text),
+        })
+    }
+}
+
+/// Parses and renders a Markdown document with front matter.
+pub fn render(templates: &EmailTemplates, source: &str) -> Result<RenderedEmail, ContentError> {
+    Ok(Document::parse(source)?.render(templates)?)
+}
+
+/// Renders Markdown to sanitized HTML, without the layout.
+pub fn to_html(markdown: &str) -> String {
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, Parser::new_ext(markdown, options()));
+    ammonia::clean(&html)
+}
+
+/// Renders Markdown to plain text, see [`plain_text::from_html`].
+pub fn to_text(markdown: &str) -> String {
+    plain_text::from_html(&to_html(markdown))
+}
+
+fn options() -> Options {
+    Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some(inner) = value
+            .strip_prefix(quote)
+            .and_then(|v| v.strip_suffix(quote))
+        {
+            return inner;
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn front_matter_gives_subject_and_preheader() {
+        let doc = Document::parse("---\nsubject: \"Hello: world\"\npreheader: Read on\n---\nBody\n").unwrap();
+        assert_eq!(doc.subject, "Hello: world");
+        assert_eq!(doc.preheader.as_deref(), Some("Read on"));
+        assert_eq!(doc.body, "Body\n");
+
+        assert!(matches!(Document::parse("# No front matter"), Err(ContentError::MissingFrontMatter)));
+        assert!(matches!(Document::parse("---\nsubject: x\n"), Err(ContentError::MissingFrontMatter)));
+        assert!(matches!(Document::parse("---\npreheader: x\n---\n"), Err(ContentError::MissingSubject)));
+        assert!(matches!(Document::parse("---\nsubject: x\nauthor: y\n---\n"), Err(ContentError::UnknownField(f)) if f == "author"));
+        assert!(matches!(Document::parse("---\nsubject: x\nno colon\n---\n"), Err(ContentError::InvalidFrontMatter(3))));
+    }
+
+    #[test]
+    fn html_is_sanitized() {
+        let html = to_html("Hi <script>alert(1)</script>[x](javascript:alert(1)) <b onclick=\"x()\">bold</b>");
+        assert!(!html.contains("script"), "{html}");
+        assert!(!html.contains("javascript"), "{html}");
+        assert!(!html.contains("onclick"), "{html}");
+        assert!(html.contains("<b>bold</b>"), "{html}");
+    }
+
+    #[test]
+    fn text_keeps_structure_and_link_targets() {
+        let markdown = "# Title\n\nSee [the site](https://example.com) or <https://example.org>.\n\n\
+            - one\n- two\n  1. nested\n\n> quoted\n\n| a | b |\n|---|---|\n| 1 | 2 |\n";
+        assert_eq!(
+            to_text(markdown),
+            "Title\n=====\n\nSee the site [1] or https://example.org.\n\n\
+            - one\n- two\n  1. nested\n\n> quoted\n\na | b\n1 | 2\n\n[1] https://example.com\n"
+        );
+    }
+
+    #[test]
+    fn documents_render_into_the_layout() {
+        let templates = EmailTemplates::load("templates", &[]).unwrap();
+        let email = render(&templates, "---\nsubject: News & more\npreheader: Peek\n---\n**Hi** there\n").unwrap();
+        assert_eq!(email.subject, "News & more");
+        assert!(email.html.contains("<title>News &amp; more</title>"), "{}", email.html);
+        assert!(email.html.contains("Peek"));
+        assert!(email.html.contains("<p><strong>Hi</strong> there</p>"));
+        assert_eq!(email.text.as_deref(), Some("Hi there\n"));
+    }
+}
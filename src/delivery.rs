@@ -0,0 +1,98 @@
+//! Sending a composed newsletter to one recipient, shared by immediate and scheduled sends.
+
+use crate::AppState;
+use crate::merge_tags::{Newsletter, Recipient};
+use crate::signed_link::LinkAction;
+use crate::token;
+use crate::tracking;
+use sqlx::PgExecutor;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Metadata key naming the delivery of an email sent for a scheduled issue.
+pub const DELIVERY_METADATA: &str = "delivery_id";
+
+/// Who one newsletter email goes to.
+#[derive(Debug, Clone, Copy)]
+pub struct DeliveryTarget<'a> {
+    /// the `issue_deliveries` row, for scheduled issues
+    pub delivery_id: Option<Uuid>,
+    pub subscriber_uuid: Uuid,
+    pub recipient: &'a Recipient,
+    pub list_id: Option<Uuid>,
+    /// subject variant of an A/B test
+    pub variant: Option<usize>,
+}
+
+/// Renders `newsletter` for `target` with their own unsubscribe link and sends it. Emails of
+/// scheduled issues get tracked links and the open tracking pixel when they are enabled. `executor` stores the
+/// link's token in token mode.
+pub async fn send_to(
+    app_state: &AppState,
+    executor: impl PgExecutor<'_>,
+    newsletter: &Newsletter,
+    target: &DeliveryTarget<'_>,
+) -> anyhow::Result<()> {
+    let mut unsubscribe_url =
+        unsubscribe_url(app_state, executor, target.subscriber_uuid, target.list_id).await?;
+    let mut metadata = HashMap::new();
+    if let Some(delivery_id) = target.delivery_id {
+        // unsubscribes and the email API's reports are attributed to the delivery
+        unsubscribe_url.push_str(&format!("&delivery={delivery_id}"));
+        metadata.insert(DELIVERY_METADATA, delivery_id.to_string());
+    }
+    let templates = &app_state.templates;
+    let mut email = match target.variant {
+        Some(variant) => {
+            newsletter.render_variant(templates, target.recipient, &unsubscribe_url, variant)?
+        }
+        None => newsletter.render(templates, target.recipient, &unsubscribe_url)?,
+    };
+    if let Some(delivery_id) = target.delivery_id {
+        let links = &app_state.conf.links;
+        let base_url = app_state.conf.application.base_url.trim_end_matches('/');
+        if app_state.conf.tracking.clicks {
+            email.html = tracking::rewrite_links(&email.html, base_url, |url| {
+                let token = tracking::click_token(links, delivery_id, url)?;
+                Ok(format!("{base_url}/t/c/{token}"))
+            })?;
+        }
+        if app_state.conf.tracking.opens {
+            let token = tracking::open_token(links, delivery_id)?;
+            email.html = tracking::add_pixel(&email.html, &format!("{base_url}/t/o/{token}.gif"));
+        }
+    }
+    app_state
+        .email_client
+        .send_email_with_metadata(
+            &target.recipient.email,
+            &email.subject,
+            &email.html,
+            email.text.as_deref(),
+            &metadata,
+        )
+        .await
+}
+
+/// The recipient's link to unsubscribe from `list_id`, or from everything without one.
+pub async fn unsubscribe_url(
+    app_state: &AppState,
+    executor: impl PgExecutor<'_>,
+    subscriber_uuid: Uuid,
+    list_id: Option<Uuid>,
+) -> anyhow::Result<String> {
+    let query = token::link_query(
+        &app_state.conf.links,
+        executor,
+        LinkAction::Unsubscribe,
+        subscriber_uuid,
+        list_id,
+    )
+    .await?;
+    let base_url = app_state.conf.application.base_url.trim_end_matches('/');
+    let path = match list_id {
+        Some(list_id) => format!("/lists/{list_id}/subscription/unsubscribe"),
+        None => "/subscription/unsubscribe".to_string(),
+    };
+    Ok(format!("{base_url}{path}?{query}"))
+}
@@ -1,7 +1,9 @@
-use crate::errors::AppError;
+use crate::html_email::HtmlProcessor;
+use crate::plain_text;
 use crate::validation::ValidatedEmail;
 use reqwest::Client;
 use serde::Serialize;
+use std::collections::HashMap;
 use std::time::Duration;
 use anyhow::Context;
 
@@ -12,6 +14,7 @@ pub struct EmailClient {
     email_server_url: String,
     my_domain_email: ValidatedEmail, // 自身のドメインのメアド
     authorization_token: String,
+    html_processor: HtmlProcessor,
 }
 #[derive(Serialize, Debug)]
 struct SendEmailRequest<'a> {
@@ -20,22 +23,53 @@ struct SendEmailRequest<'a> {
     subject: &'a str,
     html_body: &'a str,
     text_body: &'a str,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    metadata: &'a HashMap<&'a str, String>,
 }
 impl EmailClient {
+    /// Sends an email. The HTML is prepared for mail clients first, see [`HtmlProcessor`];
+    /// without `text_content` the text part is derived from the result.
     pub async fn send_email(
         &self,
         recipient: &str, // todo : email checking..
         subject: &str,
         html_content: &str,
-        text_content: &str,
+        text_content: Option<&str>,
+    ) -> anyhow::Result<()> {
+        self.send_email_with_metadata(recipient, subject, html_content, text_content, &HashMap::new())
+            .await
+    }
+
+    /// Sends an email as [`send_email`](Self::send_email) does, with `metadata` the email API
+    /// hands back in its webhooks, see [`crate::handlers::webhooks`].
+    pub async fn send_email_with_metadata(
+        &self,
+        recipient: &str,
+        subject: &str,
+        html_content: &str,
+        text_content: Option<&str>,
+        metadata: &HashMap<&str, String>,
     ) -> anyhow::Result<()> {
         let url = format!("{}/email", self.email_server_url);
+        let html = self.html_processor.process(html_content);
+        for warning in &html.warnings {
+            tracing::warn!(%warning, subject, "email HTML may not render as intended");
+        }
+        let derived_text;
+        let text_body = match text_content {
+            Some(text) => text,
+            None => {
+                derived_text = plain_text::from_html(&html.html);
+                &derived_text
+            }
+        };
         let request_body = SendEmailRequest {
             from: self.as_str(),
             to: recipient,
             subject,
-            html_body: html_content,
-            text_body: text_content,
+            html_body: &html.html,
+            text_body,
+            metadata,
         };
         let builder = self
             .http_client
@@ -43,7 +77,7 @@ impl EmailClient {
             .header("X-Postmark-Server-Token", self.authorization_token.clone())
             .timeout(Duration::from_secs(10))
             .json(&request_body);
-        let resp = builder
+        builder
             .send()
             .await?
             .error_for_status()
@@ -56,13 +90,15 @@ impl EmailClient {
         sender: ValidatedEmail,
         authorization_token: &str,
         timeout: std::time::Duration,
+        html_processor: HtmlProcessor,
     ) -> Self {
         let http_client = Client::builder().timeout(timeout).build().unwrap();
         Self {
             http_client,
             email_server_url: base_url.to_string(),
             my_domain_email: sender,
-            authorization_token: authorization_token.to_string()
+            authorization_token: authorization_token.to_string(),
+            html_processor,
         }
     }
     
@@ -75,17 +111,31 @@ impl EmailClient {
     }
 }
 
+/// Whether sending may succeed if tried again later: the email API timed out, could not be
+/// reached, or answered with a 429 or a 5xx.
+pub fn is_transient(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .filter_map(|e| e.downcast_ref::<reqwest::Error>())
+        .any(|e| {
+            e.is_timeout()
+                || e.is_connect()
+                || e.status().is_some_and(|status| {
+                    status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                })
+        })
+}
+
 #[cfg(test)]
 mod tests {
     use crate::email_client::EmailClient;
+    use crate::html_email::HtmlProcessor;
     use crate::validation::ValidatedEmail;
     use fake::faker::internet::en::SafeEmail;
     use fake::faker::lorem::ja_jp::{Paragraph, Sentence};
     use fake::{Fake, Faker};
     use std::thread;
     use std::time::Duration;
-    use tracing::Instrument;
-    use tracing_subscriber::fmt::format::json;
 
     /// Generate a random email subject
     fn subject() -> String {
@@ -100,17 +150,6 @@ mod tests {
         ValidatedEmail::parse(&SafeEmail().fake::<String>()).unwrap().0
     }
 
-    /// Get a test instance of `EmailClient`.
-    fn email_client(base_url: &str) -> EmailClient {
-        let tmp_token: String = Faker.fake();
-        EmailClient::new(
-            base_url,
-            ValidatedEmail::parse(&SafeEmail().fake::<String>()).unwrap(),
-            &tmp_token,
-            Duration::from_secs(10),
-        )
-    }
-
     #[tokio::test]
     async fn send_email_with_expected_request() {
         let mut server = mockito::Server::new_async().await;
@@ -122,6 +161,7 @@ mod tests {
             sender,
             &tmp_token,
             Duration::from_secs(10),
+            HtmlProcessor::default(),
         );
 
         let mock = server
@@ -136,7 +176,7 @@ mod tests {
         // let content: String = Paragraph(1..10).fake();
 
         email_client
-            .send_email(&email(), &subject(), &content(), &content())
+            .send_email(&email(), &subject(), &content(), Some(&content()))
             .await
             .expect("error sending email");
         mock.assert_async().await;
@@ -153,6 +193,7 @@ mod tests {
             sender,
             &tmp_token,
             std::time::Duration::from_secs(10),
+            HtmlProcessor::default(),
         );
 
         let mock = server
@@ -168,7 +209,7 @@ mod tests {
         // let content: String = Paragraph(1..10).fake();
 
         let res = email_client
-            .send_email(&email(), &subject(), &content(), &content())
+            .send_email(&email(), &subject(), &content(), Some(&content()))
             .await;
         assert!(res.is_ok());
         mock.assert_async().await;
@@ -185,6 +226,7 @@ mod tests {
             sender,
             &tmp_token,
             std::time::Duration::from_secs(10),
+            HtmlProcessor::default(),
         );
 
         let mock = server
@@ -196,7 +238,7 @@ mod tests {
             .create();
 
         let res = email_client
-            .send_email(&email(), &subject(), &content(), &content())
+            .send_email(&email(), &subject(), &content(), Some(&content()))
             .await;
         println!("res : {res:?}");
         assert!(res.is_err());
@@ -214,6 +256,7 @@ mod tests {
             sender,
             &tmp_token,
             std::time::Duration::from_secs(10),
+            HtmlProcessor::default(),
         );
 
         let mock = server
@@ -229,7 +272,7 @@ mod tests {
             .create();
 
         let res = email_client
-            .send_email(&email(), &subject(), &content(), &content())
+            .send_email(&email(), &subject(), &content(), Some(&content()))
             .await;
         assert!(res.is_err());
         mock.assert_async().await;
@@ -28,6 +28,9 @@ pub enum AppError {
     
     #[error("error user not found: {0}")]
     UserNotFound(String),
+
+    #[error("{0}")]
+    UsageError(String),
 }
 
 impl IntoResponse for AppError {
@@ -59,6 +62,7 @@ impl IntoResponse for AppError {
             AppError::EnvError(_) => self.to_string().into_response(),
             AppError::SendingRequest(_) => self.to_string().into_response(),
             AppError::UserNotFound(_) => self.to_string().into_response(),
+            AppError::UsageError(_) => self.to_string().into_response(),
         }
     }
 }
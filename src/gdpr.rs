@@ -0,0 +1,256 @@
+//! Data subject access and erasure.
+//!
+//! Both operations work on an email address, matched case-insensitively, and cover every
+//! table holding data about the subscriber. Erasure deletes the rows outright and leaves a
+//! tombstone with only a SHA-256 of the normalized address, which [`crate::import`] checks so
+//! an old export cannot bring the subscriber back. Subscribing again through the form is
+//! still possible since that is a fresh opt-in.
+
+use crate::consent::{self, ConsentEvent};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+/// Everything held about one email address.
+#[derive(Serialize, Debug)]
+pub struct DataBundle {
+    pub email: String,
+    pub generated_at: DateTime<Utc>,
+    /// set when the address was erased before; only its hash is still stored
+    pub erased_at: Option<DateTime<Utc>>,
+    pub subscriptions: Vec<SubscriberData>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SubscriberData {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub digest_frequency: String,
+    pub attributes: serde_json::Value,
+    pub locale: Option<String>,
+    pub lists: Vec<ListMembershipData>,
+    pub tags: Vec<TagData>,
+    pub tokens: Vec<TokenData>,
+    pub consent_events: Vec<ConsentEvent>,
+    pub deliveries: Vec<DeliveryData>,
+    pub engagement_events: Vec<EngagementData>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ListMembershipData {
+    pub list_id: Uuid,
+    pub list_name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TagData {
+    pub tag: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TokenData {
+    /// only the hash is stored, see [`crate::token`]
+    pub token_hash: String,
+    pub list_id: Option<Uuid>,
+    /// what the link was mailed for, see [`crate::signed_link::LinkAction`]
+    pub action: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// A newsletter issue sent, or queued to be sent, to the subscriber.
+#[derive(Serialize, Debug)]
+pub struct DeliveryData {
+    pub issue_id: Uuid,
+    pub status: String,
+    pub queued_at: DateTime<Utc>,
+    pub sent_at: Option<DateTime<Utc>>,
+}
+
+/// An open or click of an issue, see [`crate::tracking`].
+#[derive(Serialize, Debug)]
+pub struct EngagementData {
+    pub issue_id: Uuid,
+    pub kind: String,
+    pub user_agent: Option<String>,
+    pub machine_reason: Option<String>,
+    /// the clicked link
+    pub url: Option<String>,
+    pub occurred_at: DateTime<Utc>,
+}
+
+/// Hash stored in the tombstone of an erased address.
+pub fn email_hash(email: &str) -> String {
+    hex::encode(Sha256::digest(email.trim().to_lowercase().as_bytes()))
+}
+
+/// Collects everything held about `email`. The bundle is empty when nothing is stored.
+pub async fn export(pool: &PgPool, email: &str) -> anyhow::Result<DataBundle> {
+    let subscribers = sqlx::query!(
+        r#"SELECT id, email, name, status, subscribed_at, digest_frequency, attributes, locale
+        FROM subscriptions WHERE lower(email) = lower($1)
+        ORDER BY subscribed_at"#,
+        email.trim()
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut subscriptions = Vec::with_capacity(subscribers.len());
+    for s in subscribers {
+        let lists = sqlx::query_as!(
+            ListMembershipData,
+            r#"SELECT m.list_id, l.name AS list_name, m.status, m.subscribed_at
+            FROM list_memberships m JOIN lists l ON l.id = m.list_id
+            WHERE m.subscriber_uuid = $1
+            ORDER BY m.subscribed_at"#,
+            s.id
+        )
+        .fetch_all(pool)
+        .await?;
+        let tags = sqlx::query_as!(
+            TagData,
+            "SELECT tag, created_at FROM subscriber_tags WHERE subscriber_uuid = $1 ORDER BY tag",
+            s.id
+        )
+        .fetch_all(pool)
+        .await?;
+        let tokens = sqlx::query_as!(
+            TokenData,
+            r#"SELECT subscription_token AS token_hash, list_id, action, expires_at
+            FROM subscription_tokens WHERE subscriber_uuid = $1"#,
+            s.id
+        )
+        .fetch_all(pool)
+        .await?;
+        let consent_events = consent::events(pool, s.id).await?;
+        let deliveries = sqlx::query_as!(
+            DeliveryData,
+            r#"SELECT issue_id, status, queued_at, sent_at FROM issue_deliveries
+            WHERE subscriber_uuid = $1 ORDER BY queued_at"#,
+            s.id
+        )
+        .fetch_all(pool)
+        .await?;
+        let engagement_events = sqlx::query_as!(
+            EngagementData,
+            r#"SELECT d.issue_id, e.kind, e.user_agent, e.machine_reason, e.url, e.occurred_at
+            FROM engagement_events e JOIN issue_deliveries d ON d.id = e.delivery_id
+            WHERE d.subscriber_uuid = $1 ORDER BY e.occurred_at"#,
+            s.id
+        )
+        .fetch_all(pool)
+        .await?;
+        subscriptions.push(SubscriberData {
+            id: s.id,
+            email: s.email,
+            name: s.name,
+            status: s.status,
+            subscribed_at: s.subscribed_at,
+            digest_frequency: s.digest_frequency,
+            attributes: s.attributes,
+            locale: s.locale,
+            lists,
+            tags,
+            tokens,
+            consent_events,
+            deliveries,
+            engagement_events,
+        });
+    }
+
+    let erased_at = erased_at(pool, email).await?;
+    Ok(DataBundle {
+        email: email.trim().to_string(),
+        generated_at: Utc::now(),
+        erased_at,
+        subscriptions,
+    })
+}
+
+/// Deletes everything held about `email` and records a tombstone, in one transaction.
+/// Returns the number of subscribers deleted; the tombstone is written even when it is zero.
+pub async fn erase(pool: &PgPool, email: &str, erased_by: &str) -> anyhow::Result<u64> {
+    let mut transaction = pool.begin().await?;
+    let ids: Vec<Uuid> = sqlx::query!(
+        "SELECT id FROM subscriptions WHERE lower(email) = lower($1) FOR UPDATE",
+        email.trim()
+    )
+    .fetch_all(&mut *transaction)
+    .await?
+    .into_iter()
+    .map(|r| r.id)
+    .collect();
+
+    // tokens do not cascade; memberships, tags and consent events do. deliveries and their
+    // engagement events are kept for the issue's counts, with the subscriber set to NULL and
+    // without user agents
+    sqlx::query!("DELETE FROM subscription_tokens WHERE subscriber_uuid = ANY($1)", &ids)
+        .execute(&mut *transaction)
+        .await?;
+    sqlx::query!(
+        r#"UPDATE engagement_events SET user_agent = NULL
+        WHERE delivery_id IN (SELECT id FROM issue_deliveries WHERE subscriber_uuid = ANY($1))"#,
+        &ids
+    )
+    .execute(&mut *transaction)
+    .await?;
+    let deleted = sqlx::query!("DELETE FROM subscriptions WHERE id = ANY($1)", &ids)
+        .execute(&mut *transaction)
+        .await?
+        .rows_affected();
+    sqlx::query!(
+        r#"INSERT INTO erased_subscribers (email_hash, erased_by, erased_at) VALUES ($1, $2, $3)
+        ON CONFLICT (email_hash) DO NOTHING"#,
+        email_hash(email),
+        erased_by,
+        Utc::now(),
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(deleted)
+}
+
+/// When `email` was erased, if it ever was.
+pub async fn erased_at(
+    executor: impl PgExecutor<'_>,
+    email: &str,
+) -> anyhow::Result<Option<DateTime<Utc>>> {
+    let res = sqlx::query!(
+        "SELECT erased_at FROM erased_subscribers WHERE email_hash = $1",
+        email_hash(email)
+    )
+    .fetch_optional(executor)
+    .await?;
+    Ok(res.map(|r| r.erased_at))
+}
+
+/// The subset of `emails` that were erased.
+pub async fn erased_emails<'a>(
+    executor: impl PgExecutor<'_>,
+    emails: impl Iterator<Item = &'a str>,
+) -> anyhow::Result<Vec<&'a str>> {
+    let (emails, hashes): (Vec<&str>, Vec<String>) = emails.map(|e| (e, email_hash(e))).unzip();
+    let erased = sqlx::query!(
+        "SELECT email_hash FROM erased_subscribers WHERE email_hash = ANY($1)",
+        &hashes
+    )
+    .fetch_all(executor)
+    .await?
+    .into_iter()
+    .map(|r| r.email_hash)
+    .collect::<std::collections::HashSet<_>>();
+    Ok(emails
+        .into_iter()
+        .zip(hashes)
+        .filter(|(_, hash)| erased.contains(hash))
+        .map(|(email, _)| email)
+        .collect())
+}
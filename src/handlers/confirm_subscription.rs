@@ -1,10 +1,13 @@
 use crate::AppState;
-use crate::errors::AppError;
-use axum::extract::{Query, State};
+use crate::consent::{self, ClientInfo, ConsentDetails, ConsentEventKind};
+use crate::signed_link::{self, LinkAction, LinkError, SignedParameters};
+use crate::stats;
+use crate::token;
+use axum::extract::{Path, Query, State};
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use serde::Deserialize;
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, Transaction};
 use tracing::instrument;
 use uuid::Uuid;
 use anyhow::Context;
@@ -12,7 +15,16 @@ use anyhow::Context;
 #[derive(thiserror::Error, Debug)]
 pub enum ConfirmationError {
     #[error("{0}")]
-    ConfirmationError(#[from] anyhow::Error)
+    ConfirmationError(#[from] anyhow::Error),
+
+    #[error("the token does not belong to list {0}")]
+    ListMismatch(Uuid),
+
+    #[error("missing token")]
+    MissingToken,
+
+    #[error("invalid link: {0}")]
+    InvalidLink(#[from] LinkError),
 }
 
 impl IntoResponse for ConfirmationError {
@@ -21,46 +33,211 @@ impl IntoResponse for ConfirmationError {
             ConfirmationError::ConfirmationError(error) => {
                 (StatusCode::BAD_REQUEST, error.to_string()).into_response()
             },
+            ConfirmationError::ListMismatch(_)
+            | ConfirmationError::MissingToken
+            | ConfirmationError::InvalidLink(_) => {
+                (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+            },
         }
     }
 }
 
+/// A link carries either a stored `token` or the parameters of a signed link.
+#[derive(Deserialize, Debug)]
+pub struct LinkParameters {
+    token: Option<String>, // dbのuuidに相当
+    #[serde(flatten)]
+    signed: Option<SignedParameters>,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct Parameters {
-    token: String, // dbのuuidに相当
+    #[serde(flatten)]
+    link: LinkParameters,
+    /// the issue delivery an unsubscribe link was mailed in, see [`crate::stats`]
+    delivery: Option<Uuid>,
 }
 #[instrument(name = "confirm a pending subscriber")]
 pub async fn confirm(
     State(app_state): State<AppState>,
+    client: ClientInfo,
     Query(param): Query<Parameters>,
 ) -> Result<StatusCode, ConfirmationError> {
-    let subscriber_uuid = get_subscriber_uuid_from_token(&app_state.pg_pool, param.token)
-        .await?;
-    confirm_subscriber(&app_state.pg_pool, subscriber_uuid).await?;
+    let (subscriber_uuid, list_id) =
+        resolve_link(&app_state, param.link, LinkAction::Confirm).await?;
+    let mut transaction = app_state
+        .pg_pool
+        .begin()
+        .await
+        .context("error starting transaction")?;
+    confirm_subscriber(&mut transaction, subscriber_uuid, list_id, &client).await?;
+    transaction
+        .commit()
+        .await
+        .context("error commiting transaction")?;
+    Ok(StatusCode::OK)
+}
+
+#[instrument(name = "confirm a pending list member")]
+pub async fn confirm_list_member(
+    State(app_state): State<AppState>,
+    client: ClientInfo,
+    Path(list_id): Path<Uuid>,
+    Query(param): Query<Parameters>,
+) -> Result<StatusCode, ConfirmationError> {
+    let (subscriber_uuid, token_list_id) =
+        resolve_link(&app_state, param.link, LinkAction::Confirm).await?;
+    if token_list_id != Some(list_id) {
+        return Err(ConfirmationError::ListMismatch(list_id));
+    }
+    let mut transaction = app_state
+        .pg_pool
+        .begin()
+        .await
+        .context("error starting transaction")?;
+    confirm_subscriber(&mut transaction, subscriber_uuid, token_list_id, &client).await?;
+    transaction
+        .commit()
+        .await
+        .context("error commiting transaction")?;
+    Ok(StatusCode::OK)
+}
+
+#[instrument(name = "unsubscribe a list member")]
+pub async fn unsubscribe_list_member(
+    State(app_state): State<AppState>,
+    Path(list_id): Path<Uuid>,
+    Query(param): Query<Parameters>,
+) -> Result<StatusCode, ConfirmationError> {
+    let delivery = param.delivery;
+    let (subscriber_uuid, token_list_id) =
+        resolve_link(&app_state, param.link, LinkAction::Unsubscribe).await?;
+    if token_list_id != Some(list_id) {
+        return Err(ConfirmationError::ListMismatch(list_id));
+    }
+    sqlx::query!(
+        r#"UPDATE list_memberships SET status = 'unsubscribed' WHERE list_id = $1 AND subscriber_uuid = $2"#,
+        list_id,
+        subscriber_uuid,
+    )
+    .execute(app_state.pg_pool.as_ref())
+    .await
+    .context("error unsubscribing list member")?;
+    if let Some(delivery_id) = delivery {
+        stats::record_unsubscribe(app_state.pg_pool.as_ref(), delivery_id, subscriber_uuid).await?;
+    }
     Ok(StatusCode::OK)
 }
 
-async fn confirm_subscriber(pool: &PgPool, subscriber_uuid: Uuid) -> anyhow::Result<()> {
+/// Unsubscribes from everything, as linked from newsletters sent to all subscribers.
+#[instrument(name = "unsubscribe a subscriber")]
+pub async fn unsubscribe(
+    State(app_state): State<AppState>,
+    Query(param): Query<Parameters>,
+) -> Result<StatusCode, ConfirmationError> {
+    let delivery = param.delivery;
+    let (subscriber_uuid, _) =
+        resolve_link(&app_state, param.link, LinkAction::Unsubscribe).await?;
+    let mut transaction = app_state
+        .pg_pool
+        .begin()
+        .await
+        .context("error starting transaction")?;
     sqlx::query!(
+        "UPDATE subscriptions SET status = 'unsubscribed' WHERE id = $1",
+        subscriber_uuid
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("error unsubscribing subscriber")?;
+    sqlx::query!(
+        "UPDATE list_memberships SET status = 'unsubscribed' WHERE subscriber_uuid = $1",
+        subscriber_uuid
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("error unsubscribing list memberships")?;
+    if let Some(delivery_id) = delivery {
+        stats::record_unsubscribe(&mut *transaction, delivery_id, subscriber_uuid).await?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("error commiting transaction")?;
+    Ok(StatusCode::OK)
+}
+
+/// Finds the subscriber and list a link for `action` was issued for. Signed links are
+/// accepted in either link mode, so switching modes does not break links already mailed.
+pub(crate) async fn resolve_link(
+    app_state: &AppState,
+    link: LinkParameters,
+    action: LinkAction,
+) -> Result<(Uuid, Option<Uuid>), ConfirmationError> {
+    match (link.token, link.signed) {
+        (Some(token), _) => {
+            Ok(get_subscriber_uuid_from_token(&app_state.pg_pool, token, action).await?)
+        }
+        (None, Some(signed)) => Ok(signed_link::verify(&app_state.conf.links, action, &signed)?),
+        (None, None) => Err(ConfirmationError::MissingToken),
+    }
+}
+
+/// Confirms the subscriber and their membership of `list_id`, and records the consent of
+/// `client`.
+pub(crate) async fn confirm_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_uuid: Uuid,
+    list_id: Option<Uuid>,
+    client: &ClientInfo,
+) -> anyhow::Result<()> {
+    let res = sqlx::query!(
         r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
         subscriber_uuid,
     )
-    .execute(pool)
+    .execute(&mut **transaction)
     .await?;
+    // a signed link can outlive its subscriber
+    if res.rows_affected() == 0 {
+        anyhow::bail!("subscriber not found");
+    }
 
+    if let Some(list_id) = list_id {
+        sqlx::query!(
+            r#"UPDATE list_memberships SET status = 'confirmed' WHERE list_id = $1 AND subscriber_uuid = $2"#,
+            list_id,
+            subscriber_uuid,
+        )
+        .execute(&mut **transaction)
+        .await?;
+    }
+    consent::record(
+        &mut **transaction,
+        subscriber_uuid,
+        list_id,
+        ConsentEventKind::Confirmed,
+        client,
+        &ConsentDetails::default(),
+    )
+    .await?;
     Ok(())
 }
 
+/// Returns the subscriber the token was issued to and the list it was issued for. Tokens
+/// issued for another action and expired ones are not found.
 async fn get_subscriber_uuid_from_token(
     pool: &PgPool,
     token: String,
-) -> anyhow::Result<Uuid> {
+    action: LinkAction,
+) -> anyhow::Result<(Uuid, Option<Uuid>)> {
     let res = sqlx::query!(
-        r"SELECT subscriber_uuid FROM subscription_tokens WHERE subscription_token = $1",
-        token
+        r#"SELECT subscriber_uuid, list_id FROM subscription_tokens
+        WHERE subscription_token = $1 AND action = $2 AND expires_at > now()"#,
+        token::hash(&token),
+        action.as_str(),
     )
     .fetch_one(pool)
     .await
     .context("cannot find subscriber from the token")?;
-    Ok(res.subscriber_uuid)
+    Ok((res.subscriber_uuid, res.list_id))
 }
@@ -0,0 +1,449 @@
+use crate::AppState;
+use crate::authentication::AdminUser;
+use crate::plain_text;
+use crate::templates::{self, RenderedEmail, TemplateError, TemplateSource};
+use anyhow::Context;
+use axum::Json;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use garde::Validate;
+use serde::{Deserialize, Serialize};
+use sqlx::PgExecutor;
+use sqlx::types::chrono::{DateTime, Utc};
+use tracing::instrument;
+use uuid::Uuid;
+
+/// Body of `POST /admin/templates`. The source becomes version 1 and is made active.
+#[derive(Deserialize, Validate, Debug)]
+pub struct NewTemplate {
+    /// the email kind the template replaces, e.g. `confirmation`
+    #[garde(length(min = 1, max = 100))]
+    name: String,
+    /// one of `application.locales`; a template without one is used for every locale
+    /// that has no template of its own
+    #[garde(skip)]
+    locale: Option<String>,
+    #[garde(skip)]
+    #[serde(flatten)]
+    source: TemplateSource,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NewVersion {
+    #[serde(flatten)]
+    source: TemplateSource,
+    /// whether the new version becomes active, `true` when omitted
+    activate: Option<bool>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ActiveVersion {
+    version: i32,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PreviewRequest {
+    /// the active version when omitted
+    version: Option<i32>,
+    /// render with this subscriber's details instead of example values
+    subscriber_id: Option<Uuid>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct EmailTemplate {
+    id: Uuid,
+    name: String,
+    locale: Option<String>,
+    active_version: Option<i32>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TemplateVersion {
+    version: i32,
+    subject: String,
+    html: String,
+    text: Option<String>,
+    created_by: String,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TemplateDetail {
+    #[serde(flatten)]
+    template: EmailTemplate,
+    /// newest first
+    versions: Vec<TemplateVersion>,
+}
+
+#[instrument(name = "creating an email template", skip(app_state))]
+pub async fn create_template(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+    Json(new_template): Json<NewTemplate>,
+) -> Result<(StatusCode, Json<TemplateDetail>), EmailTemplateError> {
+    new_template.validate()?;
+    check_source(&app_state, &new_template.name, &new_template.source)?;
+    let locale = new_template
+        .locale
+        .as_deref()
+        .map(|locale| {
+            app_state
+                .conf
+                .application
+                .supported_locale(locale)
+                .ok_or_else(|| EmailTemplateError::UnsupportedLocale(locale.to_string()))
+        })
+        .transpose()?;
+
+    let mut transaction = app_state
+        .pg_pool
+        .begin()
+        .await
+        .context("error starting transaction")?;
+    let now = Utc::now();
+    let id = sqlx::query!(
+        r#"INSERT INTO email_templates (id, name, locale, active_version, created_at, updated_at)
+        VALUES ($1, $2, $3, 1, $4, $4)
+        ON CONFLICT ON CONSTRAINT email_templates_name_locale_key DO NOTHING
+        RETURNING id"#,
+        Uuid::new_v4(),
+        new_template.name,
+        locale,
+        now,
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("error inserting email template")?
+    .ok_or_else(|| EmailTemplateError::Conflict(new_template.name.clone()))?
+    .id;
+    insert_version(&mut transaction, id, 1, &new_template.source, &admin.name).await?;
+    transaction
+        .commit()
+        .await
+        .context("error commiting transaction")?;
+
+    let detail = fetch_template(&app_state, id).await?;
+    Ok((StatusCode::CREATED, Json(detail)))
+}
+
+#[instrument(name = "listing email templates", skip(app_state))]
+pub async fn get_templates(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+) -> Result<Json<Vec<EmailTemplate>>, EmailTemplateError> {
+    let templates = sqlx::query_as!(
+        EmailTemplate,
+        r#"SELECT id, name, locale, active_version, created_at, updated_at
+        FROM email_templates ORDER BY name, locale NULLS FIRST"#
+    )
+    .fetch_all(app_state.pg_pool.as_ref())
+    .await
+    .context("error fetching email templates")?;
+    Ok(Json(templates))
+}
+
+#[instrument(name = "fetching an email template", skip(app_state))]
+pub async fn get_template(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+    Path(template_id): Path<Uuid>,
+) -> Result<Json<TemplateDetail>, EmailTemplateError> {
+    fetch_template(&app_state, template_id).await.map(Json)
+}
+
+/// Deleting a template puts the file templates of its kind back in use.
+#[instrument(name = "deleting an email template", skip(app_state))]
+pub async fn delete_template(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+    Path(template_id): Path<Uuid>,
+) -> Result<StatusCode, EmailTemplateError> {
+    let res = sqlx::query!("DELETE FROM email_templates WHERE id = $1", template_id)
+        .execute(app_state.pg_pool.as_ref())
+        .await
+        .context("error deleting email template")?;
+    if res.rows_affected() == 0 {
+        return Err(EmailTemplateError::NotFound(template_id));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[instrument(name = "adding an email template version", skip(app_state))]
+pub async fn create_version(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+    Path(template_id): Path<Uuid>,
+    Json(new_version): Json<NewVersion>,
+) -> Result<(StatusCode, Json<TemplateDetail>), EmailTemplateError> {
+    let mut transaction = app_state
+        .pg_pool
+        .begin()
+        .await
+        .context("error starting transaction")?;
+    // locking the template serializes version numbers
+    let name = sqlx::query!(
+        "SELECT name FROM email_templates WHERE id = $1 FOR UPDATE",
+        template_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("error fetching email template")?
+    .ok_or(EmailTemplateError::NotFound(template_id))?
+    .name;
+    check_source(&app_state, &name, &new_version.source)?;
+
+    let version = sqlx::query_scalar!(
+        r#"SELECT COALESCE(MAX(version), 0) + 1 AS "version!" FROM email_template_versions WHERE template_id = $1"#,
+        template_id
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .context("error numbering email template version")?;
+    insert_version(&mut transaction, template_id, version, &new_version.source, &admin.name).await?;
+    if new_version.activate.unwrap_or(true) {
+        activate(&mut transaction, template_id, version).await?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("error commiting transaction")?;
+
+    let detail = fetch_template(&app_state, template_id).await?;
+    Ok((StatusCode::CREATED, Json(detail)))
+}
+
+/// Makes an existing version active, e.g. to roll back.
+#[instrument(name = "activating an email template version", skip(app_state))]
+pub async fn set_active_version(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+    Path(template_id): Path<Uuid>,
+    Json(active): Json<ActiveVersion>,
+) -> Result<Json<TemplateDetail>, EmailTemplateError> {
+    let mut transaction = app_state
+        .pg_pool
+        .begin()
+        .await
+        .context("error starting transaction")?;
+    fetch_source(&mut *transaction, template_id, active.version).await?;
+    activate(&mut transaction, template_id, active.version).await?;
+    transaction
+        .commit()
+        .await
+        .context("error commiting transaction")?;
+
+    let detail = fetch_template(&app_state, template_id).await?;
+    Ok(Json(detail))
+}
+
+#[instrument(name = "previewing an email template", skip(app_state))]
+pub async fn preview_template(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+    Path(template_id): Path<Uuid>,
+    Json(request): Json<PreviewRequest>,
+) -> Result<Json<RenderedEmail>, EmailTemplateError> {
+    let template = sqlx::query!(
+        "SELECT name, active_version FROM email_templates WHERE id = $1",
+        template_id
+    )
+    .fetch_optional(app_state.pg_pool.as_ref())
+    .await
+    .context("error fetching email template")?
+    .ok_or(EmailTemplateError::NotFound(template_id))?;
+    let version = request
+        .version
+        .or(template.active_version)
+        .ok_or(EmailTemplateError::NoActiveVersion(template_id))?;
+
+    let source = fetch_source(app_state.pg_pool.as_ref(), template_id, version).await?;
+
+    let mut context = templates::example_context(&template.name)
+        .ok_or_else(|| EmailTemplateError::UnknownKind(template.name.clone()))?;
+    if let Some(subscriber_id) = request.subscriber_id {
+        let subscriber = sqlx::query!(
+            "SELECT email, name FROM subscriptions WHERE id = $1",
+            subscriber_id
+        )
+        .fetch_optional(app_state.pg_pool.as_ref())
+        .await
+        .context("error fetching subscriber")?
+        .ok_or(EmailTemplateError::SubscriberNotFound(subscriber_id))?;
+        // only fields the kind has are replaced, links keep their example values
+        if let Some(fields) = context.as_object_mut() {
+            for (key, value) in [("email", subscriber.email), ("name", subscriber.name)] {
+                if let Some(field) = fields.get_mut(key) {
+                    *field = value.into();
+                }
+            }
+        }
+    }
+    let mut email = app_state
+        .templates
+        .render_source(&template.name, &source, &context)?;
+    // show the text part that would be sent
+    email
+        .text
+        .get_or_insert_with(|| plain_text::from_html(&email.html));
+    Ok(Json(email))
+}
+
+/// Renders `source` with the example context of the kind `name`.
+fn check_source(
+    app_state: &AppState,
+    name: &str,
+    source: &TemplateSource,
+) -> Result<(), EmailTemplateError> {
+    let context = templates::example_context(name)
+        .ok_or_else(|| EmailTemplateError::UnknownKind(name.to_string()))?;
+    app_state.templates.render_source(name, source, &context)?;
+    Ok(())
+}
+
+async fn insert_version(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    template_id: Uuid,
+    version: i32,
+    source: &TemplateSource,
+    created_by: &str,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"INSERT INTO email_template_versions (template_id, version, subject, html, text, created_by, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
+        template_id,
+        version,
+        source.subject,
+        source.html,
+        source.text,
+        created_by,
+        Utc::now(),
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("error inserting email template version")?;
+    Ok(())
+}
+
+async fn activate(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    template_id: Uuid,
+    version: i32,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        "UPDATE email_templates SET active_version = $1, updated_at = $2 WHERE id = $3",
+        version,
+        Utc::now(),
+        template_id,
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("error activating email template version")?;
+    Ok(())
+}
+
+async fn fetch_source(
+    executor: impl PgExecutor<'_>,
+    template_id: Uuid,
+    version: i32,
+) -> Result<TemplateSource, EmailTemplateError> {
+    let source = sqlx::query_as!(
+        TemplateSource,
+        "SELECT subject, html, text FROM email_template_versions WHERE template_id = $1 AND version = $2",
+        template_id,
+        version,
+    )
+    .fetch_optional(executor)
+    .await
+    .context("error fetching email template version")?
+    .ok_or(EmailTemplateError::VersionNotFound(template_id, version))?;
+    Ok(source)
+}
+
+async fn fetch_template(
+    app_state: &AppState,
+    template_id: Uuid,
+) -> Result<TemplateDetail, EmailTemplateError> {
+    let template = sqlx::query_as!(
+        EmailTemplate,
+        "SELECT id, name, locale, active_version, created_at, updated_at FROM email_templates WHERE id = $1",
+        template_id
+    )
+    .fetch_optional(app_state.pg_pool.as_ref())
+    .await
+    .context("error fetching email template")?
+    .ok_or(EmailTemplateError::NotFound(template_id))?;
+    let versions = sqlx::query_as!(
+        TemplateVersion,
+        r#"SELECT version, subject, html, text, created_by, created_at
+        FROM email_template_versions WHERE template_id = $1
+        ORDER BY version DESC"#,
+        template_id
+    )
+    .fetch_all(app_state.pg_pool.as_ref())
+    .await
+    .context("error fetching email template versions")?;
+    Ok(TemplateDetail { template, versions })
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum EmailTemplateError {
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+
+    #[error("{0}")]
+    ValidationError(#[from] garde::Report),
+
+    #[error(transparent)]
+    InvalidTemplate(#[from] TemplateError),
+
+    #[error("unknown email kind: {0}")]
+    UnknownKind(String),
+
+    #[error("unsupported locale: {0}")]
+    UnsupportedLocale(String),
+
+    #[error("email template already exists: {0}")]
+    Conflict(String),
+
+    #[error("email template not found: {0}")]
+    NotFound(Uuid),
+
+    #[error("email template {0} has no version {1}")]
+    VersionNotFound(Uuid, i32),
+
+    #[error("email template {0} has no active version")]
+    NoActiveVersion(Uuid),
+
+    #[error("subscriber not found: {0}")]
+    SubscriberNotFound(Uuid),
+}
+
+impl IntoResponse for EmailTemplateError {
+    fn into_response(self) -> Response {
+        match self {
+            EmailTemplateError::UnexpectedError(e) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+            }
+            EmailTemplateError::ValidationError(e) => {
+                (StatusCode::BAD_REQUEST, e.to_string()).into_response()
+            }
+            EmailTemplateError::InvalidTemplate(_)
+            | EmailTemplateError::UnknownKind(_)
+            | EmailTemplateError::UnsupportedLocale(_) => {
+                (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+            }
+            EmailTemplateError::Conflict(_) => {
+                (StatusCode::CONFLICT, self.to_string()).into_response()
+            }
+            EmailTemplateError::NotFound(_)
+            | EmailTemplateError::VersionNotFound(..)
+            | EmailTemplateError::NoActiveVersion(_)
+            | EmailTemplateError::SubscriberNotFound(_) => {
+                (StatusCode::NOT_FOUND, self.to_string()).into_response()
+            }
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use crate::AppState;
+use crate::authentication::AdminUser;
+use crate::gdpr::{self, DataBundle};
+use crate::handlers::confirm_subscription::{LinkParameters, resolve_link};
+use crate::signed_link::LinkAction;
+use anyhow::Context;
+use axum::Json;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+/// Recorded as `erased_by` when subscribers erase themselves.
+const SUBSCRIBER_REQUEST: &str = "subscriber";
+
+#[derive(Deserialize)]
+pub struct DataSubjectRequest {
+    email: String,
+}
+
+impl std::fmt::Debug for DataSubjectRequest {
+    // keeps the address out of the logs of an erasure
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DataSubjectRequest").finish_non_exhaustive()
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct ErasureResult {
+    erased: u64,
+}
+
+#[instrument(name = "exporting subscriber data", skip(app_state))]
+pub async fn admin_export(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+    Json(request): Json<DataSubjectRequest>,
+) -> Result<Json<DataBundle>, GdprError> {
+    let bundle = gdpr::export(&app_state.pg_pool, &request.email)
+        .await
+        .context("error exporting subscriber data")?;
+    Ok(Json(bundle))
+}
+
+#[instrument(name = "erasing subscriber data", skip(app_state))]
+pub async fn admin_erase(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+    Json(request): Json<DataSubjectRequest>,
+) -> Result<Json<ErasureResult>, GdprError> {
+    let erased = gdpr::erase(&app_state.pg_pool, &request.email, &admin.name)
+        .await
+        .context("error erasing subscriber data")?;
+    Ok(Json(ErasureResult { erased }))
+}
+
+#[instrument(name = "exporting own subscriber data", skip(app_state))]
+pub async fn subscriber_export(
+    State(app_state): State<AppState>,
+    Query(param): Query<LinkParameters>,
+) -> Result<Json<DataBundle>, GdprError> {
+    let email = email_from_link(&app_state, param).await?;
+    let bundle = gdpr::export(&app_state.pg_pool, &email)
+        .await
+        .context("error exporting subscriber data")?;
+    Ok(Json(bundle))
+}
+
+#[instrument(name = "erasing own subscriber data", skip(app_state))]
+pub async fn subscriber_erase(
+    State(app_state): State<AppState>,
+    Query(param): Query<LinkParameters>,
+) -> Result<StatusCode, GdprError> {
+    let email = email_from_link(&app_state, param).await?;
+    gdpr::erase(&app_state.pg_pool, &email, SUBSCRIBER_REQUEST)
+        .await
+        .context("error erasing subscriber data")?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn email_from_link(app_state: &AppState, link: LinkParameters) -> Result<String, GdprError> {
+    let (subscriber_uuid, _) = resolve_link(app_state, link, LinkAction::Data)
+        .await
+        .map_err(|e| GdprError::InvalidToken(e.into()))?;
+    // a signed link outlives the erasure of its subscriber
+    let res = sqlx::query!("SELECT email FROM subscriptions WHERE id = $1", subscriber_uuid)
+        .fetch_optional(app_state.pg_pool.as_ref())
+        .await
+        .context("error fetching subscriber")?
+        .ok_or_else(|| GdprError::InvalidToken(anyhow::anyhow!("subscriber not found")))?;
+    Ok(res.email)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum GdprError {
+    #[error("invalid token: {0}")]
+    InvalidToken(anyhow::Error),
+
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl IntoResponse for GdprError {
+    fn into_response(self) -> Response {
+        match self {
+            GdprError::InvalidToken(_) => (StatusCode::UNAUTHORIZED, self.to_string()).into_response(),
+            GdprError::UnexpectedError(e) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+            }
+        }
+    }
+}
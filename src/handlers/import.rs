@@ -0,0 +1,35 @@
+use crate::AppState;
+use crate::authentication::AdminUser;
+use crate::import::{ImportError, ImportReport, ImportStatus, import_subscribers};
+use axum::Json;
+use axum::body::Body;
+use axum::extract::{Query, State};
+use futures::TryStreamExt;
+use serde::Deserialize;
+use tokio_util::io::StreamReader;
+use tracing::instrument;
+use uuid::Uuid;
+
+#[derive(Deserialize, Debug)]
+pub struct ImportParameters {
+    status: ImportStatus,
+    list_id: Option<Uuid>,
+}
+
+/// Imports the CSV sent as the request body. The body is parsed while it is being received.
+#[instrument(name = "importing subscribers", skip(app_state, body))]
+pub async fn import(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+    Query(params): Query<ImportParameters>,
+    body: Body,
+) -> Result<Json<ImportReport>, ImportError> {
+    let reader = StreamReader::new(body.into_data_stream().map_err(std::io::Error::other));
+    let report = import_subscribers(&app_state.pg_pool, reader, params.status, params.list_id).await?;
+    tracing::info!(
+        imported = report.imported,
+        failed = report.failed,
+        "finished importing subscribers"
+    );
+    Ok(Json(report))
+}
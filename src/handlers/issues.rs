@@ -0,0 +1,642 @@
+use crate::AppState;
+use crate::ab_test::AbTest;
+use crate::authentication::AdminUser;
+use crate::merge_tags::{
+    Defaults, EXAMPLE_UNSUBSCRIBE_URL, MergeError, Newsletter, NewsletterSource, Recipient,
+};
+use crate::stats::{self, Interval, IssueStats};
+use crate::tracking::{self, ClickCounts, OpenCounts};
+use crate::validation::ValidatedEmail;
+use anyhow::Context;
+use axum::Json;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use chrono::{LocalResult, NaiveDateTime, TimeZone};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
+use tracing::instrument;
+use uuid::Uuid;
+
+/// Body of `POST /admin/issues` and `PUT /admin/issues/{id}`: a newsletter as for
+/// `POST /admin/newsletters`, and when the scheduler should send it once approved, see
+/// [`crate::scheduler`].
+#[derive(Deserialize, Debug)]
+pub struct IssueContent {
+    #[serde(flatten)]
+    source: NewsletterSource,
+    #[serde(default)]
+    defaults: Defaults,
+    list_id: Option<Uuid>,
+    /// only confirmed subscribers matching this saved segment, see [`crate::segment`]
+    segment_id: Option<Uuid>,
+    /// wall-clock time in `timezone`; an issue needs one to be approved
+    scheduled_at: Option<NaiveDateTime>,
+    #[serde(default = "utc")]
+    timezone: String,
+    /// send variant subjects to a sample first, see [`crate::ab_test`]
+    ab_test: Option<AbTest>,
+}
+
+/// Body of `PUT /admin/issues/{id}/schedule`. A time in the past is sent on the next round.
+#[derive(Deserialize, Debug)]
+pub struct Schedule {
+    /// wall-clock time in `timezone`, e.g. `2026-10-26T09:00:00`
+    scheduled_at: NaiveDateTime,
+    /// IANA name such as `Asia/Tokyo`, UTC when omitted
+    #[serde(default = "utc")]
+    timezone: String,
+}
+
+/// Body of `POST /admin/issues/{id}/test`.
+#[derive(Deserialize, Debug)]
+pub struct TestSend {
+    /// addresses at `admin.internal_domains`
+    recipients: Vec<String>,
+}
+
+/// Query of `GET /admin/issues/{id}/stats`.
+#[derive(Deserialize, Debug)]
+pub struct StatsQuery {
+    /// length of the buckets of the series, `hour` when omitted
+    #[serde(default)]
+    interval: Interval,
+    /// IANA name the buckets start in, the issue's timezone when omitted
+    timezone: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TestSendReport {
+    sent: Vec<String>,
+    failed: Vec<FailedTestSend>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct FailedTestSend {
+    email: String,
+    error: String,
+}
+
+fn utc() -> String {
+    "UTC".to_string()
+}
+
+fn parse_timezone(timezone: &str) -> Result<Tz, IssueError> {
+    timezone
+        .parse()
+        .map_err(|_| IssueError::UnknownTimezone(timezone.to_string()))
+}
+
+/// The instant `local` means in `timezone`. A time repeated by a DST change means its first
+/// occurrence.
+fn resolve(local: NaiveDateTime, timezone: &str) -> Result<DateTime<Utc>, IssueError> {
+    match parse_timezone(timezone)?.from_local_datetime(&local) {
+        LocalResult::Single(at) | LocalResult::Ambiguous(at, _) => Ok(at.with_timezone(&Utc)),
+        LocalResult::None => Err(IssueError::NonexistentTime(local, timezone.to_string())),
+    }
+}
+
+impl IssueContent {
+    /// An issue of `source` scheduled for the moment it is created.
+    pub(crate) fn send_now(
+        source: NewsletterSource,
+        defaults: Defaults,
+        list_id: Option<Uuid>,
+        segment_id: Option<Uuid>,
+    ) -> Self {
+        IssueContent {
+            source,
+            defaults,
+            list_id,
+            segment_id,
+            scheduled_at: Some(Utc::now().naive_utc()),
+            timezone: utc(),
+            ab_test: None,
+        }
+    }
+
+    /// Checks the content and list, and returns when to send.
+    async fn check(&self, app_state: &AppState) -> Result<Option<DateTime<Utc>>, IssueError> {
+        parse_timezone(&self.timezone)?;
+        let scheduled_at = self
+            .scheduled_at
+            .map(|local| resolve(local, &self.timezone))
+            .transpose()?;
+        let tracking = &app_state.conf.tracking;
+        if let Some(problem) = self.ab_test.as_ref().and_then(|t| t.problem(tracking)) {
+            return Err(IssueError::InvalidAbTest(problem));
+        }
+        compose(
+            app_state,
+            self.source.clone(),
+            self.defaults.clone(),
+            self.ab_test.clone(),
+        )?;
+        if let Some(list_id) = self.list_id {
+            sqlx::query!("SELECT id FROM lists WHERE id = $1", list_id)
+                .fetch_optional(app_state.pg_pool.as_ref())
+                .await
+                .context("error fetching list")?
+                .ok_or(IssueError::ListNotFound(list_id))?;
+        }
+        if let Some(segment_id) = self.segment_id {
+            sqlx::query!("SELECT id FROM segments WHERE id = $1", segment_id)
+                .fetch_optional(app_state.pg_pool.as_ref())
+                .await
+                .context("error fetching segment")?
+                .ok_or(IssueError::SegmentNotFound(segment_id))?;
+        }
+        Ok(scheduled_at)
+    }
+}
+
+/// The newsletter of an issue, with its A/B test subjects added as variants.
+fn compose(
+    app_state: &AppState,
+    source: NewsletterSource,
+    defaults: Defaults,
+    ab_test: Option<AbTest>,
+) -> Result<Newsletter, IssueError> {
+    let mut newsletter = Newsletter::compose(source, defaults, &app_state.templates)?;
+    for subject in ab_test.into_iter().flat_map(|ab_test| ab_test.subjects) {
+        newsletter.add_subject_variant(subject)?;
+    }
+    Ok(newsletter)
+}
+
+#[derive(Serialize, Debug)]
+pub struct Issue {
+    id: Uuid,
+    /// `draft`, `approved`, `testing`, `sending`, `sent`, `cancelled` or `failed`
+    status: String,
+    /// why the scheduler could not start a `failed` issue
+    error: Option<String>,
+    scheduled_at: Option<DateTime<Utc>>,
+    timezone: String,
+    /// `scheduled_at` in `timezone`
+    local_scheduled_at: Option<NaiveDateTime>,
+    list_id: Option<Uuid>,
+    segment_id: Option<Uuid>,
+    created_by: String,
+    created_at: DateTime<Utc>,
+    /// the admin who last changed the content
+    updated_by: String,
+    updated_at: DateTime<Utc>,
+    approved_by: Option<String>,
+    approved_at: Option<DateTime<Utc>>,
+    started_at: Option<DateTime<Utc>>,
+    completed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct IssueDetail {
+    #[serde(flatten)]
+    issue: Issue,
+    content: serde_json::Value,
+    defaults: serde_json::Value,
+    ab_test: Option<serde_json::Value>,
+    /// one [`crate::ab_test::VariantResult`] per variant, once the winner is picked
+    ab_results: Option<serde_json::Value>,
+    ab_winner: Option<i32>,
+    ab_decided_at: Option<DateTime<Utc>>,
+    opens: OpenCounts,
+    clicks: ClickCounts,
+}
+
+#[instrument(name = "creating an issue", skip(app_state, content))]
+pub async fn create_issue(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+    Json(content): Json<IssueContent>,
+) -> Result<(StatusCode, Json<IssueDetail>), IssueError> {
+    let scheduled_at = content.check(&app_state).await?;
+    let id = Uuid::new_v4();
+    sqlx::query!(
+        r#"INSERT INTO newsletter_issues
+            (id, content, defaults, list_id, segment_id, status, scheduled_at, timezone, ab_test,
+            created_by, created_at, updated_by, updated_at)
+        VALUES ($1, $2, $3, $4, $5, 'draft', $6, $7, $8, $9, $10, $9, $10)"#,
+        id,
+        serde_json::to_value(&content.source).context("error serializing issue content")?,
+        serde_json::to_value(&content.defaults).context("error serializing issue defaults")?,
+        content.list_id,
+        content.segment_id,
+        scheduled_at,
+        content.timezone,
+        content
+            .ab_test
+            .as_ref()
+            .map(serde_json::to_value)
+            .transpose()
+            .context("error serializing A/B test")?,
+        admin.name,
+        Utc::now(),
+    )
+    .execute(app_state.pg_pool.as_ref())
+    .await
+    .context("error inserting issue")?;
+
+    let detail = fetch_issue(&app_state, id).await?;
+    Ok((StatusCode::CREATED, Json(detail)))
+}
+
+#[instrument(name = "listing issues", skip(app_state))]
+pub async fn get_issues(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+) -> Result<Json<Vec<Issue>>, IssueError> {
+    let issues = sqlx::query_as!(
+        Issue,
+        r#"SELECT id, status, error, scheduled_at, timezone,
+            scheduled_at AT TIME ZONE timezone AS local_scheduled_at,
+            list_id, segment_id, created_by, created_at, updated_by, updated_at, approved_by, approved_at,
+            started_at, completed_at
+        FROM newsletter_issues ORDER BY scheduled_at DESC NULLS FIRST, created_at DESC"#
+    )
+    .fetch_all(app_state.pg_pool.as_ref())
+    .await
+    .context("error fetching issues")?;
+    Ok(Json(issues))
+}
+
+#[instrument(name = "fetching an issue", skip(app_state))]
+pub async fn get_issue(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+    Path(issue_id): Path<Uuid>,
+) -> Result<Json<IssueDetail>, IssueError> {
+    fetch_issue(&app_state, issue_id).await.map(Json)
+}
+
+/// Replaces the content and schedule of a draft. An approved issue becomes a draft again and
+/// needs a new approval.
+#[instrument(name = "editing an issue", skip(app_state, content))]
+pub async fn update_issue(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+    Path(issue_id): Path<Uuid>,
+    Json(content): Json<IssueContent>,
+) -> Result<Json<IssueDetail>, IssueError> {
+    let scheduled_at = content.check(&app_state).await?;
+    let res = sqlx::query!(
+        r#"UPDATE newsletter_issues
+        SET content = $2, defaults = $3, list_id = $4, segment_id = $5, scheduled_at = $6,
+            timezone = $7, ab_test = $8, status = 'draft', approved_by = NULL, approved_at = NULL,
+            updated_by = $9, updated_at = $10
+        WHERE id = $1 AND status IN ('draft', 'approved')"#,
+        issue_id,
+        serde_json::to_value(&content.source).context("error serializing issue content")?,
+        serde_json::to_value(&content.defaults).context("error serializing issue defaults")?,
+        content.list_id,
+        content.segment_id,
+        scheduled_at,
+        content.timezone,
+        content
+            .ab_test
+            .as_ref()
+            .map(serde_json::to_value)
+            .transpose()
+            .context("error serializing A/B test")?,
+        admin.name,
+        Utc::now(),
+    )
+    .execute(app_state.pg_pool.as_ref())
+    .await
+    .context("error updating issue")?;
+    let detail = fetch_issue(&app_state, issue_id).await?;
+    if res.rows_affected() == 0 {
+        return Err(IssueError::NotEditable(detail.issue.status));
+    }
+    Ok(Json(detail))
+}
+
+/// Moves an issue to another time. An approved issue stays approved since its content is
+/// unchanged. Fails once the scheduler has started it.
+#[instrument(name = "rescheduling an issue", skip(app_state))]
+pub async fn reschedule_issue(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+    Path(issue_id): Path<Uuid>,
+    Json(schedule): Json<Schedule>,
+) -> Result<Json<IssueDetail>, IssueError> {
+    let scheduled_at = resolve(schedule.scheduled_at, &schedule.timezone)?;
+    let res = sqlx::query!(
+        r#"UPDATE newsletter_issues SET scheduled_at = $2, timezone = $3, updated_at = $4
+        WHERE id = $1 AND status IN ('draft', 'approved')"#,
+        issue_id,
+        scheduled_at,
+        schedule.timezone,
+        Utc::now(),
+    )
+    .execute(app_state.pg_pool.as_ref())
+    .await
+    .context("error rescheduling issue")?;
+    let detail = fetch_issue(&app_state, issue_id).await?;
+    if res.rows_affected() == 0 {
+        return Err(IssueError::NotEditable(detail.issue.status));
+    }
+    Ok(Json(detail))
+}
+
+/// Approves a scheduled draft for sending. The approver must be an admin other than the one
+/// who created the issue and the one who last edited it.
+#[instrument(name = "approving an issue", skip(app_state))]
+pub async fn approve_issue(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+    Path(issue_id): Path<Uuid>,
+) -> Result<Json<IssueDetail>, IssueError> {
+    let mut transaction = app_state
+        .pg_pool
+        .begin()
+        .await
+        .context("error starting transaction")?;
+    let issue = sqlx::query!(
+        r#"SELECT status, scheduled_at, created_by, updated_by FROM newsletter_issues
+        WHERE id = $1 FOR UPDATE"#,
+        issue_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("error fetching issue")?
+    .ok_or(IssueError::NotFound(issue_id))?;
+    if issue.status != "draft" {
+        return Err(IssueError::NotDraft(issue.status));
+    }
+    if issue.scheduled_at.is_none() {
+        return Err(IssueError::Unscheduled);
+    }
+    if admin.name == issue.created_by || admin.name == issue.updated_by {
+        return Err(IssueError::OwnIssue(admin.name));
+    }
+    sqlx::query!(
+        r#"UPDATE newsletter_issues SET status = 'approved', approved_by = $2, approved_at = $3
+        WHERE id = $1"#,
+        issue_id,
+        admin.name,
+        Utc::now(),
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("error approving issue")?;
+    transaction
+        .commit()
+        .await
+        .context("error commiting transaction")?;
+    fetch_issue(&app_state, issue_id).await.map(Json)
+}
+
+/// Cancels a draft or approved issue. Fails once the scheduler has started it.
+#[instrument(name = "cancelling an issue", skip(app_state))]
+pub async fn cancel_issue(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+    Path(issue_id): Path<Uuid>,
+) -> Result<Json<IssueDetail>, IssueError> {
+    let res = sqlx::query!(
+        r#"UPDATE newsletter_issues SET status = 'cancelled', updated_at = $2
+        WHERE id = $1 AND status IN ('draft', 'approved')"#,
+        issue_id,
+        Utc::now(),
+    )
+    .execute(app_state.pg_pool.as_ref())
+    .await
+    .context("error cancelling issue")?;
+    let detail = fetch_issue(&app_state, issue_id).await?;
+    if res.rows_affected() == 0 {
+        return Err(IssueError::NotEditable(detail.issue.status));
+    }
+    Ok(Json(detail))
+}
+
+/// Sends the issue, with `[TEST]` before the subject, to internal addresses. A recipient who
+/// is also a subscriber gets their own merge fields; unsubscribe links lead nowhere. With an
+/// A/B test every recipient gets each variant, marked `[TEST n/total]`.
+#[instrument(name = "test sending an issue", skip(app_state))]
+pub async fn test_issue(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+    Path(issue_id): Path<Uuid>,
+    Json(test): Json<TestSend>,
+) -> Result<Json<TestSendReport>, IssueError> {
+    if test.recipients.is_empty() {
+        return Err(IssueError::NoRecipients);
+    }
+    if let Some(email) = test.recipients.iter().find(|email| {
+        ValidatedEmail::parse(email).is_err() || !app_state.conf.admin.is_internal(email)
+    }) {
+        return Err(IssueError::NotInternal(email.clone()));
+    }
+    let detail = fetch_issue(&app_state, issue_id).await?;
+    let source: NewsletterSource =
+        serde_json::from_value(detail.content).context("error reading issue content")?;
+    let defaults: Defaults =
+        serde_json::from_value(detail.defaults).context("error reading issue defaults")?;
+    let ab_test: Option<AbTest> = detail
+        .ab_test
+        .map(serde_json::from_value)
+        .transpose()
+        .context("error reading A/B test")?;
+    let variants = ab_test.as_ref().map_or(0, |ab_test| ab_test.subjects.len());
+    let newsletter = compose(&app_state, source, defaults, ab_test)?;
+
+    let mut report = TestSendReport {
+        sent: Vec::new(),
+        failed: Vec::new(),
+    };
+    for email in test.recipients {
+        let subscriber = sqlx::query!(
+            r#"SELECT name, attributes FROM subscriptions WHERE lower(email) = lower($1)"#,
+            email
+        )
+        .fetch_optional(app_state.pg_pool.as_ref())
+        .await
+        .context("error fetching subscriber")?;
+        let recipient = match subscriber {
+            Some(s) => Recipient {
+                name: s.name,
+                email: email.clone(),
+                attributes: s.attributes,
+            },
+            None => Recipient {
+                name: String::new(),
+                email: email.clone(),
+                attributes: serde_json::Value::Object(Default::default()),
+            },
+        };
+        let result = async {
+            let mut emails = Vec::new();
+            if variants == 0 {
+                let rendered = newsletter.render(&app_state.templates, &recipient, EXAMPLE_UNSUBSCRIBE_URL)?;
+                emails.push((format!("[TEST] {}", rendered.subject), rendered));
+            }
+            for variant in 0..variants {
+                let rendered = newsletter.render_variant(
+                    &app_state.templates,
+                    &recipient,
+                    EXAMPLE_UNSUBSCRIBE_URL,
+                    variant,
+                )?;
+                let subject = format!("[TEST {}/{variants}] {}", variant + 1, rendered.subject);
+                emails.push((subject, rendered));
+            }
+            for (subject, rendered) in emails {
+                app_state
+                    .email_client
+                    .send_email(&email, &subject, &rendered.html, rendered.text.as_deref())
+                    .await?;
+            }
+            anyhow::Ok(())
+        }
+        .await;
+        match result {
+            Ok(()) => report.sent.push(email),
+            Err(e) => {
+                tracing::error!(error = ?e, %email, "error sending test issue");
+                report.failed.push(FailedTestSend {
+                    email,
+                    error: format!("{e:#}"),
+                });
+            }
+        }
+    }
+    Ok(Json(report))
+}
+
+/// Delivery and engagement counts of an issue, in total and over time, see [`crate::stats`].
+#[instrument(name = "reporting on an issue", skip(app_state))]
+pub async fn issue_stats(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+    Path(issue_id): Path<Uuid>,
+    Query(query): Query<StatsQuery>,
+) -> Result<Json<IssueStats>, IssueError> {
+    let issue_timezone = sqlx::query_scalar!(
+        "SELECT timezone FROM newsletter_issues WHERE id = $1",
+        issue_id
+    )
+    .fetch_optional(app_state.pg_pool.as_ref())
+    .await
+    .context("error fetching issue")?
+    .ok_or(IssueError::NotFound(issue_id))?;
+    let timezone = query.timezone.unwrap_or(issue_timezone);
+    parse_timezone(&timezone)?;
+    let report = stats::issue_stats(&app_state.pg_pool, issue_id, query.interval, &timezone).await?;
+    Ok(Json(report))
+}
+
+async fn fetch_issue(app_state: &AppState, issue_id: Uuid) -> Result<IssueDetail, IssueError> {
+    let row = sqlx::query!(
+        r#"SELECT id, status, error, scheduled_at, timezone,
+            scheduled_at AT TIME ZONE timezone AS local_scheduled_at,
+            list_id, segment_id, created_by, created_at, updated_by, updated_at, approved_by, approved_at,
+            started_at, completed_at, content, defaults, ab_test, ab_results, ab_winner, ab_decided_at
+        FROM newsletter_issues WHERE id = $1"#,
+        issue_id
+    )
+    .fetch_optional(app_state.pg_pool.as_ref())
+    .await
+    .context("error fetching issue")?
+    .ok_or(IssueError::NotFound(issue_id))?;
+    let opens = tracking::open_counts(app_state.pg_pool.as_ref(), issue_id).await?;
+    let clicks = tracking::click_counts(&app_state.pg_pool, issue_id).await?;
+    Ok(IssueDetail {
+        issue: Issue {
+            id: row.id,
+            status: row.status,
+            error: row.error,
+            scheduled_at: row.scheduled_at,
+            timezone: row.timezone,
+            local_scheduled_at: row.local_scheduled_at,
+            list_id: row.list_id,
+            segment_id: row.segment_id,
+            created_by: row.created_by,
+            created_at: row.created_at,
+            updated_by: row.updated_by,
+            updated_at: row.updated_at,
+            approved_by: row.approved_by,
+            approved_at: row.approved_at,
+            started_at: row.started_at,
+            completed_at: row.completed_at,
+        },
+        content: row.content,
+        defaults: row.defaults,
+        ab_test: row.ab_test,
+        ab_results: row.ab_results,
+        ab_winner: row.ab_winner,
+        ab_decided_at: row.ab_decided_at,
+        opens,
+        clicks,
+    })
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum IssueError {
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+
+    #[error(transparent)]
+    InvalidContent(#[from] MergeError),
+
+    #[error("unknown timezone: {0}")]
+    UnknownTimezone(String),
+
+    #[error("{0} does not exist in {1}")]
+    NonexistentTime(NaiveDateTime, String),
+
+    #[error("list not found: {0}")]
+    ListNotFound(Uuid),
+
+    #[error("segment not found: {0}")]
+    SegmentNotFound(Uuid),
+
+    #[error("{0}")]
+    InvalidAbTest(String),
+
+    #[error("issue not found: {0}")]
+    NotFound(Uuid),
+
+    #[error("issue is {0}, only drafts and approved issues can be changed")]
+    NotEditable(String),
+
+    #[error("issue is {0}, only drafts can be approved")]
+    NotDraft(String),
+
+    #[error("issue has no send time")]
+    Unscheduled,
+
+    #[error("{0} wrote or edited this issue, another admin must approve it")]
+    OwnIssue(String),
+
+    #[error("no test recipients")]
+    NoRecipients,
+
+    #[error("not an internal address: {0}")]
+    NotInternal(String),
+}
+
+impl IntoResponse for IssueError {
+    fn into_response(self) -> Response {
+        match self {
+            IssueError::UnexpectedError(e) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+            }
+            IssueError::InvalidContent(_)
+            | IssueError::UnknownTimezone(_)
+            | IssueError::NonexistentTime(..)
+            | IssueError::InvalidAbTest(_)
+            | IssueError::NoRecipients
+            | IssueError::NotInternal(_) => {
+                (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+            }
+            IssueError::ListNotFound(_) | IssueError::SegmentNotFound(_) | IssueError::NotFound(_) => {
+                (StatusCode::NOT_FOUND, self.to_string()).into_response()
+            }
+            IssueError::NotEditable(_) | IssueError::NotDraft(_) | IssueError::Unscheduled => {
+                (StatusCode::CONFLICT, self.to_string()).into_response()
+            }
+            IssueError::OwnIssue(_) => (StatusCode::FORBIDDEN, self.to_string()).into_response(),
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use crate::AppState;
+use crate::authentication::AdminUser;
+use crate::consent::ClientInfo;
+use crate::handlers::subscription::{SubscriberInfo, SubscriptionError, register_subscriber};
+use anyhow::Context;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::{Form, Json};
+use garde::Validate;
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
+use tracing::instrument;
+use uuid::Uuid;
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct NewList {
+    #[garde(length(min = 1, max = 100))]
+    name: String,
+    #[garde(skip)]
+    #[serde(default)]
+    description: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MailingList {
+    id: Uuid,
+    name: String,
+    description: String,
+    created_at: DateTime<Utc>,
+}
+
+#[instrument(name = "creating a mailing list", skip(app_state))]
+pub async fn create_list(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+    Json(new_list): Json<NewList>,
+) -> Result<(StatusCode, Json<MailingList>), ListError> {
+    new_list.validate()?;
+    let list = sqlx::query_as!(
+        MailingList,
+        r#"INSERT INTO lists (id, name, description, created_at) VALUES ($1, $2, $3, $4)
+        ON CONFLICT (name) DO NOTHING
+        RETURNING id, name, description, created_at"#,
+        Uuid::new_v4(),
+        new_list.name,
+        new_list.description,
+        Utc::now(),
+    )
+    .fetch_optional(app_state.pg_pool.as_ref())
+    .await
+    .context("error inserting list")?
+    .ok_or_else(|| ListError::Conflict(new_list.name.clone()))?;
+    Ok((StatusCode::CREATED, Json(list)))
+}
+
+#[instrument(name = "listing mailing lists", skip(app_state))]
+pub async fn get_lists(
+    State(app_state): State<AppState>,
+) -> Result<Json<Vec<MailingList>>, ListError> {
+    let lists = sqlx::query_as!(
+        MailingList,
+        "SELECT id, name, description, created_at FROM lists ORDER BY created_at"
+    )
+    .fetch_all(app_state.pg_pool.as_ref())
+    .await
+    .context("error fetching lists")?;
+    Ok(Json(lists))
+}
+
+#[instrument(
+name = "adding a new list member",
+skip(app_state),
+fields(
+    subscriber_email = %form.email,
+    subscriber_name = %form.username,
+)
+)]
+pub async fn subscribe_to_list(
+    State(app_state): State<AppState>,
+    Path(list_id): Path<Uuid>,
+    client: ClientInfo,
+    Form(form): Form<SubscriberInfo>,
+) -> Result<StatusCode, SubscriptionError> {
+    form.validate()?;
+
+    let mut transaction = app_state
+        .pg_pool
+        .begin()
+        .await
+        .context("error starting transaction")?;
+
+    let exists = sqlx::query!("SELECT id FROM lists WHERE id = $1", list_id)
+        .fetch_optional(&mut *transaction)
+        .await
+        .context("error finding list")?
+        .is_some();
+    if !exists {
+        return Err(SubscriptionError::ListNotFound(list_id));
+    }
+
+    let confirmation_path = format!("/lists/{list_id}/subscription/confirm");
+    register_subscriber(&app_state, &mut transaction, &form, &client, list_id, &confirmation_path).await?;
+
+    transaction.commit().await
+        .context("error commiting transaction")?;
+    Ok(StatusCode::OK)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ListError {
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+
+    #[error("{0}")]
+    ValidationError(#[from] garde::Report),
+
+    #[error("list already exists: {0}")]
+    Conflict(String),
+}
+
+impl IntoResponse for ListError {
+    fn into_response(self) -> Response {
+        match self {
+            ListError::UnexpectedError(e) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+            }
+            ListError::ValidationError(e) => {
+                (StatusCode::BAD_REQUEST, e.to_string()).into_response()
+            }
+            ListError::Conflict(_) => (StatusCode::CONFLICT, self.to_string()).into_response(),
+        }
+    }
+}
@@ -1,3 +1,16 @@
+pub mod email_templates;
+pub mod export;
+pub mod gdpr;
 pub mod health_check;
+pub mod import;
+pub mod issues;
 pub mod subscription;
 pub mod confirm_subscription;
+pub mod lists;
+pub mod newsletters;
+pub mod preferences;
+pub mod segments;
+pub mod subscribers;
+pub mod tags;
+pub mod tracking;
+pub mod webhooks;
@@ -0,0 +1,41 @@
+use crate::AppState;
+use crate::authentication::AdminUser;
+use crate::handlers::issues::{self, IssueContent, IssueDetail, IssueError};
+use crate::merge_tags::{Defaults, NewsletterSource};
+use axum::Json;
+use axum::extract::State;
+use axum::http::StatusCode;
+use serde::Deserialize;
+use tracing::instrument;
+use uuid::Uuid;
+
+/// Body of `POST /admin/newsletters`: `subject`, `html` and optionally `text`, or `markdown`
+/// with front matter. See [`crate::merge_tags`] for the variables they can use.
+#[derive(Deserialize, Debug)]
+pub struct NewNewsletter {
+    #[serde(flatten)]
+    source: NewsletterSource,
+    #[serde(default)]
+    defaults: Defaults,
+    /// only confirmed members of this list; every confirmed subscriber when omitted
+    list_id: Option<Uuid>,
+    /// only confirmed subscribers matching this saved segment, see [`crate::segment`]
+    segment_id: Option<Uuid>,
+}
+
+/// Creates a draft issue scheduled now, so a newsletter is only sent once a second admin
+/// approves it, like any other issue.
+#[instrument(name = "creating a newsletter", skip(app_state, newsletter))]
+pub async fn create_newsletter(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+    Json(newsletter): Json<NewNewsletter>,
+) -> Result<(StatusCode, Json<IssueDetail>), IssueError> {
+    let content = IssueContent::send_now(
+        newsletter.source,
+        newsletter.defaults,
+        newsletter.list_id,
+        newsletter.segment_id,
+    );
+    issues::create_issue(State(app_state), admin, Json(content)).await
+}
@@ -0,0 +1,401 @@
+use crate::AppState;
+use crate::consent::ClientInfo;
+use crate::handlers::confirm_subscription::{LinkParameters, confirm_subscriber, resolve_link};
+use crate::signed_link::LinkAction;
+use crate::templates::ManageSubscriptionEmail;
+use crate::token;
+use anyhow::Context;
+use axum::{Form, Json};
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse, Response};
+use garde::Validate;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use sqlx::types::chrono::Utc;
+use tracing::instrument;
+use uuid::Uuid;
+
+/// Body of `POST /preferences`.
+#[derive(Deserialize, Debug)]
+pub struct LinkRequest {
+    email: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DigestFrequency {
+    Immediate,
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+impl DigestFrequency {
+    pub fn as_str(&self) -> &str {
+        match self {
+            DigestFrequency::Immediate => "immediate",
+            DigestFrequency::Daily => "daily",
+            DigestFrequency::Weekly => "weekly",
+            DigestFrequency::Monthly => "monthly",
+        }
+    }
+}
+
+impl TryFrom<String> for DigestFrequency {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "immediate" => Ok(Self::Immediate),
+            "daily" => Ok(Self::Daily),
+            "weekly" => Ok(Self::Weekly),
+            "monthly" => Ok(Self::Monthly),
+            _ => Err(anyhow::anyhow!("unknown digest frequency: {value}")),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct ListPreference {
+    id: Uuid,
+    name: String,
+    description: String,
+    subscribed: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Preferences {
+    email: String,
+    name: String,
+    status: String,
+    digest_frequency: DigestFrequency,
+    lists: Vec<ListPreference>,
+}
+
+/// Body of `PUT /preferences/api`. Omitted fields are left unchanged.
+#[derive(Deserialize, Validate, Debug)]
+pub struct PreferencesUpdate {
+    #[garde(length(min = 1), alphanumeric)]
+    name: Option<String>,
+    #[garde(skip)]
+    digest_frequency: Option<DigestFrequency>,
+    /// the lists to receive. memberships of lists not in here are unsubscribed.
+    #[garde(skip)]
+    lists: Option<Vec<Uuid>>,
+    #[garde(skip)]
+    #[serde(default)]
+    unsubscribe_all: bool,
+}
+
+/// Mails the subscriber with `email` links to the preference center and to their data. The
+/// answer is always the same, also for unknown addresses and failed sends, so it cannot be used
+/// to find out who subscribed. An address is mailed at most once per
+/// `links.request_interval_minutes`, so the endpoint cannot be used to flood a mailbox.
+#[instrument(name = "mailing subscription management links", skip(app_state, request))]
+pub async fn request_links(
+    State(app_state): State<AppState>,
+    Form(request): Form<LinkRequest>,
+) -> StatusCode {
+    if let Err(e) = mail_links(&app_state, &request.email).await {
+        tracing::error!(error = ?e, "error mailing subscription management links");
+    }
+    StatusCode::ACCEPTED
+}
+
+async fn mail_links(app_state: &AppState, email: &str) -> anyhow::Result<()> {
+    let mut transaction = app_state
+        .pg_pool
+        .begin()
+        .await
+        .context("error starting transaction")?;
+    let links = &app_state.conf.links;
+    let now = Utc::now();
+    // matched like src/gdpr.rs; a failed send rolls back the timestamp with the tokens
+    let subscribers = sqlx::query!(
+        r#"UPDATE subscriptions SET links_requested_at = $2
+        WHERE lower(email) = lower($1) AND (links_requested_at IS NULL OR links_requested_at <= $3)
+        RETURNING id, email, name, locale"#,
+        email.trim(),
+        now,
+        now - links.request_interval(),
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("error fetching subscriber")?;
+
+    let base_url = app_state.conf.application.base_url.trim_end_matches('/');
+    for subscriber in subscribers {
+        let preferences =
+            token::link_query(links, &mut *transaction, LinkAction::Preferences, subscriber.id, None)
+                .await?;
+        let data = token::link_query(links, &mut *transaction, LinkAction::Data, subscriber.id, None)
+            .await?;
+        let context = ManageSubscriptionEmail {
+            name: &subscriber.name,
+            preferences_link: &format!("{base_url}/preferences?{preferences}"),
+            data_link: &format!("{base_url}/gdpr/data?{data}"),
+        };
+        let locale = app_state.conf.application.locale_or_default(subscriber.locale.as_deref());
+        let email = app_state
+            .templates
+            .render_current(&mut *transaction, Some(locale), &context)
+            .await?;
+        app_state
+            .email_client
+            .send_email(&subscriber.email, &email.subject, &email.html, email.text.as_deref())
+            .await
+            .context("error sending subscription management links")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("error commiting transaction")?;
+    Ok(())
+}
+
+#[instrument(name = "showing the preference center", skip(app_state, param))]
+pub async fn preferences_page(
+    State(app_state): State<AppState>,
+    Query(param): Query<LinkParameters>,
+) -> Result<Html<&'static str>, PreferencesError> {
+    resolve_link(&app_state, param, LinkAction::Preferences)
+        .await
+        .map_err(|e| PreferencesError::InvalidToken(e.into()))?;
+    Ok(Html(PREFERENCES_PAGE))
+}
+
+#[instrument(name = "fetching subscriber preferences", skip(app_state, param))]
+pub async fn get_preferences(
+    State(app_state): State<AppState>,
+    Query(param): Query<LinkParameters>,
+) -> Result<Json<Preferences>, PreferencesError> {
+    let (subscriber_uuid, _) = resolve_link(&app_state, param, LinkAction::Preferences)
+        .await
+        .map_err(|e| PreferencesError::InvalidToken(e.into()))?;
+    let preferences = fetch_preferences(&app_state.pg_pool, subscriber_uuid).await?;
+    Ok(Json(preferences))
+}
+
+#[instrument(name = "updating subscriber preferences", skip(app_state, param))]
+pub async fn update_preferences(
+    State(app_state): State<AppState>,
+    client: ClientInfo,
+    Query(param): Query<LinkParameters>,
+    Json(update): Json<PreferencesUpdate>,
+) -> Result<Json<Preferences>, PreferencesError> {
+    update.validate()?;
+    let (subscriber_uuid, _) = resolve_link(&app_state, param, LinkAction::Preferences)
+        .await
+        .map_err(|e| PreferencesError::InvalidToken(e.into()))?;
+
+    let mut transaction = app_state
+        .pg_pool
+        .begin()
+        .await
+        .context("error starting transaction")?;
+
+    if let Some(name) = &update.name {
+        sqlx::query!("UPDATE subscriptions SET name = $1 WHERE id = $2", name, subscriber_uuid)
+            .execute(&mut *transaction)
+            .await
+            .context("error updating name")?;
+    }
+    if let Some(frequency) = update.digest_frequency {
+        sqlx::query!(
+            "UPDATE subscriptions SET digest_frequency = $1 WHERE id = $2",
+            frequency.as_str(),
+            subscriber_uuid
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("error updating digest frequency")?;
+    }
+
+    if update.unsubscribe_all {
+        sqlx::query!(
+            "UPDATE subscriptions SET status = 'unsubscribed' WHERE id = $1",
+            subscriber_uuid
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("error unsubscribing subscriber")?;
+        sqlx::query!(
+            "UPDATE list_memberships SET status = 'unsubscribed' WHERE subscriber_uuid = $1",
+            subscriber_uuid
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("error unsubscribing list memberships")?;
+    } else if let Some(lists) = &update.lists {
+        // the link was mailed to the subscriber, so choosing a list here confirms it the same
+        // way a confirmation link does
+        let chosen = sqlx::query_scalar!(
+            r#"INSERT INTO list_memberships (list_id, subscriber_uuid, status, subscribed_at)
+            SELECT id, $1, 'not-confirmed', $2 FROM lists WHERE id = ANY($3)
+            ON CONFLICT (list_id, subscriber_uuid) DO UPDATE
+            SET status = 'not-confirmed', subscribed_at = EXCLUDED.subscribed_at
+            WHERE list_memberships.status <> 'confirmed'
+            RETURNING list_id"#,
+            subscriber_uuid,
+            Utc::now(),
+            lists,
+        )
+        .fetch_all(&mut *transaction)
+        .await
+        .context("error subscribing to lists")?;
+        for list_id in chosen {
+            confirm_subscriber(&mut transaction, subscriber_uuid, Some(list_id), &client)
+                .await
+                .context("error confirming list membership")?;
+        }
+        sqlx::query!(
+            r#"UPDATE list_memberships SET status = 'unsubscribed'
+            WHERE subscriber_uuid = $1 AND NOT (list_id = ANY($2))"#,
+            subscriber_uuid,
+            lists,
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("error unsubscribing from lists")?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("error commiting transaction")?;
+
+    let preferences = fetch_preferences(&app_state.pg_pool, subscriber_uuid).await?;
+    Ok(Json(preferences))
+}
+
+async fn fetch_preferences(pool: &PgPool, subscriber_uuid: Uuid) -> anyhow::Result<Preferences> {
+    let subscriber = sqlx::query!(
+        "SELECT email, name, status, digest_frequency FROM subscriptions WHERE id = $1",
+        subscriber_uuid
+    )
+    .fetch_one(pool)
+    .await
+    .context("error fetching subscriber")?;
+    let lists = sqlx::query_as!(
+        ListPreference,
+        r#"SELECT l.id, l.name, l.description, (m.status IS NOT NULL AND m.status <> 'unsubscribed') AS "subscribed!"
+        FROM lists l
+        LEFT JOIN list_memberships m ON m.list_id = l.id AND m.subscriber_uuid = $1
+        ORDER BY l.created_at"#,
+        subscriber_uuid
+    )
+    .fetch_all(pool)
+    .await
+    .context("error fetching list memberships")?;
+
+    Ok(Preferences {
+        email: subscriber.email,
+        name: subscriber.name,
+        status: subscriber.status,
+        digest_frequency: subscriber.digest_frequency.try_into()?,
+        lists,
+    })
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PreferencesError {
+    #[error("invalid token: {0}")]
+    InvalidToken(anyhow::Error),
+
+    #[error("{0}")]
+    ValidationError(#[from] garde::Report),
+
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl IntoResponse for PreferencesError {
+    fn into_response(self) -> Response {
+        match self {
+            PreferencesError::InvalidToken(_) => {
+                (StatusCode::UNAUTHORIZED, self.to_string()).into_response()
+            }
+            PreferencesError::ValidationError(e) => {
+                (StatusCode::BAD_REQUEST, e.to_string()).into_response()
+            }
+            PreferencesError::UnexpectedError(e) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+            }
+        }
+    }
+}
+
+/// The page talks to `/preferences/api` with the link parameters from its own query string,
+/// so it does not need to be rendered per subscriber.
+const PREFERENCES_PAGE: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Email preferences</title>
+</head>
+<body>
+<h1>Email preferences</h1>
+<form id="preferences">
+  <p id="email"></p>
+  <label>Name <input name="name" id="name"></label>
+  <fieldset id="lists"><legend>Newsletters</legend></fieldset>
+  <label>Frequency
+    <select name="digest_frequency" id="digest_frequency">
+      <option value="immediate">Immediately</option>
+      <option value="daily">Daily digest</option>
+      <option value="weekly">Weekly digest</option>
+      <option value="monthly">Monthly digest</option>
+    </select>
+  </label>
+  <label><input type="checkbox" id="unsubscribe_all"> Unsubscribe from everything</label>
+  <button type="submit">Save</button>
+  <p id="result"></p>
+</form>
+<script>
+const api = "/preferences/api" + window.location.search;
+const form = document.getElementById("preferences");
+
+function render(prefs) {
+  document.getElementById("email").textContent = prefs.email;
+  document.getElementById("name").value = prefs.name;
+  document.getElementById("digest_frequency").value = prefs.digest_frequency;
+  const lists = document.getElementById("lists");
+  lists.querySelectorAll("label").forEach((l) => l.remove());
+  for (const list of prefs.lists) {
+    const label = document.createElement("label");
+    const box = document.createElement("input");
+    box.type = "checkbox";
+    box.value = list.id;
+    box.checked = list.subscribed;
+    label.append(box, " " + list.name);
+    lists.append(label);
+  }
+}
+
+fetch(api).then((r) => r.json()).then(render);
+
+form.addEventListener("submit", async (e) => {
+  e.preventDefault();
+  const body = {
+    name: document.getElementById("name").value,
+    digest_frequency: document.getElementById("digest_frequency").value,
+    lists: [...document.querySelectorAll("#lists input:checked")].map((b) => b.value),
+    unsubscribe_all: document.getElementById("unsubscribe_all").checked,
+  };
+  const resp = await fetch(api, {
+    method: "PUT",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify(body),
+  });
+  if (resp.ok) {
+    render(await resp.json());
+    document.getElementById("result").textContent = "Saved.";
+  } else {
+    document.getElementById("result").textContent = await resp.text();
+  }
+});
+</script>
+</body>
+</html>
+"##;
@@ -0,0 +1,187 @@
+use crate::AppState;
+use crate::authentication::AdminUser;
+use crate::segment::{self, Recipient};
+use anyhow::Context;
+use axum::Json;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use garde::Validate;
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
+use tracing::instrument;
+use uuid::Uuid;
+
+/// number of matching subscribers returned alongside the count in a preview
+const PREVIEW_SAMPLE_SIZE: i64 = 10;
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct NewSegment {
+    #[garde(length(min = 1, max = 100))]
+    name: String,
+    #[garde(skip)]
+    expression: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PreviewRequest {
+    expression: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Segment {
+    id: Uuid,
+    name: String,
+    expression: String,
+    created_by: String,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SegmentPreview {
+    count: i64,
+    sample: Vec<Recipient>,
+}
+
+#[instrument(name = "creating a segment", skip(app_state))]
+pub async fn create_segment(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+    Json(new_segment): Json<NewSegment>,
+) -> Result<(StatusCode, Json<Segment>), SegmentError> {
+    new_segment.validate()?;
+    segment::parse(&new_segment.expression)?;
+    let segment = sqlx::query_as!(
+        Segment,
+        r#"INSERT INTO segments (id, name, expression, created_by, created_at) VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (name) DO NOTHING
+        RETURNING id, name, expression, created_by, created_at"#,
+        Uuid::new_v4(),
+        new_segment.name,
+        new_segment.expression,
+        admin.name,
+        Utc::now(),
+    )
+    .fetch_optional(app_state.pg_pool.as_ref())
+    .await
+    .context("error inserting segment")?
+    .ok_or_else(|| SegmentError::Conflict(new_segment.name.clone()))?;
+    Ok((StatusCode::CREATED, Json(segment)))
+}
+
+#[instrument(name = "listing segments", skip(app_state))]
+pub async fn get_segments(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+) -> Result<Json<Vec<Segment>>, SegmentError> {
+    let segments = sqlx::query_as!(
+        Segment,
+        "SELECT id, name, expression, created_by, created_at FROM segments ORDER BY name"
+    )
+    .fetch_all(app_state.pg_pool.as_ref())
+    .await
+    .context("error fetching segments")?;
+    Ok(Json(segments))
+}
+
+#[instrument(name = "deleting a segment", skip(app_state))]
+pub async fn delete_segment(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+    Path(segment_id): Path<Uuid>,
+) -> Result<StatusCode, SegmentError> {
+    let res = sqlx::query!("DELETE FROM segments WHERE id = $1", segment_id)
+        .execute(app_state.pg_pool.as_ref())
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(e) if e.is_foreign_key_violation() => SegmentError::InUse(segment_id),
+            e => anyhow::Error::from(e).context("error deleting segment").into(),
+        })?;
+    if res.rows_affected() == 0 {
+        return Err(SegmentError::NotFound(segment_id));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Previews an unsaved expression.
+#[instrument(name = "previewing a segment expression", skip(app_state))]
+pub async fn preview_expression(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+    Json(request): Json<PreviewRequest>,
+) -> Result<Json<SegmentPreview>, SegmentError> {
+    preview(&app_state, &request.expression).await.map(Json)
+}
+
+#[instrument(name = "previewing a segment", skip(app_state))]
+pub async fn preview_segment(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+    Path(segment_id): Path<Uuid>,
+) -> Result<Json<SegmentPreview>, SegmentError> {
+    let expression = get_segment_expression(&app_state, segment_id).await?;
+    preview(&app_state, &expression).await.map(Json)
+}
+
+/// Loads a saved segment's expression.
+pub(crate) async fn get_segment_expression(
+    app_state: &AppState,
+    segment_id: Uuid,
+) -> Result<String, SegmentError> {
+    let res = sqlx::query!("SELECT expression FROM segments WHERE id = $1", segment_id)
+        .fetch_optional(app_state.pg_pool.as_ref())
+        .await
+        .context("error fetching segment")?
+        .ok_or(SegmentError::NotFound(segment_id))?;
+    Ok(res.expression)
+}
+
+async fn preview(app_state: &AppState, expression: &str) -> Result<SegmentPreview, SegmentError> {
+    let expr = segment::parse(expression)?;
+    let count = segment::count(&app_state.pg_pool, &expr)
+        .await
+        .context("error counting segment")?;
+    let sample = segment::recipients(&app_state.pg_pool, &expr, Some(PREVIEW_SAMPLE_SIZE))
+        .await
+        .context("error fetching segment sample")?;
+    Ok(SegmentPreview { count, sample })
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SegmentError {
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+
+    #[error("{0}")]
+    ValidationError(#[from] garde::Report),
+
+    #[error(transparent)]
+    ParseError(#[from] segment::ParseError),
+
+    #[error("segment already exists: {0}")]
+    Conflict(String),
+
+    #[error("segment not found: {0}")]
+    NotFound(Uuid),
+
+    #[error("segment {0} is the recipient set of an issue")]
+    InUse(Uuid),
+}
+
+impl IntoResponse for SegmentError {
+    fn into_response(self) -> Response {
+        match self {
+            SegmentError::UnexpectedError(e) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+            }
+            SegmentError::ValidationError(e) => {
+                (StatusCode::BAD_REQUEST, e.to_string()).into_response()
+            }
+            SegmentError::ParseError(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+            SegmentError::Conflict(_) | SegmentError::InUse(_) => {
+                (StatusCode::CONFLICT, self.to_string()).into_response()
+            }
+            SegmentError::NotFound(_) => (StatusCode::NOT_FOUND, self.to_string()).into_response(),
+        }
+    }
+}
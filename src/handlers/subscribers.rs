@@ -0,0 +1,347 @@
+use crate::AppState;
+use crate::authentication::AdminUser;
+use crate::consent::{self, ConsentEvent};
+use crate::segment::like_pattern;
+use anyhow::Context;
+use axum::Json;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use garde::Validate;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use sqlx::types::chrono::{DateTime, Utc};
+use tracing::instrument;
+use uuid::Uuid;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SubscriptionStatus {
+    Confirmed,
+    NotConfirmed,
+    Unsubscribed,
+}
+
+impl SubscriptionStatus {
+    pub fn as_str(&self) -> &str {
+        match self {
+            SubscriptionStatus::Confirmed => "confirmed",
+            SubscriptionStatus::NotConfirmed => "not-confirmed",
+            SubscriptionStatus::Unsubscribed => "unsubscribed",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ListParameters {
+    status: Option<SubscriptionStatus>,
+    /// only subscribers who subscribed at or after this time
+    from: Option<DateTime<Utc>>,
+    /// only subscribers who subscribed before this time
+    to: Option<DateTime<Utc>>,
+    /// case-insensitive substring of the email or the name
+    q: Option<String>,
+    limit: Option<i64>,
+    /// `next_cursor` of the previous page
+    cursor: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Subscriber {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+    digest_frequency: String,
+    attributes: serde_json::Value,
+    /// `None` for the default locale
+    locale: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SubscriberPage {
+    subscribers: Vec<Subscriber>,
+    /// pass as `cursor` to get the next page; absent on the last page
+    next_cursor: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Membership {
+    list_id: Uuid,
+    list_name: String,
+    status: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SubscriberDetail {
+    #[serde(flatten)]
+    subscriber: Subscriber,
+    tags: Vec<String>,
+    lists: Vec<Membership>,
+}
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct SubscriberUpdate {
+    #[garde(skip)]
+    status: Option<SubscriptionStatus>,
+    #[garde(length(min = 1), alphanumeric)]
+    name: Option<String>,
+}
+
+/// The position of the last row of a page, encoded as `<subscribed_at in µs>.<id>`.
+struct Cursor {
+    subscribed_at: DateTime<Utc>,
+    id: Uuid,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        format!("{}.{}", self.subscribed_at.timestamp_micros(), self.id)
+    }
+
+    fn decode(s: &str) -> Option<Self> {
+        let (micros, id) = s.split_once('.')?;
+        Some(Cursor {
+            subscribed_at: DateTime::from_timestamp_micros(micros.parse().ok()?)?,
+            id: Uuid::parse_str(id).ok()?,
+        })
+    }
+}
+
+#[instrument(name = "listing subscribers", skip(app_state))]
+pub async fn list_subscribers(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+    Query(params): Query<ListParameters>,
+) -> Result<Json<SubscriberPage>, SubscriberError> {
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let cursor = params
+        .cursor
+        .as_deref()
+        .map(|c| Cursor::decode(c).ok_or(SubscriberError::InvalidCursor))
+        .transpose()?;
+    let search = params.q.as_deref().map(like_pattern);
+
+    // one extra row tells whether there is a next page
+    let mut subscribers = sqlx::query_as!(
+        Subscriber,
+        r#"SELECT id, email, name, status, subscribed_at, digest_frequency, attributes, locale
+        FROM subscriptions
+        WHERE ($1::text IS NULL OR status = $1)
+            AND ($2::timestamptz IS NULL OR subscribed_at >= $2)
+            AND ($3::timestamptz IS NULL OR subscribed_at < $3)
+            AND ($4::text IS NULL OR email ILIKE $4 OR name ILIKE $4)
+            AND ($5::timestamptz IS NULL OR (subscribed_at, id) > ($5, $6))
+        ORDER BY subscribed_at, id
+        LIMIT $7"#,
+        params.status.as_ref().map(SubscriptionStatus::as_str),
+        params.from,
+        params.to,
+        search,
+        cursor.as_ref().map(|c| c.subscribed_at),
+        cursor.as_ref().map(|c| c.id),
+        limit + 1,
+    )
+    .fetch_all(app_state.pg_pool.as_ref())
+    .await
+    .context("error fetching subscribers")?;
+
+    let next_cursor = if subscribers.len() as i64 > limit {
+        subscribers.truncate(limit as usize);
+        subscribers.last().map(|s| {
+            Cursor {
+                subscribed_at: s.subscribed_at,
+                id: s.id,
+            }
+            .encode()
+        })
+    } else {
+        None
+    };
+    Ok(Json(SubscriberPage {
+        subscribers,
+        next_cursor,
+    }))
+}
+
+#[instrument(name = "fetching a subscriber", skip(app_state))]
+pub async fn get_subscriber(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+    Path(subscriber_id): Path<Uuid>,
+) -> Result<Json<SubscriberDetail>, SubscriberError> {
+    let detail = fetch_subscriber_detail(&app_state.pg_pool, subscriber_id).await?;
+    Ok(Json(detail))
+}
+
+#[instrument(name = "updating a subscriber", skip(app_state))]
+pub async fn update_subscriber(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+    Path(subscriber_id): Path<Uuid>,
+    Json(update): Json<SubscriberUpdate>,
+) -> Result<Json<SubscriberDetail>, SubscriberError> {
+    update.validate()?;
+    let mut transaction = app_state
+        .pg_pool
+        .begin()
+        .await
+        .context("error starting transaction")?;
+
+    let res = sqlx::query!(
+        r#"UPDATE subscriptions SET status = COALESCE($1, status), name = COALESCE($2, name)
+        WHERE id = $3"#,
+        update.status.as_ref().map(SubscriptionStatus::as_str),
+        update.name,
+        subscriber_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("error updating subscriber")?;
+    if res.rows_affected() == 0 {
+        return Err(SubscriberError::NotFound(subscriber_id));
+    }
+    if update.status == Some(SubscriptionStatus::Unsubscribed) {
+        sqlx::query!(
+            "UPDATE list_memberships SET status = 'unsubscribed' WHERE subscriber_uuid = $1",
+            subscriber_id
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("error unsubscribing list memberships")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("error commiting transaction")?;
+
+    let detail = fetch_subscriber_detail(&app_state.pg_pool, subscriber_id).await?;
+    Ok(Json(detail))
+}
+
+#[instrument(name = "deleting a subscriber", skip(app_state))]
+pub async fn delete_subscriber(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+    Path(subscriber_id): Path<Uuid>,
+) -> Result<StatusCode, SubscriberError> {
+    let mut transaction = app_state
+        .pg_pool
+        .begin()
+        .await
+        .context("error starting transaction")?;
+    sqlx::query!("DELETE FROM subscription_tokens WHERE subscriber_uuid = $1", subscriber_id)
+        .execute(&mut *transaction)
+        .await
+        .context("error deleting subscription tokens")?;
+    let res = sqlx::query!("DELETE FROM subscriptions WHERE id = $1", subscriber_id)
+        .execute(&mut *transaction)
+        .await
+        .context("error deleting subscriber")?;
+    if res.rows_affected() == 0 {
+        return Err(SubscriberError::NotFound(subscriber_id));
+    }
+    transaction
+        .commit()
+        .await
+        .context("error commiting transaction")?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The subscriber's consent history, as proof of opt-in.
+#[instrument(name = "fetching consent events", skip(app_state))]
+pub async fn get_consent_events(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+    Path(subscriber_id): Path<Uuid>,
+) -> Result<Json<Vec<ConsentEvent>>, SubscriberError> {
+    sqlx::query!("SELECT id FROM subscriptions WHERE id = $1", subscriber_id)
+        .fetch_optional(app_state.pg_pool.as_ref())
+        .await
+        .context("error fetching subscriber")?
+        .ok_or(SubscriberError::NotFound(subscriber_id))?;
+    let events = consent::events(app_state.pg_pool.as_ref(), subscriber_id)
+        .await
+        .context("error fetching consent events")?;
+    Ok(Json(events))
+}
+
+async fn fetch_subscriber_detail(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<SubscriberDetail, SubscriberError> {
+    let subscriber = sqlx::query_as!(
+        Subscriber,
+        r#"SELECT id, email, name, status, subscribed_at, digest_frequency, attributes, locale
+        FROM subscriptions WHERE id = $1"#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("error fetching subscriber")?
+    .ok_or(SubscriberError::NotFound(subscriber_id))?;
+    let tags = sqlx::query!(
+        "SELECT tag FROM subscriber_tags WHERE subscriber_uuid = $1 ORDER BY tag",
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("error fetching tags")?
+    .into_iter()
+    .map(|r| r.tag)
+    .collect();
+    let lists = sqlx::query_as!(
+        Membership,
+        r#"SELECT m.list_id, l.name AS list_name, m.status
+        FROM list_memberships m JOIN lists l ON l.id = m.list_id
+        WHERE m.subscriber_uuid = $1
+        ORDER BY l.name"#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("error fetching list memberships")?;
+    Ok(SubscriberDetail {
+        subscriber,
+        tags,
+        lists,
+    })
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SubscriberError {
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+
+    #[error("{0}")]
+    ValidationError(#[from] garde::Report),
+
+    #[error("invalid cursor")]
+    InvalidCursor,
+
+    #[error("subscriber not found: {0}")]
+    NotFound(Uuid),
+}
+
+impl IntoResponse for SubscriberError {
+    fn into_response(self) -> Response {
+        match self {
+            SubscriberError::UnexpectedError(e) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+            }
+            SubscriberError::ValidationError(e) => {
+                (StatusCode::BAD_REQUEST, e.to_string()).into_response()
+            }
+            SubscriberError::InvalidCursor => {
+                (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+            }
+            SubscriberError::NotFound(_) => {
+                (StatusCode::NOT_FOUND, self.to_string()).into_response()
+            }
+        }
+    }
+}
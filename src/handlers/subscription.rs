@@ -1,6 +1,10 @@
 use crate::AppState;
-use crate::email_client::EmailClient;
-use crate::errors::AppError;
+use crate::consent::{self, ClientInfo, ConsentDetails, ConsentEventKind};
+use crate::locale;
+use crate::signed_link::LinkAction;
+use crate::templates::ConfirmationEmail;
+use crate::token;
+use crate::validation::deserialize_json_object;
 use anyhow::Context;
 use axum::Form;
 use axum::extract::State;
@@ -9,16 +13,32 @@ use axum::response::{IntoResponse, Response};
 use garde::Validate;
 use serde::Deserialize;
 use sqlx::types::chrono::Utc;
-use sqlx::{Executor, Postgres, Transaction};
+use sqlx::Executor;
 use tracing::instrument;
 use uuid::Uuid;
 
+/// name of the list created by the lists migration. `/subscription` subscribes to this list.
+pub const DEFAULT_LIST_NAME: &str = "default";
+
 #[derive(Deserialize, Validate, Debug)]
 pub struct SubscriberInfo {
     #[garde(email)]
-    email: String,
+    pub(crate) email: String,
     #[garde(length(min = 1), alphanumeric)]
-    username: String,
+    pub(crate) username: String,
+    /// optional JSON object stored as a new subscriber's `attributes`
+    #[garde(skip)]
+    #[serde(default, deserialize_with = "deserialize_json_object")]
+    pub(crate) attributes: Option<serde_json::Map<String, serde_json::Value>>,
+    /// identifies the form or page the subscription came from
+    #[garde(length(max = 100))]
+    pub(crate) source: Option<String>,
+    /// version of the consent text shown next to the form
+    #[garde(length(max = 100))]
+    pub(crate) consent_version: Option<String>,
+    /// one of `application.locales`; negotiated from `Accept-Language` when omitted
+    #[garde(skip)]
+    pub(crate) locale: Option<String>,
 }
 
 #[instrument(
@@ -32,6 +52,7 @@ fields(
 )]
 pub async fn subscribe(
     State(app_state): State<AppState>,
+    client: ClientInfo,
     Form(form): Form<SubscriberInfo>,
 ) -> Result<StatusCode, SubscriptionError> {
     // create id to identify given request
@@ -43,11 +64,11 @@ pub async fn subscribe(
         .await
         .context("error starting transaction")?;
 
-    let subscriber_uuid = insert_subscriber(&mut transaction, &form)
-        .await.context("error registering subscriber")?;
+    let list_id = get_default_list_id(&mut transaction)
+        .await.context("error finding default list")?;
 
-    send_confirmation_email(&app_state, &mut transaction, &form, &subscriber_uuid)
-        .await.context("error sending confirmation email to client")?;
+    register_subscriber(&app_state, &mut transaction, &form, &client, list_id, "/subscription/confirm")
+        .await?;
 
     transaction.commit().await
         .context("error commiting transaction")?;
@@ -59,22 +80,124 @@ pub async fn subscribe(
     Ok(StatusCode::OK)
 }
 
-async fn insert_subscriber(
+/// Registers `form` as a pending member of `list_id`, records the consent given by `client`
+/// and mails a confirmation link pointing at `confirmation_path`.
+pub(crate) async fn register_subscriber(
+    app_state: &AppState,
     transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
     form: &SubscriberInfo,
-) -> anyhow::Result<Uuid> {
-    let subscriber_uuid = Uuid::new_v4();
-    let add_subscriber_query = sqlx::query!(
-        "insert into subscriptions (id, email, name, subscribed_at, status) values ($1, $2, $3, $4, $5)",
+    client: &ClientInfo,
+    list_id: Uuid,
+    confirmation_path: &str,
+) -> Result<(), SubscriptionError> {
+    let locale = subscriber_locale(app_state, form, client)?;
+    let (subscriber_uuid, locale) = insert_subscriber(transaction, form, locale)
+        .await.context("error registering subscriber")?;
+
+    insert_list_membership(transaction, list_id, subscriber_uuid)
+        .await.context("error registering list membership")?;
+
+    let details = ConsentDetails {
+        source: form.source.as_deref(),
+        consent_text_version: form.consent_version.as_deref(),
+    };
+    consent::record(
+        &mut **transaction,
+        subscriber_uuid,
+        Some(list_id),
+        ConsentEventKind::Subscribed,
+        client,
+        &details,
+    )
+    .await
+    .context("error recording consent")?;
+
+    send_confirmation_email(
+        app_state,
+        transaction,
+        form,
         &subscriber_uuid,
+        locale.as_deref(),
+        list_id,
+        confirmation_path,
+    )
+    .await
+    .context("error sending confirmation email to client")?;
+    Ok(())
+}
+
+/// The locale asked for in the form, else the one the client prefers, if it is supported.
+fn subscriber_locale(
+    app_state: &AppState,
+    form: &SubscriberInfo,
+    client: &ClientInfo,
+) -> Result<Option<String>, SubscriptionError> {
+    let settings = &app_state.conf.application;
+    if let Some(locale) = &form.locale {
+        return settings
+            .supported_locale(locale)
+            .map(|l| Some(l.to_string()))
+            .ok_or_else(|| SubscriptionError::UnsupportedLocale(locale.clone()));
+    }
+    Ok(client
+        .accept_language
+        .as_deref()
+        .and_then(|header| locale::negotiate(header, &settings.locales))
+        .map(str::to_string))
+}
+
+pub(crate) async fn get_default_list_id(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+) -> anyhow::Result<Uuid> {
+    let res = sqlx::query!("SELECT id FROM lists WHERE name = $1", DEFAULT_LIST_NAME)
+        .fetch_one(&mut **transaction)
+        .await?;
+    Ok(res.id)
+}
+
+/// Inserts a new subscriber, or finds the existing one with the same email, and returns
+/// their id and stored locale. The form can be submitted by anyone for any address, so an
+/// existing subscriber's attributes and locale are left as they are.
+async fn insert_subscriber(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    form: &SubscriberInfo,
+    locale: Option<String>,
+) -> anyhow::Result<(Uuid, Option<String>)> {
+    let res = sqlx::query!(
+        r#"insert into subscriptions (id, email, name, subscribed_at, status, attributes, locale) values ($1, $2, $3, $4, $5, $6, $7)
+        on conflict (email) do update set email = subscriptions.email
+        returning id, locale"#,
+        Uuid::new_v4(),
         form.email,
         form.username,
         Utc::now(),
-        "not-confirmed"
-    );
+        "not-confirmed",
+        serde_json::Value::Object(form.attributes.clone().unwrap_or_default()),
+        locale,
+    )
+    .fetch_one(&mut **transaction)
+    .await?;
+    Ok((res.id, res.locale))
+}
 
-    transaction.execute(add_subscriber_query).await?;
-    Ok(subscriber_uuid)
+/// Adds the subscriber to the list as pending. An already confirmed membership is left as is.
+async fn insert_list_membership(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    list_id: Uuid,
+    subscriber_uuid: Uuid,
+) -> anyhow::Result<()> {
+    let query = sqlx::query!(
+        r#"INSERT INTO list_memberships (list_id, subscriber_uuid, status, subscribed_at)
+        VALUES ($1, $2, 'not-confirmed', $3)
+        ON CONFLICT (list_id, subscriber_uuid) DO UPDATE
+        SET status = 'not-confirmed', subscribed_at = EXCLUDED.subscribed_at
+        WHERE list_memberships.status <> 'confirmed'"#,
+        list_id,
+        subscriber_uuid,
+        Utc::now(),
+    );
+    transaction.execute(query).await?;
+    Ok(())
 }
 
 pub async fn send_confirmation_email(
@@ -82,36 +205,33 @@ pub async fn send_confirmation_email(
     transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
     new_subscriber: &SubscriberInfo,
     subscriber_uuid: &Uuid,
+    locale: Option<&str>,
+    list_id: Uuid,
+    confirmation_path: &str,
 ) -> anyhow::Result<()> {
-    let confirmation_link = app_state.conf.application.host.clone();
-    // todo: uuidを生成してuserのuuidと紐づけ(7/14)
-    let uuid_token_for_confirmation = Uuid::new_v4();
+    let query = token::link_query(
+        &app_state.conf.links,
+        &mut **transaction,
+        LinkAction::Confirm,
+        *subscriber_uuid,
+        Some(list_id),
+    )
+    .await?;
+    let base_url = app_state.conf.application.base_url.trim_end_matches('/');
+    let confirmation_link = format!("{base_url}{confirmation_path}?{query}");
 
-    let insert_new_subscriber_query = sqlx::query!(
-        "INSERT INTO subscription_tokens (subscription_token, subscriber_uuid) VALUES ($1, $2)",
-        uuid_token_for_confirmation.to_string(),
-        subscriber_uuid,
-    );
-    let res = transaction
-        .execute(insert_new_subscriber_query)
-        .await
-        .map_err(|e| {
-            tracing::error!(
-                parent: &tracing::Span::current(),
-                error.kind="db registering subscription",
-            );
-            AppError::DbError(e.to_string())
-        })?;
-    let plain_body = format!(
-        "Welcome to our newsletter!\nVisit http://{confirmation_link}/subscription/confirm to confirm your subscription.",
-    );
-    let html_body = format!(
-        "Welcome to our newsletter!<br />\
-Click <a href=http://\"{confirmation_link}\">here</a> to confirm your subscription.",
-    );
+    let context = ConfirmationEmail {
+        name: &new_subscriber.username,
+        confirmation_link: &confirmation_link,
+    };
+    let locale = app_state.conf.application.locale_or_default(locale);
+    let email = app_state
+        .templates
+        .render_current(&mut **transaction, Some(locale), &context)
+        .await?;
     app_state
         .email_client
-        .send_email(&new_subscriber.email, "Welcome!", &html_body, &plain_body)
+        .send_email(&new_subscriber.email, &email.subject, &email.html, email.text.as_deref())
         .await
 }
 
@@ -121,7 +241,13 @@ pub enum SubscriptionError {
     UnexpectedError(#[from] anyhow::Error),
     
     #[error("{0}")]
-    ValidationError(#[from] garde::Report)
+    ValidationError(#[from] garde::Report),
+
+    #[error("list not found: {0}")]
+    ListNotFound(Uuid),
+
+    #[error("unsupported locale: {0}")]
+    UnsupportedLocale(String),
 }
 
 impl IntoResponse for SubscriptionError {
@@ -133,6 +259,12 @@ impl IntoResponse for SubscriptionError {
             SubscriptionError::ValidationError(e) => {
                 (StatusCode::BAD_REQUEST, e.to_string()).into_response()
             }
+            SubscriptionError::ListNotFound(_) => {
+                (StatusCode::NOT_FOUND, self.to_string()).into_response()
+            }
+            SubscriptionError::UnsupportedLocale(_) => {
+                (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+            }
         }
     }
 }
@@ -0,0 +1,153 @@
+use crate::AppState;
+use crate::authentication::AdminUser;
+use crate::validation::ValidatedTag;
+use anyhow::Context;
+use axum::Json;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::Utc;
+use tracing::instrument;
+use uuid::Uuid;
+
+#[derive(Serialize, Debug)]
+pub struct SubscriberTags {
+    subscriber_id: Uuid,
+    tags: Vec<String>,
+}
+
+/// Body of `POST /admin/tags/{tag}/bulk`. Subscribers can be given by id, by email or both.
+#[derive(Deserialize, Debug)]
+pub struct BulkTagRequest {
+    #[serde(default)]
+    subscriber_ids: Vec<Uuid>,
+    #[serde(default)]
+    emails: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BulkTagResult {
+    tag: String,
+    tagged: u64,
+}
+
+#[instrument(name = "listing subscriber tags", skip(app_state))]
+pub async fn get_tags(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+    Path(subscriber_id): Path<Uuid>,
+) -> Result<Json<SubscriberTags>, TagError> {
+    ensure_subscriber_exists(&app_state, subscriber_id).await?;
+    let tags = sqlx::query!(
+        "SELECT tag FROM subscriber_tags WHERE subscriber_uuid = $1 ORDER BY tag",
+        subscriber_id
+    )
+    .fetch_all(app_state.pg_pool.as_ref())
+    .await
+    .context("error fetching tags")?
+    .into_iter()
+    .map(|r| r.tag)
+    .collect();
+    Ok(Json(SubscriberTags { subscriber_id, tags }))
+}
+
+#[instrument(name = "tagging a subscriber", skip(app_state))]
+pub async fn add_tag(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+    Path((subscriber_id, tag)): Path<(Uuid, String)>,
+) -> Result<StatusCode, TagError> {
+    let tag = ValidatedTag::parse(&tag).map_err(TagError::InvalidTag)?;
+    ensure_subscriber_exists(&app_state, subscriber_id).await?;
+    sqlx::query!(
+        r#"INSERT INTO subscriber_tags (subscriber_uuid, tag, created_at) VALUES ($1, $2, $3)
+        ON CONFLICT DO NOTHING"#,
+        subscriber_id,
+        tag.as_str(),
+        Utc::now(),
+    )
+    .execute(app_state.pg_pool.as_ref())
+    .await
+    .context("error inserting tag")?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[instrument(name = "untagging a subscriber", skip(app_state))]
+pub async fn remove_tag(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+    Path((subscriber_id, tag)): Path<(Uuid, String)>,
+) -> Result<StatusCode, TagError> {
+    ensure_subscriber_exists(&app_state, subscriber_id).await?;
+    sqlx::query!(
+        "DELETE FROM subscriber_tags WHERE subscriber_uuid = $1 AND tag = $2",
+        subscriber_id,
+        tag,
+    )
+    .execute(app_state.pg_pool.as_ref())
+    .await
+    .context("error deleting tag")?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[instrument(name = "bulk tagging subscribers", skip(app_state, request))]
+pub async fn bulk_add_tag(
+    State(app_state): State<AppState>,
+    admin: AdminUser,
+    Path(tag): Path<String>,
+    Json(request): Json<BulkTagRequest>,
+) -> Result<Json<BulkTagResult>, TagError> {
+    let tag = ValidatedTag::parse(&tag).map_err(TagError::InvalidTag)?;
+    let res = sqlx::query!(
+        r#"INSERT INTO subscriber_tags (subscriber_uuid, tag, created_at)
+        SELECT id, $1, $2 FROM subscriptions WHERE id = ANY($3) OR email = ANY($4)
+        ON CONFLICT DO NOTHING"#,
+        tag.as_str(),
+        Utc::now(),
+        &request.subscriber_ids,
+        &request.emails,
+    )
+    .execute(app_state.pg_pool.as_ref())
+    .await
+    .context("error inserting tags")?;
+    Ok(Json(BulkTagResult {
+        tag: tag.0,
+        tagged: res.rows_affected(),
+    }))
+}
+
+async fn ensure_subscriber_exists(app_state: &AppState, subscriber_id: Uuid) -> Result<(), TagError> {
+    sqlx::query!("SELECT id FROM subscriptions WHERE id = $1", subscriber_id)
+        .fetch_optional(app_state.pg_pool.as_ref())
+        .await
+        .context("error finding subscriber")?
+        .ok_or(TagError::SubscriberNotFound(subscriber_id))?;
+    Ok(())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TagError {
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+
+    #[error("invalid tag: {0}")]
+    InvalidTag(anyhow::Error),
+
+    #[error("subscriber not found: {0}")]
+    SubscriberNotFound(Uuid),
+}
+
+impl IntoResponse for TagError {
+    fn into_response(self) -> Response {
+        match self {
+            TagError::UnexpectedError(e) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+            }
+            TagError::InvalidTag(_) => (StatusCode::BAD_REQUEST, self.to_string()).into_response(),
+            TagError::SubscriberNotFound(_) => {
+                (StatusCode::NOT_FOUND, self.to_string()).into_response()
+            }
+        }
+    }
+}
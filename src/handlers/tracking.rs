@@ -0,0 +1,93 @@
+use crate::AppState;
+use crate::consent::ClientInfo;
+use crate::tracking;
+use axum::extract::{Path, State};
+use axum::http::{StatusCode, header};
+use axum::response::{IntoResponse, Redirect, Response};
+use tracing::instrument;
+
+/// `GET /t/o/{token}.gif`: records an open of the delivery named by the token and returns
+/// the pixel. Nothing is recorded while `tracking.opens` is off.
+#[instrument(name = "recording an open", skip(app_state))]
+pub async fn open_pixel(
+    State(app_state): State<AppState>,
+    Path(file): Path<String>,
+    client: ClientInfo,
+) -> Result<Response, TrackingError> {
+    let token = file.strip_suffix(".gif").ok_or(TrackingError::NotFound)?;
+    let delivery_id = tracking::verify_open_token(&app_state.conf.links, token)
+        .map_err(|_| TrackingError::NotFound)?;
+    if app_state.conf.tracking.opens {
+        let delivery = tracking::find_delivery(&app_state.pg_pool, delivery_id)
+            .await?
+            .ok_or(TrackingError::NotFound)?;
+        tracking::record_open(
+            &app_state.pg_pool,
+            &delivery,
+            client.user_agent.as_deref(),
+            client.ip_address.as_deref(),
+        )
+        .await?;
+    }
+    Ok((
+        [
+            (header::CONTENT_TYPE, "image/gif"),
+            (header::CACHE_CONTROL, "no-store, no-cache, must-revalidate, private"),
+        ],
+        tracking::PIXEL,
+    )
+        .into_response())
+}
+
+/// `GET /t/c/{token}`: records a click of the link named by the token and redirects to it.
+/// The link keeps working for deliveries that no longer exist or while `tracking.clicks` is
+/// off, since the destination is signed.
+#[instrument(name = "recording a click", skip(app_state))]
+pub async fn click(
+    State(app_state): State<AppState>,
+    Path(token): Path<String>,
+    client: ClientInfo,
+) -> Result<Redirect, TrackingError> {
+    let (delivery_id, url) = tracking::verify_click_token(&app_state.conf.links, &token)
+        .map_err(|_| TrackingError::NotFound)?;
+    let tracking_conf = &app_state.conf.tracking;
+    let delivery = tracking::find_delivery(&app_state.pg_pool, delivery_id).await?;
+    let Some(delivery) = delivery else {
+        return Ok(Redirect::to(&url));
+    };
+    if tracking_conf.clicks {
+        tracking::record_click(
+            &app_state.pg_pool,
+            &delivery,
+            &url,
+            client.user_agent.as_deref(),
+            client.ip_address.as_deref(),
+        )
+        .await?;
+    }
+    let destination = match &tracking_conf.utm {
+        Some(utm) => tracking::with_utm(&url, utm, delivery.issue_id),
+        None => url,
+    };
+    Ok(Redirect::to(&destination))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TrackingError {
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+
+    #[error("not found")]
+    NotFound,
+}
+
+impl IntoResponse for TrackingError {
+    fn into_response(self) -> Response {
+        match self {
+            TrackingError::UnexpectedError(e) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+            }
+            TrackingError::NotFound => (StatusCode::NOT_FOUND, self.to_string()).into_response(),
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use crate::AppState;
+use crate::delivery::DELIVERY_METADATA;
+use crate::stats::{self, Report};
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Deserialize;
+use std::collections::HashMap;
+use subtle::ConstantTimeEq;
+use tracing::instrument;
+use uuid::Uuid;
+
+/// An event the email API posts about an email it sent.
+#[derive(Deserialize, Debug)]
+pub struct EmailEvent {
+    /// `Bounce` and `SpamComplaint` are recorded, other events are ignored
+    record_type: String,
+    /// as given when sending, see [`crate::delivery::DELIVERY_METADATA`]
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+}
+
+/// `POST /webhooks/email`: records bounces and spam complaints of issue deliveries. Events
+/// about other emails or deliveries that no longer exist are acknowledged without effect, so
+/// the email API does not retry them.
+#[instrument(name = "receiving an email event", skip(app_state, headers))]
+pub async fn email_event(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    Json(event): Json<EmailEvent>,
+) -> Result<StatusCode, WebhookError> {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    let expected = app_state.conf.email_client.webhook_token.as_bytes();
+    if !token.is_some_and(|token| token.trim().as_bytes().ct_eq(expected).into()) {
+        return Err(WebhookError::Unauthorized);
+    }
+    let report = match event.record_type.as_str() {
+        "Bounce" => Report::Bounce,
+        "SpamComplaint" => Report::Complaint,
+        _ => return Ok(StatusCode::NO_CONTENT),
+    };
+    let Some(delivery_id) = event
+        .metadata
+        .get(DELIVERY_METADATA)
+        .and_then(|id| Uuid::parse_str(id).ok())
+    else {
+        return Ok(StatusCode::NO_CONTENT);
+    };
+    if !stats::record_report(&app_state.pg_pool, delivery_id, report).await? {
+        tracing::warn!(%delivery_id, "email event for an unknown delivery");
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum WebhookError {
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+
+    #[error("invalid webhook token")]
+    Unauthorized,
+}
+
+impl IntoResponse for WebhookError {
+    fn into_response(self) -> Response {
+        match self {
+            WebhookError::UnexpectedError(e) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+            }
+            WebhookError::Unauthorized => (StatusCode::UNAUTHORIZED, self.to_string()).into_response(),
+        }
+    }
+}
@@ -0,0 +1,197 @@
+//! Preparing HTML bodies for mail clients.
+//!
+//! Many clients drop `<style>` blocks, so [`HtmlProcessor::process`] copies their rules into
+//! `style` attributes, then sanitizes the result: scripts, forms, embedded media and other
+//! tags mail clients do not support are removed, and relative URLs are made absolute against
+//! `application.base_url`. The body is put back into a minimal document.
+//!
+//! Constructs that survive but are known to render badly in Outlook or Gmail are reported as
+//! warnings rather than rejected, since the email is still readable.
+
+use ammonia::{Url, UrlRelative};
+use css_inline::CSSInliner;
+use scraper::{Html, Selector};
+
+/// Tags removed with their content, reported since the author likely expected them to work.
+const REMOVED_TAGS: [&str; 13] = [
+    "script", "iframe", "object", "embed", "form", "input", "button", "select", "textarea",
+    "video", "audio", "svg", "canvas",
+];
+
+/// Gmail clips messages larger than this and hides the rest behind a link.
+const GMAIL_CLIP_BYTES: usize = 102 * 1024;
+
+#[derive(Debug, Clone, Default)]
+pub struct HtmlProcessor {
+    /// relative URLs are kept as they are without one
+    base_url: Option<Url>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessedHtml {
+    pub html: String,
+    pub warnings: Vec<String>,
+}
+
+impl HtmlProcessor {
+    pub fn new(base_url: &str) -> anyhow::Result<Self> {
+        let base_url = Url::parse(base_url)?;
+        Ok(HtmlProcessor {
+            base_url: Some(base_url),
+        })
+    }
+
+    pub fn process(&self, html: &str) -> ProcessedHtml {
+        let mut warnings = Vec::new();
+        check_source(html, &mut warnings);
+
+        let inliner = CSSInliner::options()
+            .keep_style_tags(false)
+            .keep_link_tags(false)
+            .load_remote_stylesheets(false)
+            .build();
+        let inlined = match inliner.inline(html) {
+            Ok(inlined) => inlined,
+            Err(e) => {
+                warn(&mut warnings, format!("CSS could not be inlined: {e}"));
+                html.to_string()
+            }
+        };
+
+        let url_relative = match &self.base_url {
+            Some(base_url) => UrlRelative::RewriteWithBase(base_url.clone()),
+            None => UrlRelative::PassThrough,
+        };
+        let body = ammonia::Builder::default()
+            .add_generic_attributes(["style", "align", "valign", "width", "height", "bgcolor", "dir"])
+            .add_tag_attributes("table", ["border", "cellpadding", "cellspacing"])
+            .add_clean_content_tags(REMOVED_TAGS)
+            .add_clean_content_tags(["title"])
+            .url_relative(url_relative)
+            .clean(&inlined)
+            .to_string();
+        let html = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+            <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+            </head>\n<body>\n{}\n</body>\n</html>\n",
+            body.trim()
+        );
+
+        check_output(&html, &mut warnings);
+        ProcessedHtml { html, warnings }
+    }
+}
+
+/// Warnings about parts of the input that processing removes.
+fn check_source(html: &str, warnings: &mut Vec<String>) {
+    let document = Html::parse_document(html);
+    for tag in REMOVED_TAGS {
+        let selector = Selector::parse(tag).expect("a tag name is a valid selector");
+        if document.select(&selector).next().is_some() {
+            warn(warnings, format!("<{tag}> is not supported in email and was removed"));
+        }
+    }
+    let styles = Selector::parse("style").expect("a valid selector");
+    for style in document.select(&styles) {
+        let css = style.text().collect::<String>();
+        for at_rule in ["@media", "@font-face", "@import"] {
+            if css.contains(at_rule) {
+                warn(warnings, format!("{at_rule} rules cannot be inlined and were removed"));
+            }
+        }
+    }
+    let stylesheets = Selector::parse("link[rel~=stylesheet]").expect("a valid selector");
+    if document.select(&stylesheets).next().is_some() {
+        warn(warnings, "external stylesheets are not loaded".to_string());
+    }
+}
+
+/// Warnings about constructs that are sent but render badly in common clients.
+fn check_output(html: &str, warnings: &mut Vec<String>) {
+    if html.len() > GMAIL_CLIP_BYTES {
+        warn(warnings, format!("Gmail clips messages larger than {}KB", GMAIL_CLIP_BYTES / 1024));
+    }
+    let document = Html::parse_document(html);
+    let styled = Selector::parse("[style]").expect("a valid selector");
+    for element in document.select(&styled) {
+        let style = element.attr("style").unwrap_or_default();
+        for (property, value) in style.split(';').filter_map(|d| d.split_once(':')) {
+            let (property, value) = (property.trim().to_lowercase(), value.trim().to_lowercase());
+            let warning = match property.as_str() {
+                "display" if ["flex", "inline-flex", "grid", "inline-grid"].contains(&value.as_str()) => {
+                    format!("display: {value} is ignored by Outlook")
+                }
+                "position" => "position is removed by Gmail".to_string(),
+                "float" => "float is ignored by Outlook".to_string(),
+                "background-image" => "CSS background images are not shown by Outlook".to_string(),
+                "background" if value.contains("url(") => {
+                    "CSS background images are not shown by Outlook".to_string()
+                }
+                _ => continue,
+            };
+            warn(warnings, warning);
+        }
+    }
+    let images = Selector::parse("img:not([width])").expect("a valid selector");
+    if document.select(&images).next().is_some() {
+        warn(
+            warnings,
+            "images without a width attribute are shown at full size by Outlook".to_string(),
+        );
+    }
+}
+
+fn warn(warnings: &mut Vec<String>, warning: String) {
+    if !warnings.contains(&warning) {
+        warnings.push(warning);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn styles_are_inlined_and_unsafe_tags_removed() {
+        let processor = HtmlProcessor::new("https://news.example.com/app/").unwrap();
+        let processed = processor.process(
+            r#"<html><head><title>t</title><style>p { color: red }</style></head><body>
+            <p>Hi</p><script>alert(1)</script><form><input></form>
+            <a href="/archive">archive</a> <img src="logo.png" width="100" alt="logo">
+            </body></html>"#,
+        );
+        assert!(processed.html.contains(r#"<p style="color: red;">Hi</p>"#), "{}", processed.html);
+        assert!(!processed.html.contains("<style"));
+        assert!(!processed.html.contains("script"));
+        assert!(!processed.html.contains("<form"));
+        assert!(!processed.html.contains("<title"));
+        assert!(processed.html.contains(r#"href="https://news.example.com/archive""#), "{}", processed.html);
+        assert!(processed.html.contains(r#"src="https://news.example.com/app/logo.png""#));
+        assert_eq!(
+            processed.warnings,
+            [
+                "<script> is not supported in email and was removed",
+                "<form> is not supported in email and was removed",
+                "<input> is not supported in email and was removed",
+            ]
+        );
+    }
+
+    #[test]
+    fn constructs_breaking_in_outlook_or_gmail_are_reported() {
+        let processed = HtmlProcessor::default().process(
+            r#"<style>@media (max-width: 600px) { p { color: blue } } .row { display: flex }</style>
+            <div class="row" style="background: url(bg.png)"><img src="a.png"></div>"#,
+        );
+        assert_eq!(
+            processed.warnings,
+            [
+                "@media rules cannot be inlined and were removed",
+                "display: flex is ignored by Outlook",
+                "CSS background images are not shown by Outlook",
+                "images without a width attribute are shown at full size by Outlook",
+            ]
+        );
+        assert!(processed.html.contains(r#"src="a.png""#));
+    }
+}
@@ -0,0 +1,402 @@
+//! Bulk import of subscribers from CSV.
+//!
+//! The input needs a header row with `email` and `name` columns; any other column is stored
+//! as a string in the subscriber's `attributes`. Rows are validated one at a time and upserted
+//! in batches, so memory use does not grow with the size of the file.
+//!
+//! Existing subscribers keep their status when it is `confirmed` or `unsubscribed`, so an import
+//! never resubscribes someone who opted out. Members left pending by an import are queued in
+//! `pending_confirmations`, and the scheduler mails their confirmation links in batches, see
+//! [`send_queued_confirmations`].
+//! Addresses erased through [`crate::gdpr::erase`] are rejected.
+
+use crate::AppState;
+use crate::email_client;
+use crate::gdpr;
+use crate::handlers::subscription::{SubscriberInfo, send_confirmation_email};
+use crate::validation::{ValidatedEmail, ValidatedName};
+use anyhow::Context;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use sqlx::types::chrono::Utc;
+use std::collections::HashMap;
+use tokio::io::AsyncRead;
+use uuid::Uuid;
+
+const BATCH_SIZE: usize = 500;
+/// rows failing beyond this are only counted, keeping the report bounded
+const MAX_REPORTED_ERRORS: usize = 1000;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportStatus {
+    /// subscribers are stored as `confirmed`
+    Confirmed,
+    /// subscribers are stored as `not-confirmed` and mailed a confirmation link to opt in
+    Pending,
+}
+
+impl ImportStatus {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ImportStatus::Confirmed => "confirmed",
+            ImportStatus::Pending => "not-confirmed",
+        }
+    }
+}
+
+impl TryFrom<&str> for ImportStatus {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "confirmed" => Ok(Self::Confirmed),
+            "pending" => Ok(Self::Pending),
+            _ => Err(anyhow::anyhow!("unknown import status: {value}, use `confirmed` or `pending`")),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct ImportReport {
+    pub imported: u64,
+    pub failed: u64,
+    /// the first `MAX_REPORTED_ERRORS` failures
+    pub errors: Vec<RowError>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RowError {
+    pub line: u64,
+    pub email: Option<String>,
+    pub error: String,
+}
+
+impl ImportReport {
+    fn reject(&mut self, line: u64, email: Option<String>, error: String) {
+        self.failed += 1;
+        if self.errors.len() < MAX_REPORTED_ERRORS {
+            self.errors.push(RowError { line, email, error });
+        }
+    }
+}
+
+struct Row {
+    line: u64,
+    email: String,
+    name: String,
+    attributes: serde_json::Value,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ImportError {
+    #[error("invalid CSV: {0}")]
+    InvalidInput(String),
+
+    #[error("list not found: {0}")]
+    ListNotFound(Uuid),
+
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl From<sqlx::Error> for ImportError {
+    fn from(e: sqlx::Error) -> Self {
+        ImportError::UnexpectedError(e.into())
+    }
+}
+
+impl From<csv_async::Error> for ImportError {
+    fn from(e: csv_async::Error) -> Self {
+        ImportError::InvalidInput(e.to_string())
+    }
+}
+
+impl IntoResponse for ImportError {
+    fn into_response(self) -> Response {
+        match self {
+            ImportError::InvalidInput(_) => (StatusCode::BAD_REQUEST, self.to_string()).into_response(),
+            ImportError::ListNotFound(_) => (StatusCode::NOT_FOUND, self.to_string()).into_response(),
+            ImportError::UnexpectedError(e) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+            }
+        }
+    }
+}
+
+/// Imports subscribers from `reader` into `list_id` (the default list when `None`).
+///
+/// Invalid rows are reported and skipped; an error is only returned when the input
+/// cannot be read at all or the database fails.
+pub async fn import_subscribers<R>(
+    pool: &PgPool,
+    reader: R,
+    status: ImportStatus,
+    list_id: Option<Uuid>,
+) -> Result<ImportReport, ImportError>
+where
+    R: AsyncRead + Unpin + Send,
+{
+    let list_id = match list_id {
+        Some(list_id) => sqlx::query!("SELECT id FROM lists WHERE id = $1", list_id)
+            .fetch_optional(pool)
+            .await?
+            .ok_or(ImportError::ListNotFound(list_id))?
+            .id,
+        None => {
+            let mut transaction = pool.begin().await?;
+            let id = crate::handlers::subscription::get_default_list_id(&mut transaction).await?;
+            transaction.commit().await?;
+            id
+        }
+    };
+
+    let mut csv = csv_async::AsyncReaderBuilder::new()
+        .trim(csv_async::Trim::All)
+        .create_reader(reader);
+    let headers = csv.headers().await?.clone();
+    let position = |column: &str| headers.iter().position(|h| h.eq_ignore_ascii_case(column));
+    let (Some(email_idx), Some(name_idx)) = (position("email"), position("name")) else {
+        return Err(ImportError::InvalidInput(
+            "the header row needs `email` and `name` columns".to_string(),
+        ));
+    };
+
+    let mut report = ImportReport::default();
+    // keyed by email so a repeated address within one batch does not hit the same row twice;
+    // the last occurrence wins and the earlier ones are reported
+    let mut batch: HashMap<String, Row> = HashMap::with_capacity(BATCH_SIZE);
+    let mut records = csv.records();
+    let mut line = 1;
+    while let Some(record) = records.next().await {
+        line += 1;
+        let record = match record {
+            Ok(record) => record,
+            Err(e) if e.is_io_error() => return Err(ImportError::InvalidInput(e.to_string())),
+            Err(e) => {
+                report.reject(line, None, e.to_string());
+                continue;
+            }
+        };
+        if let Some(position) = record.position() {
+            line = position.line();
+        }
+        let email = record.get(email_idx).unwrap_or_default();
+        let name = record.get(name_idx).unwrap_or_default();
+        if let Err(e) = ValidatedEmail::parse(email) {
+            let error = format!("invalid email: {}", e.to_string().trim_end());
+            report.reject(line, Some(email.to_string()), error);
+            continue;
+        }
+        if let Err(e) = ValidatedName::parse(name) {
+            let error = format!("invalid name: {}", e.to_string().trim_end());
+            report.reject(line, Some(email.to_string()), error);
+            continue;
+        }
+        let attributes = headers
+            .iter()
+            .zip(record.iter())
+            .enumerate()
+            .filter(|(i, (_, value))| *i != email_idx && *i != name_idx && !value.is_empty())
+            .map(|(_, (key, value))| (key.to_string(), serde_json::Value::from(value)))
+            .collect();
+        let row = Row {
+            line,
+            email: email.to_string(),
+            name: name.to_string(),
+            attributes: serde_json::Value::Object(attributes),
+        };
+        if let Some(earlier) = batch.insert(email.to_string(), row) {
+            report.reject(earlier.line, Some(earlier.email), format!("duplicate of row {line}"));
+        }
+        if batch.len() >= BATCH_SIZE {
+            reject_erased(pool, &mut batch, &mut report).await?;
+            report.imported += upsert_batch(pool, batch.drain().map(|(_, r)| r), status, list_id).await?;
+        }
+    }
+    reject_erased(pool, &mut batch, &mut report).await?;
+    if !batch.is_empty() {
+        report.imported += upsert_batch(pool, batch.drain().map(|(_, r)| r), status, list_id).await?;
+    }
+    Ok(report)
+}
+
+/// Moves rows of erased subscribers from `batch` to the report's failures.
+async fn reject_erased(
+    pool: &PgPool,
+    batch: &mut HashMap<String, Row>,
+    report: &mut ImportReport,
+) -> anyhow::Result<()> {
+    let erased: Vec<String> = gdpr::erased_emails(pool, batch.keys().map(String::as_str))
+        .await?
+        .into_iter()
+        .map(str::to_string)
+        .collect();
+    for email in erased {
+        if let Some(row) = batch.remove(&email) {
+            report.reject(row.line, Some(email), "the subscriber was erased on request".to_string());
+        }
+    }
+    Ok(())
+}
+
+async fn upsert_batch(
+    pool: &PgPool,
+    rows: impl Iterator<Item = Row>,
+    status: ImportStatus,
+    list_id: Uuid,
+) -> anyhow::Result<u64> {
+    let (mut ids, mut emails, mut names, mut attributes) = (vec![], vec![], vec![], vec![]);
+    for row in rows {
+        ids.push(Uuid::new_v4());
+        emails.push(row.email);
+        names.push(row.name);
+        attributes.push(row.attributes);
+    }
+    let now = Utc::now();
+
+    let mut transaction = pool.begin().await?;
+    let upserted = sqlx::query!(
+        r#"INSERT INTO subscriptions (id, email, name, subscribed_at, status, attributes)
+        SELECT id, email, name, $5, $6, attributes
+        FROM UNNEST($1::uuid[], $2::text[], $3::text[], $4::jsonb[]) AS t(id, email, name, attributes)
+        ON CONFLICT (email) DO UPDATE SET
+            name = EXCLUDED.name,
+            attributes = subscriptions.attributes || EXCLUDED.attributes,
+            status = CASE WHEN subscriptions.status IN ('confirmed', 'unsubscribed')
+                THEN subscriptions.status ELSE EXCLUDED.status END
+        RETURNING id, status"#,
+        &ids,
+        &emails,
+        &names,
+        &attributes,
+        now,
+        status.as_str(),
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+
+    let (ids, statuses): (Vec<Uuid>, Vec<String>) =
+        upserted.into_iter().map(|r| (r.id, r.status)).unzip();
+    let pending: Vec<Uuid> = sqlx::query!(
+        r#"INSERT INTO list_memberships (list_id, subscriber_uuid, status, subscribed_at)
+        SELECT $1, id, status, $4 FROM UNNEST($2::uuid[], $3::text[]) AS t(id, status)
+        ON CONFLICT (list_id, subscriber_uuid) DO UPDATE SET
+            status = CASE WHEN list_memberships.status IN ('confirmed', 'unsubscribed')
+                THEN list_memberships.status ELSE EXCLUDED.status END
+        RETURNING subscriber_uuid, status"#,
+        list_id,
+        &ids,
+        &statuses,
+        now,
+    )
+    .fetch_all(&mut *transaction)
+    .await?
+    .into_iter()
+    .filter(|r| r.status == "not-confirmed")
+    .map(|r| r.subscriber_uuid)
+    .collect();
+    sqlx::query!(
+        r#"INSERT INTO pending_confirmations (subscriber_uuid, list_id, queued_at)
+        SELECT id, $1, $3 FROM UNNEST($2::uuid[]) AS t(id)
+        ON CONFLICT (subscriber_uuid, list_id) DO NOTHING"#,
+        list_id,
+        &pending,
+        now,
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+
+    Ok(ids.len() as u64)
+}
+
+/// Mails the confirmation links of up to `limit` queued imported members, oldest first, and
+/// returns how many were mailed. Members who confirmed or unsubscribed meanwhile are dropped
+/// from the queue unmailed. A member whose email fails for a transient reason stays queued and
+/// is tried again after a backoff, up to `scheduler.max_attempts` times; other failures drop
+/// them from the queue, so one bad address never holds up the rest.
+pub async fn send_queued_confirmations(app_state: &AppState, limit: usize) -> anyhow::Result<usize> {
+    let pool = app_state.pg_pool.as_ref();
+    let settings = &app_state.conf.scheduler;
+    let mut sent = 0;
+    for _ in 0..limit {
+        let mut transaction = pool.begin().await.context("error starting transaction")?;
+        let Some(p) = sqlx::query!(
+            r#"SELECT p.subscriber_uuid, p.list_id, p.attempts, s.email, s.name, s.locale,
+                COALESCE(m.status = 'not-confirmed', false) AS "pending!"
+            FROM pending_confirmations p
+            JOIN subscriptions s ON s.id = p.subscriber_uuid
+            LEFT JOIN list_memberships m
+                ON m.subscriber_uuid = p.subscriber_uuid AND m.list_id = p.list_id
+            WHERE p.next_attempt_at IS NULL OR p.next_attempt_at <= now()
+            ORDER BY p.queued_at
+            LIMIT 1
+            FOR UPDATE OF p SKIP LOCKED"#
+        )
+        .fetch_optional(&mut *transaction)
+        .await
+        .context("error claiming a queued confirmation")?
+        else {
+            break;
+        };
+
+        if p.pending {
+            let subscriber = SubscriberInfo {
+                email: p.email,
+                username: p.name,
+                attributes: None,
+                source: None,
+                consent_version: None,
+                locale: None,
+            };
+            let result = send_confirmation_email(
+                app_state,
+                &mut transaction,
+                &subscriber,
+                &p.subscriber_uuid,
+                p.locale.as_deref(),
+                p.list_id,
+                &format!("/lists/{}/subscription/confirm", p.list_id),
+            )
+            .await;
+            let attempts = p.attempts + 1;
+            match result {
+                Ok(()) => sent += 1,
+                Err(e) if email_client::is_transient(&e) && attempts < settings.max_attempts => {
+                    tracing::warn!(error = ?e, subscriber_uuid = %p.subscriber_uuid, attempts, "error sending confirmation email, retrying later");
+                    sqlx::query!(
+                        r#"UPDATE pending_confirmations SET attempts = $3, next_attempt_at = $4, last_error = $5
+                        WHERE subscriber_uuid = $1 AND list_id = $2"#,
+                        p.subscriber_uuid,
+                        p.list_id,
+                        attempts,
+                        Utc::now() + settings.retry_backoff(attempts),
+                        format!("{e:#}"),
+                    )
+                    .execute(&mut *transaction)
+                    .await
+                    .context("error recording a failed confirmation")?;
+                    transaction.commit().await.context("error commiting transaction")?;
+                    continue;
+                }
+                Err(e) => {
+                    tracing::error!(error = ?e, subscriber_uuid = %p.subscriber_uuid, attempts, "error sending confirmation email, giving up");
+                }
+            }
+        }
+        sqlx::query!(
+            "DELETE FROM pending_confirmations WHERE subscriber_uuid = $1 AND list_id = $2",
+            p.subscriber_uuid,
+            p.list_id,
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("error dequeuing confirmation")?;
+        transaction.commit().await.context("error commiting transaction")?;
+    }
+    Ok(sent)
+}
@@ -1,19 +1,41 @@
+pub mod ab_test;
+pub mod authentication;
+pub mod cleanup;
+pub mod cli;
 pub mod configuration;
+pub mod consent;
+pub mod content;
+pub mod delivery;
 pub mod email_client;
 pub mod errors;
+pub mod gdpr;
 pub mod handlers;
-mod telemetry;
+pub mod html_email;
+pub mod import;
+pub mod locale;
+pub mod merge_tags;
+pub mod plain_text;
+pub mod scheduler;
+pub mod segment;
+pub mod signed_link;
+pub mod stats;
+pub mod telemetry;
+pub mod templates;
+pub mod token;
+pub mod tracking;
 pub mod validation;
 
 use crate::configuration::{get_configuration, Settings};
 use crate::email_client::EmailClient;
 use crate::errors::AppError;
+use crate::html_email::HtmlProcessor;
+use crate::templates::EmailTemplates;
 use axum::Router;
 use axum::body::Bytes;
 use axum::extract::{ConnectInfo, MatchedPath};
 use axum::http::{HeaderMap, Request};
 use axum::response::Response;
-use axum::routing::{get, post};
+use axum::routing::{delete, get, post, put};
 use sqlx::postgres::PgPoolOptions;
 use sqlx::{Connection, PgPool};
 use std::net::SocketAddr;
@@ -36,7 +58,9 @@ pub async fn run() -> Result<(), AppError> {
         &conf.email_client.email_server_url,
         my_domain_email,
         &conf.email_client.authorization_token,
-        timeout
+        timeout,
+        HtmlProcessor::new(&conf.application.base_url)
+            .map_err(|e| AppError::ConfigError(format!("invalid application.base_url: {e}")))?,
     );
 
     let pool = PgPoolOptions::new().connect_lazy_with(conf.database.connection_options());
@@ -53,11 +77,24 @@ pub async fn run() -> Result<(), AppError> {
         .await
         .expect("error establishing db connection");
 
+    let templates = EmailTemplates::load(&conf.application.templates_dir, &conf.application.locales)
+        .map_err(|e| AppError::ConfigError(e.to_string()))?;
+
     let app_state = AppState {
         pg_pool: Arc::new(pool),
         email_client: Arc::new(email_client),
+        templates: Arc::new(templates),
         conf: Arc::new(conf),
     };
+    tokio::spawn(cleanup::run_pending_cleanup(
+        app_state.pg_pool.clone(),
+        app_state.conf.cleanup.pending_ttl(),
+        app_state.conf.cleanup.interval(),
+    ));
+    tokio::spawn(scheduler::run_scheduler(
+        app_state.clone(),
+        app_state.conf.scheduler.interval(),
+    ));
     let app = app_internal(app_state);
 
     let listener = tokio::net::TcpListener::bind(addr)
@@ -89,6 +126,7 @@ fn init_tracing() {
 pub struct AppState {
     pub pg_pool: Arc<PgPool>,
     pub email_client: Arc<EmailClient>,
+    pub templates: Arc<EmailTemplates>,
     pub conf: Arc<Settings>
 }
 
@@ -98,6 +136,100 @@ pub fn app_internal(app_state: AppState) -> Router {
         .route("/health/{name}", get(handlers::health_check::health))
         .route("/subscription", post(handlers::subscription::subscribe))
         .route("/subscription/confirm", get(handlers::confirm_subscription::confirm))
+        .route(
+            "/subscription/unsubscribe",
+            get(handlers::confirm_subscription::unsubscribe),
+        )
+        .route("/lists", get(handlers::lists::get_lists))
+        .route("/lists/{id}/subscription", post(handlers::lists::subscribe_to_list))
+        .route(
+            "/lists/{id}/subscription/confirm",
+            get(handlers::confirm_subscription::confirm_list_member),
+        )
+        .route(
+            "/lists/{id}/subscription/unsubscribe",
+            get(handlers::confirm_subscription::unsubscribe_list_member),
+        )
+        .route("/admin/lists", post(handlers::lists::create_list))
+        .route("/admin/newsletters", post(handlers::newsletters::create_newsletter))
+        .route(
+            "/admin/issues",
+            get(handlers::issues::get_issues).post(handlers::issues::create_issue),
+        )
+        .route(
+            "/admin/issues/{id}",
+            get(handlers::issues::get_issue).put(handlers::issues::update_issue),
+        )
+        .route("/admin/issues/{id}/schedule", put(handlers::issues::reschedule_issue))
+        .route("/admin/issues/{id}/approve", post(handlers::issues::approve_issue))
+        .route("/admin/issues/{id}/cancel", post(handlers::issues::cancel_issue))
+        .route("/admin/issues/{id}/test", post(handlers::issues::test_issue))
+        .route("/admin/issues/{id}/stats", get(handlers::issues::issue_stats))
+        .route("/admin/subscribers", get(handlers::subscribers::list_subscribers))
+        .route(
+            "/admin/subscribers/{id}",
+            get(handlers::subscribers::get_subscriber)
+                .patch(handlers::subscribers::update_subscriber)
+                .delete(handlers::subscribers::delete_subscriber),
+        )
+        .route("/admin/subscribers/import", post(handlers::import::import))
+        .route("/admin/subscribers/export", get(handlers::export::export))
+        .route(
+            "/admin/subscribers/{id}/consent",
+            get(handlers::subscribers::get_consent_events),
+        )
+        .route("/admin/subscribers/{id}/tags", get(handlers::tags::get_tags))
+        .route(
+            "/admin/subscribers/{id}/tags/{tag}",
+            put(handlers::tags::add_tag).delete(handlers::tags::remove_tag),
+        )
+        .route("/admin/tags/{tag}/bulk", post(handlers::tags::bulk_add_tag))
+        .route(
+            "/admin/segments",
+            get(handlers::segments::get_segments).post(handlers::segments::create_segment),
+        )
+        .route("/admin/segments/preview", post(handlers::segments::preview_expression))
+        .route("/admin/segments/{id}", delete(handlers::segments::delete_segment))
+        .route("/admin/segments/{id}/preview", get(handlers::segments::preview_segment))
+        .route(
+            "/admin/templates",
+            get(handlers::email_templates::get_templates)
+                .post(handlers::email_templates::create_template),
+        )
+        .route(
+            "/admin/templates/{id}",
+            get(handlers::email_templates::get_template)
+                .delete(handlers::email_templates::delete_template),
+        )
+        .route(
+            "/admin/templates/{id}/versions",
+            post(handlers::email_templates::create_version),
+        )
+        .route(
+            "/admin/templates/{id}/active",
+            put(handlers::email_templates::set_active_version),
+        )
+        .route(
+            "/admin/templates/{id}/preview",
+            post(handlers::email_templates::preview_template),
+        )
+        .route("/admin/gdpr/access", post(handlers::gdpr::admin_export))
+        .route("/admin/gdpr/erasure", post(handlers::gdpr::admin_erase))
+        .route(
+            "/gdpr/data",
+            get(handlers::gdpr::subscriber_export).delete(handlers::gdpr::subscriber_erase),
+        )
+        .route("/t/o/{file}", get(handlers::tracking::open_pixel))
+        .route("/t/c/{token}", get(handlers::tracking::click))
+        .route("/webhooks/email", post(handlers::webhooks::email_event))
+        .route(
+            "/preferences",
+            get(handlers::preferences::preferences_page).post(handlers::preferences::request_links),
+        )
+        .route(
+            "/preferences/api",
+            get(handlers::preferences::get_preferences).put(handlers::preferences::update_preferences),
+        )
         .with_state(app_state)
         .layer(
             TraceLayer::new_for_http()
@@ -138,8 +270,8 @@ pub fn app_internal(app_state: AppState) -> Router {
                     },
                 )
                 .on_failure(
-                    |error: ServerErrorsFailureClass, latency: Duration, span: &Span| {
-                        span.record("error_type", &tracing::field::display(&error));
+                    |error: ServerErrorsFailureClass, _latency: Duration, span: &Span| {
+                        span.record("error_type", tracing::field::display(&error));
                         tracing::error!("error: {:?}", error);
                     },
                 ),
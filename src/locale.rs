@@ -0,0 +1,44 @@
+//! Choosing the locale of a subscriber.
+
+/// Picks the entry of `supported` the client prefers according to an `Accept-Language`
+/// header. A tag matches exactly or by its primary language, so `ja-JP` selects `ja`.
+pub fn negotiate<'a>(accept_language: &str, supported: &'a [String]) -> Option<&'a str> {
+    let mut ranges: Vec<(&str, f32)> = accept_language
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';');
+            let tag = parts.next()?.trim();
+            let quality = parts
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse().ok())?;
+            (!tag.is_empty() && tag != "*" && quality > 0.0).then_some((tag, quality))
+        })
+        .collect();
+    // stable, so equally weighted tags keep the client's order
+    ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    ranges.into_iter().find_map(|(tag, _)| {
+        let primary = tag.split('-').next().unwrap_or(tag);
+        supported
+            .iter()
+            .find(|s| s.eq_ignore_ascii_case(tag))
+            .or_else(|| supported.iter().find(|s| s.eq_ignore_ascii_case(primary)))
+            .map(String::as_str)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::negotiate;
+
+    #[test]
+    fn the_most_preferred_supported_locale_is_chosen() {
+        let supported = vec!["en".to_string(), "ja".to_string()];
+        assert_eq!(negotiate("ja-JP,ja;q=0.9,en;q=0.8", &supported), Some("ja"));
+        assert_eq!(negotiate("fr-CH, fr;q=0.9, en;q=0.8, *;q=0.5", &supported), Some("en"));
+        assert_eq!(negotiate("en;q=0.2, ja;q=0.7", &supported), Some("ja"));
+        assert_eq!(negotiate("EN-us", &supported), Some("en"));
+        assert_eq!(negotiate("de, ja;q=0", &supported), None);
+        assert_eq!(negotiate("", &supported), None);
+    }
+}
@@ -1,7 +1,20 @@
-use email_sender::errors;
-use email_sender::run;
+use email_sender::errors::{self, AppError};
+use email_sender::{cli, run};
 
 #[tokio::main]
 async fn main() -> Result<(), errors::AppError> {
-    run().await
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let res = match args.first().map(String::as_str) {
+        None | Some("serve") => run().await,
+        Some("import") => cli::import(&args[1..]).await,
+        Some(other) => Err(AppError::UsageError(format!(
+            "unknown command `{other}`\n{}",
+            cli::USAGE
+        ))),
+    };
+    if let Err(AppError::UsageError(msg)) = &res {
+        eprintln!("{msg}");
+        std::process::exit(2);
+    }
+    res
 }
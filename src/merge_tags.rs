@@ -0,0 +1,379 @@
+//! Per-recipient personalization of newsletters.
+//!
+//! The subject and bodies of a newsletter are minijinja templates rendered once for every
+//! recipient. They can use
+//!
+//! - `subscriber.name` and `subscriber.email`
+//! - `subscriber.attributes.<key>`, the custom data stored in `subscriptions.attributes`
+//! - `unsubscribe_url`, the recipient's own unsubscribe link
+//!
+//! Any other variable is rejected by [`Newsletter::compose`], before anything is sent. Values
+//! a recipient does not have are taken from the newsletter's [`Defaults`]; an attribute
+//! missing from both renders as an empty string, and `{{ x | default("...") }}` works as
+//! usual. Markdown content is personalized before it is rendered, see [`crate::content`], so
+//! values end up in the Markdown as they are; the HTML it renders to is sanitized.
+
+use crate::content;
+use crate::templates::{EmailTemplates, RenderedEmail, TemplateSource};
+use minijinja::{Environment, UndefinedBehavior};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+const SUBJECT: &str = "newsletter.subject.txt";
+const HTML: &str = "newsletter.html";
+const TEXT: &str = "newsletter.txt";
+const MARKDOWN: &str = "newsletter.md";
+
+/// Stands in for `unsubscribe_url` where no real subscriber is involved.
+pub const EXAMPLE_UNSUBSCRIBE_URL: &str = "https://example.com/unsubscribe";
+
+/// What a newsletter is written in.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum NewsletterSource {
+    /// a Markdown document with front matter
+    Markdown { markdown: String },
+    Html(TemplateSource),
+}
+
+/// Values used for recipients who lack them. Empty strings count as missing.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Defaults {
+    pub name: Option<String>,
+    #[serde(default)]
+    pub attributes: Map<String, Value>,
+}
+
+/// The fields of one recipient.
+#[derive(Debug, Clone)]
+pub struct Recipient {
+    pub name: String,
+    pub email: String,
+    pub attributes: Value,
+}
+
+#[derive(Serialize, Debug)]
+struct MergeContext<'a> {
+    subscriber: MergeSubscriber<'a>,
+    unsubscribe_url: &'a str,
+}
+
+#[derive(Serialize, Debug)]
+struct MergeSubscriber<'a> {
+    name: &'a str,
+    email: &'a str,
+    attributes: Map<String, Value>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum MergeError {
+    #[error("unknown variable `{variable}` in {template}")]
+    UnknownVariable { template: String, variable: String },
+
+    #[error("error rendering {template}: {source:#}")]
+    Template {
+        template: String,
+        source: minijinja::Error,
+    },
+
+    #[error(transparent)]
+    Content(#[from] content::ContentError),
+}
+
+/// A newsletter checked for merge tags, ready to be rendered per recipient.
+#[derive(Debug)]
+pub struct Newsletter {
+    env: Environment<'static>,
+    markdown: bool,
+    defaults: Defaults,
+    /// subjects added with [`Newsletter::add_subject_variant`]
+    variants: usize,
+}
+
+impl Newsletter {
+    /// Checks `source` only uses known variables and renders it once with example values.
+    pub fn compose(
+        source: NewsletterSource,
+        defaults: Defaults,
+        templates: &EmailTemplates,
+    ) -> Result<Self, MergeError> {
+        let mut env = Environment::new();
+        env.set_undefined_behavior(UndefinedBehavior::Lenient);
+        let parts = match source {
+            NewsletterSource::Markdown { markdown } => vec![(MARKDOWN, markdown)],
+            NewsletterSource::Html(source) => {
+                let mut parts = vec![(SUBJECT, source.subject), (HTML, source.html)];
+                parts.extend(source.text.map(|text| (TEXT, text)));
+                parts
+            }
+        };
+        let markdown = parts.iter().any(|(name, _)| *name == MARKDOWN);
+        for (name, template) in parts {
+            add_part(&mut env, name.to_string(), template)?;
+        }
+
+        let newsletter = Newsletter {
+            env,
+            markdown,
+            defaults,
+            variants: 0,
+        };
+        newsletter.render(templates, &example(), EXAMPLE_UNSUBSCRIBE_URL)?;
+        Ok(newsletter)
+    }
+
+    /// Adds an alternative subject, checked like the newsletter's own, and returns its index
+    /// for [`Newsletter::render_variant`].
+    pub fn add_subject_variant(&mut self, subject: String) -> Result<usize, MergeError> {
+        let variant = self.variants;
+        add_part(&mut self.env, variant_name(variant), subject)?;
+        self.variants += 1;
+        self.render_part(&variant_name(variant), &example().context(self, EXAMPLE_UNSUBSCRIBE_URL))?;
+        Ok(variant)
+    }
+
+    /// Renders the newsletter for one recipient.
+    pub fn render(
+        &self,
+        templates: &EmailTemplates,
+        recipient: &Recipient,
+        unsubscribe_url: &str,
+    ) -> Result<RenderedEmail, MergeError> {
+        let context = recipient.context(self, unsubscribe_url);
+
+        if self.markdown {
+            let markdown = self.render_part(MARKDOWN, &context)?;
+            return Ok(content::render(templates, &markdown)?);
+        }
+        let text = match self.env.get_template(TEXT) {
+            Ok(_) => Some(self.render_part(TEXT, &context)?),
+            Err(_) => None,
+        };
+        Ok(RenderedEmail {
+            subject: self.render_part(SUBJECT, &context)?.trim().to_string(),
+            html: self.render_part(HTML, &context)?,
+            text,
+        })
+    }
+
+    /// Renders the newsletter for one recipient with the subject added as `variant`.
+    pub fn render_variant(
+        &self,
+        templates: &EmailTemplates,
+        recipient: &Recipient,
+        unsubscribe_url: &str,
+        variant: usize,
+    ) -> Result<RenderedEmail, MergeError> {
+        let mut email = self.render(templates, recipient, unsubscribe_url)?;
+        let context = recipient.context(self, unsubscribe_url);
+        email.subject = self.render_part(&variant_name(variant), &context)?.trim().to_string();
+        Ok(email)
+    }
+
+    fn render_part(&self, name: &str, context: &MergeContext) -> Result<String, MergeError> {
+        self.env
+            .get_template(name)
+            .and_then(|template| template.render(context))
+            .map_err(|source| MergeError::Template {
+                template: name.to_string(),
+                source,
+            })
+    }
+}
+
+impl Recipient {
+    /// The merge fields of this recipient, with `newsletter`'s defaults filled in.
+    fn context<'a>(&'a self, newsletter: &'a Newsletter, unsubscribe_url: &'a str) -> MergeContext<'a> {
+        let mut attributes = newsletter.defaults.attributes.clone();
+        if let Value::Object(own) = &self.attributes {
+            for (key, value) in own {
+                if !is_missing(value) {
+                    attributes.insert(key.clone(), value.clone());
+                }
+            }
+        }
+        let name = match (&newsletter.defaults.name, self.name.trim()) {
+            (Some(default), "") => default.as_str(),
+            _ => self.name.as_str(),
+        };
+        MergeContext {
+            subscriber: MergeSubscriber {
+                name,
+                email: &self.email,
+                attributes,
+            },
+            unsubscribe_url,
+        }
+    }
+}
+
+fn example() -> Recipient {
+    Recipient {
+        name: "subscriber".to_string(),
+        email: "subscriber@example.com".to_string(),
+        attributes: Value::Object(Map::new()),
+    }
+}
+
+fn variant_name(variant: usize) -> String {
+    format!("newsletter.subject.{variant}.txt")
+}
+
+/// Adds a template to `env` after checking it only uses merge fields.
+fn add_part(env: &mut Environment<'static>, name: String, template: String) -> Result<(), MergeError> {
+    env.add_template_owned(name.clone(), template)
+        .map_err(|source| MergeError::Template {
+            template: name.clone(),
+            source,
+        })?;
+    let template = env.get_template(&name).expect("the template was just added");
+    let mut variables: Vec<_> = template.undeclared_variables(true).into_iter().collect();
+    variables.sort();
+    if let Some(variable) = variables
+        .into_iter()
+        .find(|v| !known_variable(v) && !is_global(env, v))
+    {
+        return Err(MergeError::UnknownVariable {
+            template: name,
+            variable,
+        });
+    }
+    Ok(())
+}
+
+/// Whether a dotted variable path refers to a merge field.
+fn known_variable(variable: &str) -> bool {
+    let mut path = variable.split('.');
+    matches!(
+        (path.next(), path.next(), path.next()),
+        (Some("unsubscribe_url"), None, _)
+            | (Some("subscriber"), None | Some("attributes"), _)
+            | (Some("subscriber"), Some("name" | "email"), None)
+    )
+}
+
+/// Whether a variable path starts at a built-in global such as `range`.
+fn is_global(env: &Environment, variable: &str) -> bool {
+    let root = variable.split('.').next().unwrap_or(variable);
+    env.globals().any(|(name, _)| name == root)
+}
+
+fn is_missing(value: &Value) -> bool {
+    match value {
+        Value::Null => true,
+        Value::String(s) => s.trim().is_empty(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn html(subject: &str, html: &str) -> NewsletterSource {
+        NewsletterSource::Html(TemplateSource {
+            subject: subject.to_string(),
+            html: html.to_string(),
+            text: None,
+        })
+    }
+
+    fn recipient(name: &str, attributes: Value) -> Recipient {
+        Recipient {
+            name: name.to_string(),
+            email: "ada@example.com".to_string(),
+            attributes,
+        }
+    }
+
+    #[test]
+    fn unknown_variables_are_rejected() {
+        let templates = EmailTemplates::load("templates", &[]).unwrap();
+        for (subject, body, variable) in [
+            ("Hi {{ name }}", "", "name"),
+            ("Hi", "{{ subscriber.phone }}", "subscriber.phone"),
+            ("Hi", "{{ unsubscribe_link }}", "unsubscribe_link"),
+        ] {
+            let err = Newsletter::compose(html(subject, body), Defaults::default(), &templates).unwrap_err();
+            assert!(matches!(&err, MergeError::UnknownVariable { variable: v, .. } if v == variable), "{err}");
+        }
+        assert!(Newsletter::compose(html("Hi", "{% if %}"), Defaults::default(), &templates).is_err());
+        assert!(Newsletter::compose(
+            html("{{ subscriber.name }}", "{% for i in range(2) %}{{ subscriber.attributes.city }}{{ i }}{% endfor %}"),
+            Defaults::default(),
+            &templates,
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn recipients_get_their_own_values_or_the_defaults() {
+        let templates = EmailTemplates::load("templates", &[]).unwrap();
+        let defaults = Defaults {
+            name: Some("reader".to_string()),
+            attributes: json!({ "city": "Tokyo" }).as_object().unwrap().clone(),
+        };
+        let newsletter = Newsletter::compose(
+            html(
+                "Hi {{ subscriber.name }}",
+                "<p>{{ subscriber.name }} in {{ subscriber.attributes.city }}{{ subscriber.attributes.zip }}</p>\
+                <a href=\"{{ unsubscribe_url }}\">unsubscribe</a>",
+            ),
+            defaults,
+            &templates,
+        )
+        .unwrap();
+
+        let email = newsletter
+            .render(&templates, &recipient("<Ada>", json!({ "city": "Osaka" })), "https://x.test/u?a=1&b=2")
+            .unwrap();
+        assert_eq!(email.subject, "Hi <Ada>");
+        assert_eq!(
+            email.html,
+            "<p>&lt;Ada&gt; in Osaka</p><a href=\"https:&#x2f;&#x2f;x.test&#x2f;u?a=1&amp;b=2\">unsubscribe</a>"
+        );
+        assert_eq!(email.text, None);
+
+        let email = newsletter
+            .render(&templates, &recipient(" ", json!({ "city": "" })), "u")
+            .unwrap();
+        assert_eq!(email.subject, "Hi reader");
+        assert!(email.html.starts_with("<p>reader in Tokyo</p>"));
+    }
+
+    #[test]
+    fn markdown_is_personalized_before_rendering() {
+        let templates = EmailTemplates::load("templates", &[]).unwrap();
+        let newsletter = Newsletter::compose(
+            NewsletterSource::Markdown {
+                markdown: "---\nsubject: News for {{ subscriber.name }}\n---\nHi **{{ subscriber.name }}**\n".to_string(),
+            },
+            Defaults::default(),
+            &templates,
+        )
+        .unwrap();
+        let email = newsletter
+            .render(&templates, &recipient("Ada", json!({})), "u")
+            .unwrap();
+        assert_eq!(email.subject, "News for Ada");
+        assert!(email.html.contains("<p>Hi <strong>Ada</strong></p>"));
+        assert_eq!(email.text.as_deref(), Some("Hi Ada\n"));
+    }
+
+    #[test]
+    fn subject_variants_replace_the_subject() {
+        let templates = EmailTemplates::load("templates", &[]).unwrap();
+        let mut newsletter =
+            Newsletter::compose(html("Hi", "<p>hi</p>"), Defaults::default(), &templates).unwrap();
+        assert!(newsletter.add_subject_variant("{{ subscriber.nickname }}".to_string()).is_err());
+        let variant = newsletter
+            .add_subject_variant("News for {{ subscriber.name }}".to_string())
+            .unwrap();
+        let email = newsletter
+            .render_variant(&templates, &recipient("Ada", json!({})), "u", variant)
+            .unwrap();
+        assert_eq!(email.subject, "News for Ada");
+        assert_eq!(email.html, "<p>hi</p>");
+    }
+}
@@ -0,0 +1,302 @@
+//! Plain-text alternatives of HTML email bodies.
+//!
+//! Headings are underlined, list items get `-` or their number, quotes get `>` and table
+//! cells are joined with `|`. Links keep their text followed by a footnote number, and the
+//! targets are listed at the end. Elements a mail client would not show (`<head>`,
+//! `<script>`, `<style>`, and anything hidden with `display:none` such as a preheader) are
+//! left out.
+
+use scraper::node::Node;
+use scraper::{ElementRef, Html};
+
+/// Converts an HTML document or fragment to plain text.
+pub fn from_html(html: &str) -> String {
+    let document = Html::parse_document(html);
+    let mut writer = TextWriter::default();
+    writer.children(document.root_element());
+
+    let mut text = writer.out.trim_end().to_string();
+    if !writer.footnotes.is_empty() {
+        text.push_str("\n\n");
+        for (i, url) in writer.footnotes.iter().enumerate() {
+            text.push_str(&format!("[{}] {url}\n", i + 1));
+        }
+    }
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+    text
+}
+
+#[derive(Default)]
+struct TextWriter {
+    out: String,
+    /// whitespace was seen since the last character written
+    space: bool,
+    preformatted: bool,
+    quote_depth: usize,
+    /// the next number of each open list, `None` for bullet lists
+    lists: Vec<Option<u64>>,
+    table_cell: usize,
+    footnotes: Vec<String>,
+}
+
+impl TextWriter {
+    fn children(&mut self, element: ElementRef) {
+        for child in element.children() {
+            match child.value() {
+                Node::Text(text) => self.text(text),
+                Node::Element(_) => self.element(ElementRef::wrap(child).expect("an element")),
+                _ => {}
+            }
+        }
+    }
+
+    fn element(&mut self, element: ElementRef) {
+        let name = element.value().name();
+        if hidden(element) {
+            return;
+        }
+        match name {
+            "br" => self.newline(),
+            "hr" => {
+                self.line_start();
+                self.write("----");
+                self.end_block();
+            }
+            "img" => {
+                if let Some(alt) = element.attr("alt").filter(|a| !a.trim().is_empty()) {
+                    self.text(alt);
+                }
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.line_start();
+                let start = self.out.len();
+                self.children(element);
+                let width = self.out[start..].chars().count();
+                let underline = match name {
+                    "h1" => Some("="),
+                    "h2" => Some("-"),
+                    _ => None,
+                };
+                if let (Some(underline), true) = (underline, width > 0) {
+                    self.newline();
+                    self.write(&underline.repeat(width));
+                }
+                self.end_block();
+            }
+            "ul" | "ol" => {
+                let first = (name == "ol").then(|| {
+                    element
+                        .attr("start")
+                        .and_then(|s| s.trim().parse().ok())
+                        .unwrap_or(1)
+                });
+                self.line_start();
+                self.lists.push(first);
+                self.children(element);
+                self.lists.pop();
+                if self.lists.is_empty() {
+                    self.end_block();
+                } else {
+                    self.line_start();
+                }
+            }
+            "li" => {
+                self.line_start();
+                let indent = "  ".repeat(self.lists.len().saturating_sub(1));
+                let marker = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{indent}{}. ", *number - 1)
+                    }
+                    _ => format!("{indent}- "),
+                };
+                self.write(&marker);
+                self.children(element);
+                self.line_start();
+            }
+            "blockquote" => {
+                self.line_start();
+                self.quote_depth += 1;
+                self.children(element);
+                self.end_block();
+                self.quote_depth -= 1;
+            }
+            "pre" => {
+                self.line_start();
+                self.preformatted = true;
+                self.children(element);
+                self.preformatted = false;
+                self.end_block();
+            }
+            "tr" => {
+                self.line_start();
+                self.table_cell = 0;
+                self.children(element);
+                self.line_start();
+            }
+            "td" | "th" => {
+                if self.table_cell > 0 {
+                    self.write(" | ");
+                }
+                self.table_cell += 1;
+                self.children(element);
+            }
+            "a" => {
+                let start = self.out.len();
+                self.children(element);
+                if let Some(href) = element.attr("href").map(str::trim) {
+                    self.link(start, href);
+                }
+            }
+            "p" | "div" | "table" | "section" | "article" | "header" | "footer" | "main"
+            | "nav" | "aside" | "address" | "figure" | "figcaption" | "center" | "form"
+            | "dl" | "dt" | "dd" => {
+                self.line_start();
+                self.children(element);
+                self.end_block();
+            }
+            _ => self.children(element),
+        }
+    }
+
+    /// Adds the footnote of a link whose text starts at `start`.
+    fn link(&mut self, start: usize, href: &str) {
+        if href.is_empty() || href.starts_with('#') {
+            return;
+        }
+        let text = self.out[start..].trim();
+        let target = href.strip_prefix("mailto:").unwrap_or(href);
+        if text == target {
+            return;
+        }
+        let number = match self.footnotes.iter().position(|f| f == href) {
+            Some(i) => i + 1,
+            None => {
+                self.footnotes.push(href.to_string());
+                self.footnotes.len()
+            }
+        };
+        self.space = false;
+        self.write(&format!(" [{number}]"));
+    }
+
+    fn text(&mut self, text: &str) {
+        if self.preformatted {
+            for (i, line) in text.split('\n').enumerate() {
+                if i > 0 {
+                    self.newline();
+                }
+                self.write(line);
+            }
+            return;
+        }
+        for word in text.split(|c: char| c.is_ascii_whitespace()) {
+            if word.is_empty() {
+                self.space = true;
+                continue;
+            }
+            self.write(word);
+            self.space = true;
+        }
+        // the last piece is followed by a space only if the text ends with one
+        self.space = text.ends_with(|c: char| c.is_ascii_whitespace());
+    }
+
+    fn write(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        if self.at_line_start() {
+            self.out.push_str(&"> ".repeat(self.quote_depth));
+        } else if self.space && !self.out.ends_with(' ') {
+            self.out.push(' ');
+        }
+        self.space = false;
+        self.out.push_str(text);
+    }
+
+    fn at_line_start(&self) -> bool {
+        self.out.is_empty() || self.out.ends_with('\n')
+    }
+
+    fn newline(&mut self) {
+        self.out.truncate(self.out.trim_end_matches(' ').len());
+        self.out.push('\n');
+        self.space = false;
+    }
+
+    fn line_start(&mut self) {
+        if !self.at_line_start() {
+            self.newline();
+        }
+    }
+
+    /// Ends a block: the next one starts after a blank line, or on the next line in a list.
+    fn end_block(&mut self) {
+        if self.out.is_empty() {
+            return;
+        }
+        self.line_start();
+        if self.lists.is_empty() && !self.out.ends_with("\n\n") {
+            self.newline();
+        }
+    }
+}
+
+fn hidden(element: ElementRef) -> bool {
+    let name = element.value().name();
+    if matches!(name, "head" | "script" | "style" | "template" | "noscript") {
+        return true;
+    }
+    element.attr("hidden").is_some()
+        || element.attr("style").is_some_and(|style| {
+            style
+                .split(';')
+                .filter_map(|d| d.split_once(':'))
+                .any(|(property, value)| {
+                    property.trim().eq_ignore_ascii_case("display")
+                        && value.trim().eq_ignore_ascii_case("none")
+                })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn structure_is_kept_and_links_become_footnotes() {
+        let html = r#"<html><head><title>t</title><style>p { color: red }</style></head><body>
+            <div style="display: none">preheader</div>
+            <h1>Title</h1>
+            <p>Read <a href="https://example.com/a">the   post</a> or
+            <a href="https://example.com/b">this</a>, <a href="https://example.com/a">again</a>.<br>
+            Or visit <a href="https://example.org">https://example.org</a>.</p>
+            <ul><li>one</li><li>two<ol start="3"><li>three</li></ol></li></ul>
+            <blockquote><p>quoted</p></blockquote>
+            <table><tr><th>a</th><th>b</th></tr><tr><td>1</td><td>2</td></tr></table>
+            <script>alert(1)</script>
+            </body></html>"#;
+        assert_eq!(
+            from_html(html),
+            "Title\n=====\n\n\
+            Read the post [1] or this [2], again [1].\n\
+            Or visit https://example.org.\n\n\
+            - one\n- two\n  3. three\n\n\
+            > quoted\n\n\
+            a | b\n1 | 2\n\n\
+            [1] https://example.com/a\n[2] https://example.com/b\n"
+        );
+    }
+
+    #[test]
+    fn entities_are_decoded_and_preformatted_text_is_kept() {
+        assert_eq!(
+            from_html("<p>a &amp; b&nbsp;c</p><pre>x\n  y</pre>"),
+            "a & b\u{a0}c\n\nx\n  y\n"
+        );
+        assert_eq!(from_html(""), "");
+    }
+}
@@ -0,0 +1,339 @@
+//! Sending scheduled newsletter issues.
+//!
+//! Every replica runs [`run_scheduler`]. A due approved issue is promoted by writing one
+//! queued delivery per recipient in the transaction that marks it `sending`; editing,
+//! cancelling and rescheduling only touch `draft` and `approved` issues, so they either
+//! happen before the send starts or find it started. Deliveries are then claimed one at a time with `FOR UPDATE SKIP
+//! LOCKED` and marked in the transaction holding the lock, so a replica that stops mid-send
+//! leaves the rest queued for the next round, wherever it runs. A crash between the email
+//! API accepting a message and the commit sends that one message twice; none are lost.
+//! A send that times out or gets a 429 or 5xx stays queued and is tried again with an
+//! exponential backoff, up to `scheduler.max_attempts` times; other errors fail it at once.
+//! An issue that cannot be started is marked `failed` without holding up the others.
+//!
+//! Issues with an A/B test first send to a sample and wait for its result, see
+//! [`crate::ab_test`].
+//!
+//! Each round also mails the confirmation links queued by imports, see
+//! [`crate::import::send_queued_confirmations`].
+
+use crate::AppState;
+use crate::ab_test::{self, AbTest};
+use crate::delivery::{self, DeliveryTarget};
+use crate::email_client;
+use crate::import;
+use crate::merge_tags::{Defaults, Newsletter, NewsletterSource, Recipient};
+use crate::segment;
+use anyhow::Context;
+use sqlx::{Acquire, PgPool, Postgres, QueryBuilder, Transaction};
+use sqlx::types::chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Promotes due issues and sends their deliveries every `interval`. Never returns.
+pub async fn run_scheduler(app_state: AppState, interval: std::time::Duration) {
+    let pool = app_state.pg_pool.clone();
+    let batch_size = app_state.conf.scheduler.batch_size;
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        match promote_due_issues(&pool, Utc::now()).await {
+            Ok(issues) if !issues.is_empty() => tracing::info!(?issues, "started sending issues"),
+            Ok(_) => {}
+            Err(e) => tracing::error!(error = ?e, "error promoting due issues"),
+        }
+        match ab_test::decide_due_tests(&pool, Utc::now()).await {
+            Ok(issues) if !issues.is_empty() => tracing::info!(?issues, "picked A/B test winners"),
+            Ok(_) => {}
+            Err(e) => tracing::error!(error = ?e, "error deciding A/B tests"),
+        }
+        loop {
+            match deliver_queued(&app_state, batch_size).await {
+                Ok(report) if report.claimed() > 0 => {
+                    tracing::info!(?report, "sent queued deliveries");
+                    if report.claimed() < batch_size {
+                        break;
+                    }
+                }
+                Ok(_) => break,
+                Err(e) => {
+                    tracing::error!(error = ?e, "error sending queued deliveries");
+                    break;
+                }
+            }
+        }
+        match import::send_queued_confirmations(&app_state, batch_size).await {
+            Ok(sent) if sent > 0 => tracing::info!(sent, "sent queued confirmation emails"),
+            Ok(_) => {}
+            Err(e) => tracing::error!(error = ?e, "error sending queued confirmation emails"),
+        }
+    }
+}
+
+/// Deliveries handled by one call of [`deliver_queued`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DeliveryRun {
+    pub sent: usize,
+    pub failed: usize,
+    /// the recipient unsubscribed or was erased after the issue started sending
+    pub skipped: usize,
+    /// failed for a transient reason and left queued for a later try
+    pub retried: usize,
+}
+
+impl DeliveryRun {
+    pub fn claimed(&self) -> usize {
+        self.sent + self.failed + self.skipped + self.retried
+    }
+}
+
+/// Starts sending the approved issues scheduled at or before `now`: queues a delivery for each
+/// confirmed subscriber, narrowed to the confirmed members of the issue's list and to its
+/// segment's matches, and sends only to the sample of an A/B test. Returns their ids.
+///
+/// Each issue starts in its own transaction. One that cannot start, e.g. because its A/B test
+/// settings or segment no longer parse, is marked `failed` with the error and the rest go on.
+pub async fn promote_due_issues(pool: &PgPool, now: DateTime<Utc>) -> anyhow::Result<Vec<Uuid>> {
+    let mut started = vec![];
+    loop {
+        let mut transaction = pool.begin().await.context("error starting transaction")?;
+        let Some(issue) = sqlx::query!(
+            r#"SELECT i.id, i.list_id, i.ab_test, g.expression AS "segment?"
+            FROM newsletter_issues i
+            LEFT JOIN segments g ON g.id = i.segment_id
+            WHERE i.status = 'approved' AND i.scheduled_at <= $1
+            ORDER BY i.scheduled_at
+            LIMIT 1
+            FOR UPDATE OF i SKIP LOCKED"#,
+            now
+        )
+        .fetch_optional(&mut *transaction)
+        .await
+        .context("error fetching due issues")?
+        else {
+            break;
+        };
+
+        // a savepoint, so a failed start is undone while the issue stays locked
+        let mut attempt = Acquire::begin(&mut *transaction)
+            .await
+            .context("error starting savepoint")?;
+        match start_issue(&mut attempt, issue.id, issue.list_id, issue.ab_test, issue.segment, now).await {
+            Ok(()) => {
+                attempt.commit().await.context("error releasing savepoint")?;
+                started.push(issue.id);
+            }
+            Err(e) => {
+                attempt.rollback().await.context("error rolling back savepoint")?;
+                tracing::error!(error = ?e, issue_id = %issue.id, "error starting issue");
+                sqlx::query!(
+                    "UPDATE newsletter_issues SET status = 'failed', error = $3, updated_at = $2 WHERE id = $1",
+                    issue.id,
+                    now,
+                    format!("{e:#}"),
+                )
+                .execute(&mut *transaction)
+                .await
+                .context("error marking issue failed")?;
+            }
+        }
+        transaction.commit().await.context("error commiting transaction")?;
+    }
+    Ok(started)
+}
+
+async fn start_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+    list_id: Option<Uuid>,
+    ab_test: Option<serde_json::Value>,
+    segment: Option<String>,
+    now: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    let ab_test: Option<AbTest> = ab_test
+        .map(serde_json::from_value)
+        .transpose()
+        .context("error reading A/B test settings")?;
+    // segments compile to conditions over `subscriptions`, so the table is not aliased
+    let mut qb = QueryBuilder::new(
+        "INSERT INTO issue_deliveries (issue_id, subscriber_uuid, status, queued_at) SELECT ",
+    );
+    qb.push_bind(issue_id)
+        .push(", subscriptions.id, ")
+        .push_bind(if ab_test.is_some() { "held" } else { "queued" })
+        .push(", ")
+        .push_bind(now)
+        .push(" FROM subscriptions WHERE subscriptions.status = 'confirmed'");
+    if let Some(list_id) = list_id {
+        qb.push(
+            " AND EXISTS (SELECT 1 FROM list_memberships m \
+            WHERE m.subscriber_uuid = subscriptions.id AND m.status = 'confirmed' AND m.list_id = ",
+        )
+        .push_bind(list_id)
+        .push(")");
+    }
+    if let Some(expression) = &segment {
+        let expr = segment::parse(expression).context("error parsing the issue's segment")?;
+        qb.push(" AND ");
+        expr.push_sql(&mut qb);
+    }
+    qb.push(" ON CONFLICT (issue_id, subscriber_uuid) DO NOTHING");
+    qb.build()
+        .execute(&mut **transaction)
+        .await
+        .context("error queueing deliveries")?;
+    if let Some(ab_test) = &ab_test {
+        ab_test::start_sample(transaction, issue_id, ab_test).await?;
+    }
+    sqlx::query!(
+        "UPDATE newsletter_issues SET status = $3, started_at = $2, updated_at = $2 WHERE id = $1",
+        issue_id,
+        now,
+        if ab_test.is_some() { "testing" } else { "sending" },
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("error starting issue")?;
+    Ok(())
+}
+
+/// Sends up to `limit` queued deliveries that are due for a try, then marks issues with
+/// nothing left queued as sent.
+pub async fn deliver_queued(app_state: &AppState, limit: usize) -> anyhow::Result<DeliveryRun> {
+    let pool = app_state.pg_pool.as_ref();
+    let settings = &app_state.conf.scheduler;
+    let mut run = DeliveryRun::default();
+    // composed once per issue; an issue that no longer composes fails all its deliveries
+    let mut newsletters: HashMap<Uuid, Result<Newsletter, String>> = HashMap::new();
+    while run.claimed() < limit {
+        let mut transaction = pool.begin().await.context("error starting transaction")?;
+        let Some(d) = sqlx::query!(
+            r#"SELECT d.id, d.issue_id, d.subscriber_uuid, d.variant, d.attempts, i.content, i.defaults,
+                i.ab_test, i.list_id,
+                s.email AS "email?", s.name AS "name?", s.attributes AS "attributes?",
+                COALESCE(s.status = 'confirmed' AND (i.list_id IS NULL OR EXISTS (
+                    SELECT 1 FROM list_memberships m
+                    WHERE m.subscriber_uuid = s.id AND m.list_id = i.list_id AND m.status = 'confirmed'
+                )), false) AS "subscribed!"
+            FROM issue_deliveries d
+            JOIN newsletter_issues i ON i.id = d.issue_id
+            LEFT JOIN subscriptions s ON s.id = d.subscriber_uuid
+            WHERE d.status = 'queued' AND i.status IN ('sending', 'testing')
+                AND (d.next_attempt_at IS NULL OR d.next_attempt_at <= now())
+            ORDER BY d.queued_at
+            LIMIT 1
+            FOR UPDATE OF d SKIP LOCKED"#
+        )
+        .fetch_optional(&mut *transaction)
+        .await
+        .context("error claiming a delivery")?
+        else {
+            break;
+        };
+
+        let newsletter = newsletters
+            .entry(d.issue_id)
+            .or_insert_with(|| compose(app_state, d.content, d.defaults, d.ab_test));
+        let result = match (newsletter, d.subscriber_uuid) {
+            (_, None) => None,
+            _ if !d.subscribed => None,
+            (Err(e), Some(_)) => Some(Err((e.clone(), false))),
+            (Ok(newsletter), Some(subscriber_uuid)) => {
+                let recipient = Recipient {
+                    name: d.name.unwrap_or_default(),
+                    email: d.email.unwrap_or_default(),
+                    attributes: d.attributes.unwrap_or_default(),
+                };
+                let target = DeliveryTarget {
+                    delivery_id: Some(d.id),
+                    subscriber_uuid,
+                    recipient: &recipient,
+                    list_id: d.list_id,
+                    variant: d.variant.map(|v| v as usize),
+                };
+                let sent = delivery::send_to(app_state, &mut *transaction, newsletter, &target).await;
+                // the error, and whether trying again later may succeed
+                Some(sent.map_err(|e| (format!("{e:#}"), email_client::is_transient(&e))))
+            }
+        };
+        let attempts = if result.is_some() { d.attempts + 1 } else { d.attempts };
+        let (status, error) = match result {
+            Some(Ok(())) => {
+                run.sent += 1;
+                ("sent", None)
+            }
+            Some(Err((e, true))) if attempts < settings.max_attempts => {
+                tracing::warn!(error = %e, delivery_id = %d.id, attempts, "error sending newsletter, retrying later");
+                run.retried += 1;
+                sqlx::query!(
+                    "UPDATE issue_deliveries SET attempts = $2, next_attempt_at = $3, error = $4 WHERE id = $1",
+                    d.id,
+                    attempts,
+                    Utc::now() + settings.retry_backoff(attempts),
+                    e,
+                )
+                .execute(&mut *transaction)
+                .await
+                .context("error recording delivery")?;
+                transaction.commit().await.context("error commiting transaction")?;
+                continue;
+            }
+            Some(Err((e, _))) => {
+                tracing::error!(error = %e, delivery_id = %d.id, "error sending newsletter");
+                run.failed += 1;
+                ("failed", Some(e))
+            }
+            None => {
+                run.skipped += 1;
+                ("skipped", None)
+            }
+        };
+        sqlx::query!(
+            r#"UPDATE issue_deliveries SET status = $2, error = $3, sent_at = $4, attempts = $5
+            WHERE id = $1"#,
+            d.id,
+            status,
+            error,
+            (status == "sent").then(Utc::now),
+            attempts,
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("error recording delivery")?;
+        transaction.commit().await.context("error commiting transaction")?;
+    }
+
+    sqlx::query!(
+        r#"UPDATE newsletter_issues i SET status = 'sent', completed_at = $1, updated_at = $1
+        WHERE i.status = 'sending' AND NOT EXISTS (
+            SELECT 1 FROM issue_deliveries d WHERE d.issue_id = i.id AND d.status = 'queued'
+        )"#,
+        Utc::now()
+    )
+    .execute(pool)
+    .await
+    .context("error completing issues")?;
+    Ok(run)
+}
+
+fn compose(
+    app_state: &AppState,
+    content: serde_json::Value,
+    defaults: serde_json::Value,
+    ab_test: Option<serde_json::Value>,
+) -> Result<Newsletter, String> {
+    let source: NewsletterSource =
+        serde_json::from_value(content).map_err(|e| format!("invalid issue content: {e}"))?;
+    let defaults: Defaults =
+        serde_json::from_value(defaults).map_err(|e| format!("invalid issue defaults: {e}"))?;
+    let mut newsletter =
+        Newsletter::compose(source, defaults, &app_state.templates).map_err(|e| e.to_string())?;
+    if let Some(ab_test) = ab_test {
+        let ab_test: AbTest =
+            serde_json::from_value(ab_test).map_err(|e| format!("invalid A/B test: {e}"))?;
+        for subject in ab_test.subjects {
+            newsletter.add_subject_variant(subject).map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(newsletter)
+}
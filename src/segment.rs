@@ -0,0 +1,693 @@
+//! A small filter language for selecting subscribers, e.g.
+//!
+//! ```text
+//! status = "confirmed" and tag = "beta" and attributes.locale = "ja" and subscribed_at > now - 90d
+//! ```
+//!
+//! Expressions are parsed into an [`Expr`] and compiled into a parameterized `WHERE` clause
+//! over `subscriptions`. Field names are matched against a fixed set and every value is bound,
+//! so user input never ends up in the SQL text.
+//!
+//! Grammar:
+//!
+//! ```text
+//! expr      := and ("or" and)*
+//! and       := unary ("and" unary)*
+//! unary     := "not" unary | "(" expr ")" | predicate
+//! predicate := field op value
+//! field     := status | email | name | subscribed_at | tag | list | attributes.<key>
+//! op        := = | != | ~ (contains) | < | <= | > | >=
+//! value     := "string" | number | true | false | now | now - <n>(s|m|h|d|w)
+//! ```
+
+use serde::Serialize;
+use chrono::Duration;
+use sqlx::types::chrono::{DateTime, NaiveDate, Utc};
+use sqlx::{PgPool, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+const MAX_EXPRESSION_LEN: usize = 2000;
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+#[error("invalid segment expression at {position}: {message}")]
+pub struct ParseError {
+    pub position: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Predicate(Predicate),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Predicate {
+    Status(CmpOp, String),
+    Email(CmpOp, String),
+    Name(CmpOp, String),
+    SubscribedAt(CmpOp, TimeValue),
+    /// `tag = "beta"` matches subscribers carrying the tag, `tag != "beta"` those without it
+    Tag(CmpOp, String),
+    /// `list = "weekly"` matches confirmed members of the list with that name
+    List(CmpOp, String),
+    Attribute(String, CmpOp, serde_json::Value),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Contains,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TimeValue {
+    /// `now - <duration>`, resolved when the query is built
+    Ago(Duration),
+    At(DateTime<Utc>),
+}
+
+impl CmpOp {
+    fn as_sql(&self) -> &'static str {
+        match self {
+            CmpOp::Eq => "=",
+            CmpOp::Ne => "<>",
+            CmpOp::Contains => "ILIKE",
+            CmpOp::Lt => "<",
+            CmpOp::Le => "<=",
+            CmpOp::Gt => ">",
+            CmpOp::Ge => ">=",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Number(serde_json::Number),
+    /// e.g. `90d`
+    Duration(Duration),
+    Op(CmpOp),
+    Minus,
+    LParen,
+    RParen,
+}
+
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let err = |position, message: &str| ParseError {
+        position,
+        message: message.to_string(),
+    };
+    let chars: Vec<(usize, char)> = input.char_indices().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let (pos, c) = chars[i];
+        match c {
+            c if c.is_whitespace() => i += 1,
+            '(' => {
+                tokens.push((pos, Token::LParen));
+                i += 1;
+            }
+            ')' => {
+                tokens.push((pos, Token::RParen));
+                i += 1;
+            }
+            '-' => {
+                tokens.push((pos, Token::Minus));
+                i += 1;
+            }
+            '~' => {
+                tokens.push((pos, Token::Op(CmpOp::Contains)));
+                i += 1;
+            }
+            '=' => {
+                tokens.push((pos, Token::Op(CmpOp::Eq)));
+                i += 1;
+            }
+            '!' | '<' | '>' => {
+                let followed_by_eq = chars.get(i + 1).is_some_and(|(_, c)| *c == '=');
+                let op = match (c, followed_by_eq) {
+                    ('!', true) => CmpOp::Ne,
+                    ('!', false) => return Err(err(pos, "expected `!=`")),
+                    ('<', true) => CmpOp::Le,
+                    ('<', false) => CmpOp::Lt,
+                    ('>', true) => CmpOp::Ge,
+                    _ => CmpOp::Gt,
+                };
+                tokens.push((pos, Token::Op(op)));
+                i += if followed_by_eq { 2 } else { 1 };
+            }
+            '"' => {
+                let mut value = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => return Err(err(pos, "unterminated string")),
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => {
+                            let (_, escaped) = chars
+                                .get(i + 1)
+                                .ok_or_else(|| err(pos, "unterminated string"))?;
+                            value.push(*escaped);
+                            i += 2;
+                        }
+                        Some((_, c)) => {
+                            value.push(*c);
+                            i += 1;
+                        }
+                    }
+                }
+                tokens.push((pos, Token::Str(value)));
+                i += 1;
+            }
+            c if c.is_ascii_digit() => {
+                let start = i;
+                while chars
+                    .get(i)
+                    .is_some_and(|(_, c)| c.is_ascii_digit() || *c == '.')
+                {
+                    i += 1;
+                }
+                let number: String = chars[start..i].iter().map(|(_, c)| c).collect();
+                let unit = chars.get(i).map(|(_, c)| *c);
+                let duration = unit.and_then(|unit| {
+                    let n: i64 = number.parse().ok()?;
+                    match unit {
+                        's' => Duration::try_seconds(n),
+                        'm' => Duration::try_minutes(n),
+                        'h' => Duration::try_hours(n),
+                        'd' => Duration::try_days(n),
+                        'w' => Duration::try_weeks(n),
+                        _ => None,
+                    }
+                });
+                match duration {
+                    Some(duration) => {
+                        tokens.push((pos, Token::Duration(duration)));
+                        i += 1;
+                    }
+                    None => {
+                        let number = if number.contains('.') {
+                            number.parse().ok().and_then(serde_json::Number::from_f64)
+                        } else {
+                            number.parse::<i64>().ok().map(Into::into)
+                        };
+                        let number = number.ok_or_else(|| err(pos, "invalid number"))?;
+                        tokens.push((pos, Token::Number(number)));
+                    }
+                }
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let start = i;
+                while chars
+                    .get(i)
+                    .is_some_and(|(_, c)| c.is_alphanumeric() || *c == '_' || *c == '.')
+                {
+                    i += 1;
+                }
+                let ident: String = chars[start..i].iter().map(|(_, c)| c).collect();
+                tokens.push((pos, Token::Ident(ident)));
+            }
+            _ => return Err(err(pos, &format!("unexpected character `{c}`"))),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    end: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, t)| t)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.pos).map(|(p, _)| *p).unwrap_or(self.end)
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, ParseError> {
+        Err(ParseError {
+            position: self.position(),
+            message: message.into(),
+        })
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(_, t)| t.clone());
+        self.pos += 1;
+        token
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Ident(ident)) if ident.eq_ignore_ascii_case(keyword) => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.and()?;
+        while self.eat_keyword("or") {
+            lhs = Expr::Or(Box::new(lhs), Box::new(self.and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.unary()?;
+        while self.eat_keyword("and") {
+            lhs = Expr::And(Box::new(lhs), Box::new(self.unary()?));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return self.error("expression is nested too deeply");
+        }
+        let expr = if self.eat_keyword("not") {
+            Expr::Not(Box::new(self.unary()?))
+        } else if self.peek() == Some(&Token::LParen) {
+            self.pos += 1;
+            let expr = self.expr()?;
+            if self.next() != Some(Token::RParen) {
+                self.pos -= 1;
+                return self.error("expected `)`");
+            }
+            expr
+        } else {
+            Expr::Predicate(self.predicate()?)
+        };
+        self.depth -= 1;
+        Ok(expr)
+    }
+
+    fn predicate(&mut self) -> Result<Predicate, ParseError> {
+        let field_pos = self.position();
+        let Some(Token::Ident(field)) = self.next() else {
+            self.pos -= 1;
+            return self.error("expected a field name");
+        };
+        let op = match self.next() {
+            Some(Token::Op(op)) => op,
+            _ => {
+                self.pos -= 1;
+                return self.error("expected a comparison operator");
+            }
+        };
+        let field_error = |message: String| ParseError {
+            position: field_pos,
+            message,
+        };
+        let text_ops = [CmpOp::Eq, CmpOp::Ne, CmpOp::Contains];
+        let set_ops = [CmpOp::Eq, CmpOp::Ne];
+        let check_op = |allowed: &[CmpOp]| {
+            if allowed.contains(&op) {
+                Ok(())
+            } else {
+                Err(field_error(format!("operator not supported for `{field}`")))
+            }
+        };
+
+        match field.to_ascii_lowercase().as_str() {
+            "status" => {
+                check_op(&text_ops)?;
+                Ok(Predicate::Status(op, self.string()?))
+            }
+            "email" => {
+                check_op(&text_ops)?;
+                Ok(Predicate::Email(op, self.string()?))
+            }
+            "name" => {
+                check_op(&text_ops)?;
+                Ok(Predicate::Name(op, self.string()?))
+            }
+            "tag" => {
+                check_op(&set_ops)?;
+                Ok(Predicate::Tag(op, self.string()?))
+            }
+            "list" => {
+                check_op(&set_ops)?;
+                Ok(Predicate::List(op, self.string()?))
+            }
+            "subscribed_at" => {
+                check_op(&[CmpOp::Eq, CmpOp::Ne, CmpOp::Lt, CmpOp::Le, CmpOp::Gt, CmpOp::Ge])?;
+                Ok(Predicate::SubscribedAt(op, self.time()?))
+            }
+            _ => match field.split_once('.') {
+                Some((prefix, key)) if prefix.eq_ignore_ascii_case("attributes") && !key.is_empty() => {
+                    check_op(&text_ops)?;
+                    let value = self.json_value()?;
+                    if op == CmpOp::Contains && !value.is_string() {
+                        return Err(field_error("`~` needs a string".to_string()));
+                    }
+                    Ok(Predicate::Attribute(key.to_string(), op, value))
+                }
+                _ => Err(field_error(format!("unknown field `{field}`"))),
+            },
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        match self.next() {
+            Some(Token::Str(s)) => Ok(s),
+            _ => {
+                self.pos -= 1;
+                self.error("expected a string")
+            }
+        }
+    }
+
+    fn json_value(&mut self) -> Result<serde_json::Value, ParseError> {
+        match self.next() {
+            Some(Token::Str(s)) => Ok(serde_json::Value::String(s)),
+            Some(Token::Number(n)) => Ok(serde_json::Value::Number(n)),
+            Some(Token::Ident(ident)) if ident.eq_ignore_ascii_case("true") => Ok(true.into()),
+            Some(Token::Ident(ident)) if ident.eq_ignore_ascii_case("false") => Ok(false.into()),
+            _ => {
+                self.pos -= 1;
+                self.error("expected a string, number or boolean")
+            }
+        }
+    }
+
+    fn time(&mut self) -> Result<TimeValue, ParseError> {
+        if self.eat_keyword("now") {
+            if self.peek() != Some(&Token::Minus) {
+                return Ok(TimeValue::Ago(Duration::zero()));
+            }
+            self.pos += 1;
+            return match self.next() {
+                Some(Token::Duration(d)) if Utc::now().checked_sub_signed(d).is_some() => {
+                    Ok(TimeValue::Ago(d))
+                }
+                Some(Token::Duration(_)) => {
+                    self.pos -= 1;
+                    self.error("duration is out of range")
+                }
+                _ => {
+                    self.pos -= 1;
+                    self.error("expected a duration such as `90d`")
+                }
+            };
+        }
+        let s = self.string()?;
+        if let Ok(t) = DateTime::parse_from_rfc3339(&s) {
+            return Ok(TimeValue::At(t.with_timezone(&Utc)));
+        }
+        match NaiveDate::parse_from_str(&s, "%Y-%m-%d") {
+            Ok(date) => Ok(TimeValue::At(date.and_time(Default::default()).and_utc())),
+            Err(_) => {
+                self.pos -= 1;
+                self.error("expected `now - <duration>`, an RFC 3339 timestamp or a YYYY-MM-DD date")
+            }
+        }
+    }
+}
+
+/// Parses a segment expression.
+pub fn parse(input: &str) -> Result<Expr, ParseError> {
+    if input.len() > MAX_EXPRESSION_LEN {
+        return Err(ParseError {
+            position: MAX_EXPRESSION_LEN,
+            message: "expression is too long".to_string(),
+        });
+    }
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        pos: 0,
+        end: input.len(),
+        depth: 0,
+    };
+    if parser.peek().is_none() {
+        return parser.error("empty expression");
+    }
+    let expr = parser.expr()?;
+    if parser.peek().is_some() {
+        return parser.error("unexpected trailing input");
+    }
+    Ok(expr)
+}
+
+/// An `ILIKE` pattern matching `s` anywhere, with the wildcards in `s` escaped.
+pub(crate) fn like_pattern(s: &str) -> String {
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{escaped}%")
+}
+
+impl Expr {
+    /// Appends this expression as a boolean SQL condition over the `subscriptions` table.
+    pub fn push_sql(&self, qb: &mut QueryBuilder<'_, Postgres>) {
+        match self {
+            Expr::And(lhs, rhs) | Expr::Or(lhs, rhs) => {
+                qb.push("(");
+                lhs.push_sql(qb);
+                qb.push(if matches!(self, Expr::And(..)) { " AND " } else { " OR " });
+                rhs.push_sql(qb);
+                qb.push(")");
+            }
+            Expr::Not(expr) => {
+                qb.push("(NOT ");
+                expr.push_sql(qb);
+                qb.push(")");
+            }
+            Expr::Predicate(predicate) => predicate.push_sql(qb),
+        }
+    }
+}
+
+impl Predicate {
+    fn push_sql(&self, qb: &mut QueryBuilder<'_, Postgres>) {
+        let push_text = |qb: &mut QueryBuilder<'_, Postgres>, column: &str, op: &CmpOp, value: &str| {
+            qb.push(format!("subscriptions.{column} {} ", op.as_sql()));
+            if *op == CmpOp::Contains {
+                qb.push_bind(like_pattern(value));
+            } else {
+                qb.push_bind(value.to_string());
+            }
+        };
+        match self {
+            Predicate::Status(op, value) => push_text(qb, "status", op, value),
+            Predicate::Email(op, value) => push_text(qb, "email", op, value),
+            Predicate::Name(op, value) => push_text(qb, "name", op, value),
+            Predicate::SubscribedAt(op, value) => {
+                let at = match value {
+                    TimeValue::Ago(d) => Utc::now()
+                        .checked_sub_signed(*d)
+                        .unwrap_or(DateTime::<Utc>::MIN_UTC),
+                    TimeValue::At(t) => *t,
+                };
+                qb.push(format!("subscriptions.subscribed_at {} ", op.as_sql()));
+                qb.push_bind(at);
+            }
+            Predicate::Tag(op, tag) => {
+                if *op == CmpOp::Ne {
+                    qb.push("NOT ");
+                }
+                qb.push("EXISTS (SELECT 1 FROM subscriber_tags t WHERE t.subscriber_uuid = subscriptions.id AND t.tag = ");
+                qb.push_bind(tag.clone());
+                qb.push(")");
+            }
+            Predicate::List(op, list) => {
+                if *op == CmpOp::Ne {
+                    qb.push("NOT ");
+                }
+                qb.push(
+                    "EXISTS (SELECT 1 FROM list_memberships m JOIN lists l ON l.id = m.list_id \
+                    WHERE m.subscriber_uuid = subscriptions.id AND m.status = 'confirmed' AND l.name = ",
+                );
+                qb.push_bind(list.clone());
+                qb.push(")");
+            }
+            Predicate::Attribute(key, op, value) => match (op, value) {
+                (CmpOp::Contains, serde_json::Value::String(s)) => {
+                    qb.push("subscriptions.attributes ->> ");
+                    qb.push_bind(key.clone());
+                    qb.push(" ILIKE ");
+                    qb.push_bind(like_pattern(s));
+                }
+                _ => {
+                    qb.push("subscriptions.attributes -> ");
+                    qb.push_bind(key.clone());
+                    // a missing attribute counts as "not equal"
+                    qb.push(if *op == CmpOp::Ne { " IS DISTINCT FROM " } else { " = " });
+                    qb.push_bind(sqlx::types::Json(value.clone()));
+                    qb.push("::jsonb");
+                }
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Debug, sqlx::FromRow)]
+pub struct Recipient {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+}
+
+/// Number of subscribers matching `expr`.
+pub async fn count(pool: &PgPool, expr: &Expr) -> anyhow::Result<i64> {
+    let mut qb = QueryBuilder::new("SELECT count(*) FROM subscriptions WHERE ");
+    expr.push_sql(&mut qb);
+    let count: i64 = qb.build_query_scalar().fetch_one(pool).await?;
+    Ok(count)
+}
+
+/// Subscribers matching `expr`, ordered by email. `limit` caps the result for previews.
+pub async fn recipients(
+    pool: &PgPool,
+    expr: &Expr,
+    limit: Option<i64>,
+) -> anyhow::Result<Vec<Recipient>> {
+    let mut qb = QueryBuilder::new("SELECT id, email, name FROM subscriptions WHERE ");
+    expr.push_sql(&mut qb);
+    qb.push(" ORDER BY email");
+    if let Some(limit) = limit {
+        qb.push(" LIMIT ");
+        qb.push_bind(limit);
+    }
+    let recipients = qb.build_query_as().fetch_all(pool).await?;
+    Ok(recipients)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pred(p: Predicate) -> Expr {
+        Expr::Predicate(p)
+    }
+
+    #[test]
+    fn parses_the_example_expression() {
+        let expr = parse(
+            r#"status = "confirmed" and tag = "beta" and attributes.locale = "ja" and subscribed_at > now - 90d"#,
+        )
+        .unwrap();
+        let expected = Expr::And(
+            Box::new(Expr::And(
+                Box::new(Expr::And(
+                    Box::new(pred(Predicate::Status(CmpOp::Eq, "confirmed".into()))),
+                    Box::new(pred(Predicate::Tag(CmpOp::Eq, "beta".into()))),
+                )),
+                Box::new(pred(Predicate::Attribute(
+                    "locale".into(),
+                    CmpOp::Eq,
+                    "ja".into(),
+                ))),
+            )),
+            Box::new(pred(Predicate::SubscribedAt(
+                CmpOp::Gt,
+                TimeValue::Ago(Duration::days(90)),
+            ))),
+        );
+        assert_eq!(expr, expected);
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let expr = parse(r#"tag = "a" or tag = "b" and not tag = "c""#).unwrap();
+        let Expr::Or(_, rhs) = expr else {
+            panic!("expected `or` at the top level")
+        };
+        assert!(matches!(*rhs, Expr::And(_, ref r) if matches!(**r, Expr::Not(_))));
+    }
+
+    #[test]
+    fn parentheses_group_expressions() {
+        let expr = parse(r#"(tag = "a" or tag = "b") and list = "weekly""#).unwrap();
+        assert!(matches!(expr, Expr::And(ref l, _) if matches!(**l, Expr::Or(..))));
+    }
+
+    #[test]
+    fn parses_values_of_every_kind() {
+        assert_eq!(
+            parse("attributes.seats = 3").unwrap(),
+            pred(Predicate::Attribute("seats".into(), CmpOp::Eq, 3.into()))
+        );
+        assert_eq!(
+            parse("attributes.beta = true").unwrap(),
+            pred(Predicate::Attribute("beta".into(), CmpOp::Eq, true.into()))
+        );
+        assert_eq!(
+            parse(r#"subscribed_at < "2025-01-01""#).unwrap(),
+            pred(Predicate::SubscribedAt(
+                CmpOp::Lt,
+                TimeValue::At("2025-01-01T00:00:00Z".parse().unwrap())
+            ))
+        );
+        assert_eq!(
+            parse(r#"email ~ "example.com""#).unwrap(),
+            pred(Predicate::Email(CmpOp::Contains, "example.com".into()))
+        );
+        assert_eq!(
+            parse(r#"name = "say \"hi\"""#).unwrap(),
+            pred(Predicate::Name(CmpOp::Eq, r#"say "hi""#.into()))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        for (input, position) in [
+            ("", 0),
+            ("password = \"x\"", 0),
+            ("status > \"confirmed\"", 0),
+            ("tag ~ \"beta\"", 0),
+            ("status = confirmed", 9),
+            ("status = \"confirmed", 9),
+            ("(tag = \"a\"", 10),
+            ("tag = \"a\" tag = \"b\"", 10),
+            ("subscribed_at > now - 90", 22),
+            ("subscribed_at > now - 100000000d", 22),
+            ("subscribed_at > now - 100000000000d", 22),
+            ("status = \"a\"; drop table subscriptions", 12),
+        ] {
+            let err = parse(input).expect_err(input);
+            assert_eq!(err.position, position, "{input}: {err}");
+        }
+    }
+
+    #[test]
+    fn rejects_deeply_nested_expressions() {
+        let input = format!("{}tag = \"a\"{}", "(".repeat(100), ")".repeat(100));
+        assert!(parse(&input).is_err());
+    }
+
+    #[test]
+    fn compiles_to_parameterized_sql() {
+        let expr = parse(r#"email ~ "100%" and not (tag = "beta" or attributes.locale != "ja")"#)
+            .unwrap();
+        let mut qb = QueryBuilder::<Postgres>::new("");
+        expr.push_sql(&mut qb);
+        assert_eq!(
+            qb.sql(),
+            "(subscriptions.email ILIKE $1 AND (NOT (EXISTS (SELECT 1 FROM subscriber_tags t \
+            WHERE t.subscriber_uuid = subscriptions.id AND t.tag = $2) OR \
+            subscriptions.attributes -> $3 IS DISTINCT FROM $4::jsonb)))"
+        );
+        assert_eq!(like_pattern("100%"), "%100\\%%");
+    }
+}
@@ -0,0 +1,205 @@
+//! Links to act on a subscription (see [`LinkAction`]) verified without a database lookup.
+//!
+//! A link carries the subscriber id, the list id, an expiry and an HMAC-SHA256 over them and
+//! the action, keyed by one of `links.keys`. The key id travels with the link, so a new
+//! signing key can be introduced while links signed by the previous one keep working until
+//! that key is removed from the configuration.
+
+use crate::configuration::LinkSettings;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use sqlx::types::chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// What a link lets its holder do. A link or token issued for one action is rejected for
+/// the others.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkAction {
+    Confirm,
+    Unsubscribe,
+    /// open the preference center
+    Preferences,
+    /// export or erase the subscriber's data
+    Data,
+}
+
+impl LinkAction {
+    pub fn as_str(&self) -> &str {
+        match self {
+            LinkAction::Confirm => "confirm",
+            LinkAction::Unsubscribe => "unsubscribe",
+            LinkAction::Preferences => "preferences",
+            LinkAction::Data => "data",
+        }
+    }
+}
+
+/// Query parameters of a signed link. They are kept as strings so they can be flattened
+/// into other query structs, and parsed by [`verify`].
+#[derive(Deserialize, Debug)]
+pub struct SignedParameters {
+    sid: String,
+    #[serde(default)]
+    list: Option<String>,
+    exp: String,
+    kid: String,
+    sig: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum LinkError {
+    #[error("malformed link")]
+    Malformed,
+
+    #[error("unknown signing key")]
+    UnknownKey,
+
+    #[error("invalid signature")]
+    InvalidSignature,
+
+    #[error("the link has expired")]
+    Expired,
+
+    #[error("links cannot be signed: links.signing_key is not configured")]
+    NoSigningKey,
+}
+
+/// Signs a link and returns its query string, without the leading `?`.
+pub fn sign(
+    settings: &LinkSettings,
+    action: LinkAction,
+    subscriber_uuid: Uuid,
+    list_id: Option<Uuid>,
+    expires_at: DateTime<Utc>,
+) -> Result<String, LinkError> {
+    let secret = settings.signing_secret().ok_or(LinkError::NoSigningKey)?;
+    let exp = expires_at.timestamp();
+    let mac = mac(secret, action, subscriber_uuid, list_id, exp);
+    let sig = hex::encode(mac.finalize().into_bytes());
+    let list = list_id.map(|id| format!("&list={id}")).unwrap_or_default();
+    Ok(format!(
+        "sid={subscriber_uuid}{list}&exp={exp}&kid={}&sig={sig}",
+        settings.signing_key
+    ))
+}
+
+/// Checks the signature and expiry of a link for `action` and returns the subscriber
+/// and list it was issued for.
+pub fn verify(
+    settings: &LinkSettings,
+    action: LinkAction,
+    params: &SignedParameters,
+) -> Result<(Uuid, Option<Uuid>), LinkError> {
+    let subscriber_uuid = Uuid::parse_str(&params.sid).map_err(|_| LinkError::Malformed)?;
+    let list_id = params
+        .list
+        .as_deref()
+        .map(Uuid::parse_str)
+        .transpose()
+        .map_err(|_| LinkError::Malformed)?;
+    let exp: i64 = params.exp.parse().map_err(|_| LinkError::Malformed)?;
+    let sig = hex::decode(&params.sig).map_err(|_| LinkError::Malformed)?;
+    let secret = settings.keys.get(&params.kid).ok_or(LinkError::UnknownKey)?;
+
+    mac(secret, action, subscriber_uuid, list_id, exp)
+        .verify_slice(&sig)
+        .map_err(|_| LinkError::InvalidSignature)?;
+    if exp < Utc::now().timestamp() {
+        return Err(LinkError::Expired);
+    }
+    Ok((subscriber_uuid, list_id))
+}
+
+fn mac(
+    secret: &str,
+    action: LinkAction,
+    subscriber_uuid: Uuid,
+    list_id: Option<Uuid>,
+    exp: i64,
+) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    let list = list_id.map(|id| id.to_string()).unwrap_or_default();
+    mac.update(format!("{}\n{subscriber_uuid}\n{list}\n{exp}", action.as_str()).as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::LinkMode;
+    use chrono::Duration;
+
+    fn settings(signing_key: &str) -> LinkSettings {
+        LinkSettings {
+            mode: LinkMode::Signed,
+            signing_key: signing_key.to_string(),
+            keys: [("old", "old-secret"), ("new", "new-secret")]
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            expiry_hours: 1,
+            unsubscribe_expiry_days: 1,
+            request_interval_minutes: 10,
+        }
+    }
+
+    /// the values in signed query strings need no percent-decoding
+    fn parse(query: &str) -> SignedParameters {
+        let value: serde_json::Map<String, serde_json::Value> = query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(k, v)| (k.to_string(), serde_json::Value::from(v)))
+            .collect();
+        serde_json::from_value(value.into()).unwrap()
+    }
+
+    #[test]
+    fn signed_links_verify_until_they_expire() {
+        let settings = settings("new");
+        let (subscriber, list) = (Uuid::new_v4(), Some(Uuid::new_v4()));
+        let query = sign(&settings, LinkAction::Confirm, subscriber, list, Utc::now() + Duration::hours(1)).unwrap();
+        let params = parse(&query);
+        assert_eq!(verify(&settings, LinkAction::Confirm, &params).unwrap(), (subscriber, list));
+        assert!(matches!(
+            verify(&settings, LinkAction::Unsubscribe, &params),
+            Err(LinkError::InvalidSignature)
+        ));
+
+        let query = sign(&settings, LinkAction::Confirm, subscriber, None, Utc::now() - Duration::hours(1)).unwrap();
+        assert!(matches!(
+            verify(&settings, LinkAction::Confirm, &parse(&query)),
+            Err(LinkError::Expired)
+        ));
+    }
+
+    #[test]
+    fn links_signed_by_a_rotated_key_still_verify() {
+        let subscriber = Uuid::new_v4();
+        let expires_at = Utc::now() + Duration::hours(1);
+        let query = sign(&settings("old"), LinkAction::Unsubscribe, subscriber, None, expires_at).unwrap();
+        assert!(verify(&settings("new"), LinkAction::Unsubscribe, &parse(&query)).is_ok());
+
+        let mut retired = settings("new");
+        retired.keys.remove("old");
+        assert!(matches!(
+            verify(&retired, LinkAction::Unsubscribe, &parse(&query)),
+            Err(LinkError::UnknownKey)
+        ));
+    }
+
+    #[test]
+    fn tampered_links_are_rejected() {
+        let settings = settings("new");
+        let query = sign(&settings, LinkAction::Confirm, Uuid::new_v4(), None, Utc::now() + Duration::hours(1)).unwrap();
+        let mut params = parse(&query);
+        params.sid = Uuid::new_v4().to_string();
+        assert!(matches!(
+            verify(&settings, LinkAction::Confirm, &params),
+            Err(LinkError::InvalidSignature)
+        ));
+    }
+}
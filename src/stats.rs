@@ -0,0 +1,173 @@
+//! Delivery and engagement reports of newsletter issues.
+//!
+//! Counts are not computed from deliveries and events when a report is asked for: triggers
+//! keep `issue_delivery_counts` (deliveries per status) and `issue_event_counts` (events per
+//! quarter of an hour) up to date as they are written, so a report reads a few rows per issue.
+//! Every timezone offset is a multiple of 15 minutes, so the hours and days of any timezone
+//! are made of whole buckets. Opens and
+//! clicks count the deliveries opened or clicked by a person, as in [`crate::tracking`].
+//!
+//! Besides opens and clicks, `engagement_events` holds unsubscribes through an issue's link
+//! and the bounces and spam complaints the email API reports, see [`record_unsubscribe`] and
+//! [`record_report`].
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Interval {
+    #[default]
+    Hour,
+    Day,
+}
+
+impl Interval {
+    fn unit(self) -> &'static str {
+        match self {
+            Interval::Hour => "hour",
+            Interval::Day => "day",
+        }
+    }
+}
+
+/// What the email API reported about a delivered email.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Report {
+    Bounce,
+    Complaint,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct IssueStats {
+    pub counts: StatCounts,
+    pub interval: Interval,
+    /// days of the series start at midnight in this timezone
+    pub timezone: String,
+    /// oldest first; buckets without events are left out
+    pub series: Vec<StatBucket>,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct StatCounts {
+    /// waiting to be sent, including recipients held back by an A/B test
+    pub queued: i64,
+    pub sent: i64,
+    pub failed: i64,
+    pub bounced: i64,
+    pub complained: i64,
+    pub opened: i64,
+    pub clicked: i64,
+    pub unsubscribed: i64,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct StatBucket {
+    pub start: DateTime<Utc>,
+    pub sent: i64,
+    pub failed: i64,
+    pub bounced: i64,
+    pub complained: i64,
+    pub opened: i64,
+    pub clicked: i64,
+    pub unsubscribed: i64,
+}
+
+/// The report of `issue_id`, with the series in `interval`s of `timezone`.
+pub async fn issue_stats(
+    pool: &PgPool,
+    issue_id: Uuid,
+    interval: Interval,
+    timezone: &str,
+) -> anyhow::Result<IssueStats> {
+    let statuses = sqlx::query!(
+        r#"SELECT
+            COALESCE(SUM(count) FILTER (WHERE status IN ('queued', 'held')), 0)::bigint AS "queued!",
+            COALESCE(SUM(count) FILTER (WHERE status = 'sent'), 0)::bigint AS "sent!",
+            COALESCE(SUM(count) FILTER (WHERE status = 'failed'), 0)::bigint AS "failed!"
+        FROM issue_delivery_counts WHERE issue_id = $1"#,
+        issue_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("error counting deliveries")?;
+    let series = sqlx::query_as!(
+        StatBucket,
+        r#"SELECT date_trunc($2, bucket, $3) AS "start!",
+            COALESCE(SUM(count) FILTER (WHERE metric = 'sent'), 0)::bigint AS "sent!",
+            COALESCE(SUM(count) FILTER (WHERE metric = 'failed'), 0)::bigint AS "failed!",
+            COALESCE(SUM(count) FILTER (WHERE metric = 'bounced'), 0)::bigint AS "bounced!",
+            COALESCE(SUM(count) FILTER (WHERE metric = 'complained'), 0)::bigint AS "complained!",
+            COALESCE(SUM(count) FILTER (WHERE metric = 'opened'), 0)::bigint AS "opened!",
+            COALESCE(SUM(count) FILTER (WHERE metric = 'clicked'), 0)::bigint AS "clicked!",
+            COALESCE(SUM(count) FILTER (WHERE metric = 'unsubscribed'), 0)::bigint AS "unsubscribed!"
+        FROM issue_event_counts WHERE issue_id = $1
+        GROUP BY 1 ORDER BY 1"#,
+        issue_id,
+        interval.unit(),
+        timezone,
+    )
+    .fetch_all(pool)
+    .await
+    .context("error fetching event counts")?;
+
+    let total = |metric: fn(&StatBucket) -> i64| series.iter().map(metric).sum();
+    let counts = StatCounts {
+        queued: statuses.queued,
+        sent: statuses.sent,
+        failed: statuses.failed,
+        bounced: total(|b| b.bounced),
+        complained: total(|b| b.complained),
+        opened: total(|b| b.opened),
+        clicked: total(|b| b.clicked),
+        unsubscribed: total(|b| b.unsubscribed),
+    };
+    Ok(IssueStats {
+        counts,
+        interval,
+        timezone: timezone.to_string(),
+        series,
+    })
+}
+
+/// Attributes an unsubscribe to the delivery whose link was followed, if the delivery went to
+/// `subscriber_uuid`; the delivery id in the link is not signed.
+pub async fn record_unsubscribe(
+    executor: impl PgExecutor<'_>,
+    delivery_id: Uuid,
+    subscriber_uuid: Uuid,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"INSERT INTO engagement_events (delivery_id, kind, occurred_at)
+        SELECT id, 'unsubscribe', now() FROM issue_deliveries
+        WHERE id = $1 AND subscriber_uuid = $2"#,
+        delivery_id,
+        subscriber_uuid,
+    )
+    .execute(executor)
+    .await
+    .context("error recording unsubscribe")?;
+    Ok(())
+}
+
+/// Records a report of the email API about `delivery_id`. Returns `false` when the delivery
+/// does not exist.
+pub async fn record_report(pool: &PgPool, delivery_id: Uuid, report: Report) -> anyhow::Result<bool> {
+    let kind = match report {
+        Report::Bounce => "bounce",
+        Report::Complaint => "complaint",
+    };
+    let recorded = sqlx::query!(
+        r#"INSERT INTO engagement_events (delivery_id, kind, occurred_at)
+        SELECT id, $2, now() FROM issue_deliveries WHERE id = $1"#,
+        delivery_id,
+        kind,
+    )
+    .execute(pool)
+    .await
+    .context("error recording email report")?;
+    Ok(recorded.rows_affected() > 0)
+}
@@ -0,0 +1,314 @@
+//! Templates of the emails sent by the application.
+//!
+//! Every kind of email has templates in `application.templates_dir`, named after
+//! [`EmailTemplate::NAME`]: `<name>.subject.txt`, `<name>.html` and optionally `<name>.txt`.
+//! Without a `.txt` template the plain-text part is derived from the HTML when the email is
+//! sent (see [`crate::plain_text`]), so the two cannot disagree. Templates are rendered with
+//! minijinja; `.html` templates are auto-escaped. Using a variable the context does not
+//! define is an error, and [`EmailTemplates::load`] renders every kind with its example
+//! context, so a broken template stops the server from starting.
+//!
+//! A locale can override any of them with `<name>.<locale>.html` and so on; files
+//! missing for a locale fall back to the ones without a locale.
+//!
+//! Content written in Markdown (see [`crate::content`]) is wrapped in `layout.html`, which
+//! gets the subject, the optional preheader and the sanitized body as `content`.
+//!
+//! A kind can also be overridden from the database: the active version of the
+//! `email_templates` row with the same name, for the subscriber's locale or else without one,
+//! is rendered instead of the files. Stored versions are checked against the example context
+//! when they are saved.
+
+use minijinja::{Environment, ErrorKind, UndefinedBehavior, path_loader};
+use serde::{Deserialize, Serialize};
+use sqlx::PgExecutor;
+
+/// The context of one kind of email.
+pub trait EmailTemplate: Serialize {
+    /// stem of the template file names
+    const NAME: &'static str;
+
+    /// a context with every field set, used to check the templates at startup
+    fn example() -> Self;
+}
+
+/// Sent on subscription with the link confirming it.
+#[derive(Serialize, Debug)]
+pub struct ConfirmationEmail<'a> {
+    pub name: &'a str,
+    pub confirmation_link: &'a str,
+}
+
+impl EmailTemplate for ConfirmationEmail<'_> {
+    const NAME: &'static str = "confirmation";
+
+    fn example() -> Self {
+        ConfirmationEmail {
+            name: "subscriber",
+            confirmation_link: "https://example.com/subscription/confirm?token=token",
+        }
+    }
+}
+
+/// Sent on request with links to the preference center and to the subscriber's data.
+#[derive(Serialize, Debug)]
+pub struct ManageSubscriptionEmail<'a> {
+    pub name: &'a str,
+    pub preferences_link: &'a str,
+    pub data_link: &'a str,
+}
+
+impl EmailTemplate for ManageSubscriptionEmail<'_> {
+    const NAME: &'static str = "manage_subscription";
+
+    fn example() -> Self {
+        ManageSubscriptionEmail {
+            name: "subscriber",
+            preferences_link: "https://example.com/preferences?token=token",
+            data_link: "https://example.com/gdpr/data?token=token",
+        }
+    }
+}
+
+/// Context of the kind `name` filled with example values, or `None` for an unknown kind.
+pub fn example_context(name: &str) -> Option<serde_json::Value> {
+    let context = match name {
+        ConfirmationEmail::NAME => serde_json::to_value(ConfirmationEmail::example()),
+        ManageSubscriptionEmail::NAME => serde_json::to_value(ManageSubscriptionEmail::example()),
+        _ => return None,
+    };
+    Some(context.expect("email contexts serialize to JSON"))
+}
+
+/// Context of `layout.html`.
+#[derive(Serialize, Debug)]
+pub struct LayoutContext<'a> {
+    pub subject: &'a str,
+    /// preview text shown by mail clients next to the subject, hidden in the body
+    pub preheader: Option<&'a str>,
+    /// sanitized HTML, inserted without escaping
+    pub content: &'a str,
+}
+
+const LAYOUT: &str = "layout.html";
+
+/// The templates of one email kind, as stored in `email_template_versions`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TemplateSource {
+    pub subject: String,
+    pub html: String,
+    /// derived from `html` when sent if `None`
+    pub text: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct RenderedEmail {
+    pub subject: String,
+    pub html: String,
+    /// `None` when the kind has no text template
+    pub text: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("error rendering template {name}: {source:#}")]
+pub struct TemplateError {
+    name: String,
+    source: minijinja::Error,
+}
+
+#[derive(Debug)]
+pub struct EmailTemplates {
+    env: Environment<'static>,
+}
+
+impl EmailTemplates {
+    /// Loads the templates from `dir` and checks every kind of email renders in each of
+    /// `locales`.
+    pub fn load(dir: &str, locales: &[String]) -> Result<Self, TemplateError> {
+        let mut env = Environment::new();
+        env.set_loader(path_loader(dir));
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+        let templates = EmailTemplates { env };
+        for locale in locales.iter().map(|l| Some(l.as_str())).chain([None]) {
+            templates.check::<ConfirmationEmail>(locale)?;
+            templates.check::<ManageSubscriptionEmail>(locale)?;
+        }
+        templates.render_layout(&LayoutContext {
+            subject: "subject",
+            preheader: Some("preheader"),
+            content: "<p>content</p>",
+        })?;
+        Ok(templates)
+    }
+
+    /// Renders the active stored version of `T` if there is one, the files otherwise.
+    pub async fn render_current<T: EmailTemplate>(
+        &self,
+        executor: impl PgExecutor<'_>,
+        locale: Option<&str>,
+        context: &T,
+    ) -> anyhow::Result<RenderedEmail> {
+        let stored = sqlx::query_as!(
+            TemplateSource,
+            r#"SELECT v.subject, v.html, v.text
+            FROM email_templates t
+            JOIN email_template_versions v ON v.template_id = t.id AND v.version = t.active_version
+            WHERE t.name = $1 AND (t.locale = $2 OR t.locale IS NULL)
+            ORDER BY t.locale IS NULL
+            LIMIT 1"#,
+            T::NAME,
+            locale,
+        )
+        .fetch_optional(executor)
+        .await?;
+        let email = match stored {
+            Some(source) => self.render_source(T::NAME, &source, context)?,
+            None => self.render(locale, context)?,
+        };
+        Ok(email)
+    }
+
+    /// Renders stored templates of the kind `name`, with the same escaping as the files.
+    pub fn render_source(
+        &self,
+        name: &str,
+        source: &TemplateSource,
+        context: &impl Serialize,
+    ) -> Result<RenderedEmail, TemplateError> {
+        let render = |file: String, template: &str| {
+            self.env
+                .render_named_str(&file, template, context)
+                .map_err(|source| TemplateError { name: file, source })
+        };
+        Ok(RenderedEmail {
+            subject: render(format!("{name}.subject.txt"), &source.subject)?
+                .trim()
+                .to_string(),
+            html: render(format!("{name}.html"), &source.html)?,
+            text: source
+                .text
+                .as_deref()
+                .map(|text| render(format!("{name}.txt"), text))
+                .transpose()?,
+        })
+    }
+
+    /// Renders the file templates of `T` for `locale`.
+    pub fn render<T: EmailTemplate>(
+        &self,
+        locale: Option<&str>,
+        context: &T,
+    ) -> Result<RenderedEmail, TemplateError> {
+        let subject = self.render_file(self.file_name(T::NAME, "subject.txt", locale), context)?;
+        let text_file = self.file_name(T::NAME, "txt", locale);
+        let text = match self.env.get_template(&text_file) {
+            Err(e) if e.kind() == ErrorKind::TemplateNotFound => None,
+            _ => Some(self.render_file(text_file, context)?),
+        };
+        Ok(RenderedEmail {
+            // a trailing newline in the file is not part of the subject
+            subject: subject.trim().to_string(),
+            html: self.render_file(self.file_name(T::NAME, "html", locale), context)?,
+            text,
+        })
+    }
+
+    /// Wraps HTML content in `layout.html`.
+    pub fn render_layout(&self, context: &LayoutContext) -> Result<String, TemplateError> {
+        self.env
+            .get_template(LAYOUT)
+            .and_then(|template| template.render(context))
+            .map_err(|source| TemplateError {
+                name: LAYOUT.to_string(),
+                source,
+            })
+    }
+
+    fn check<T: EmailTemplate>(&self, locale: Option<&str>) -> Result<(), TemplateError> {
+        self.render(locale, &T::example()).map(|_| ())
+    }
+
+    /// The file for `locale` if there is one, the one without a locale otherwise.
+    fn file_name(&self, name: &str, extension: &str, locale: Option<&str>) -> String {
+        if let Some(locale) = locale {
+            let localized = format!("{name}.{locale}.{extension}");
+            match self.env.get_template(&localized) {
+                Err(e) if e.kind() == ErrorKind::TemplateNotFound => {}
+                _ => return localized,
+            }
+        }
+        format!("{name}.{extension}")
+    }
+
+    fn render_file(&self, file: String, context: &impl Serialize) -> Result<String, TemplateError> {
+        self.env
+            .get_template(&file)
+            .and_then(|template| template.render(context))
+            .map_err(|source| TemplateError { name: file, source })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn html_is_escaped() {
+        let templates = EmailTemplates::load("templates", &[]).unwrap();
+        let email = templates
+            .render(None, &ConfirmationEmail {
+                name: "<b>Ada</b>",
+                confirmation_link: "https://example.com/?a=1&b=2",
+            })
+            .unwrap();
+        assert_eq!(email.subject, "Welcome!");
+        assert!(email.html.contains("&lt;b&gt;Ada&lt;&#x2f;b&gt;"), "{}", email.html);
+        assert!(email.html.contains("a=1&amp;b=2"));
+        assert_eq!(email.text, None);
+    }
+
+    #[test]
+    fn missing_or_broken_templates_fail_to_load() {
+        assert!(EmailTemplates::load("does-not-exist", &[]).is_err());
+
+        let dir = std::env::temp_dir().join(format!("templates-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        for (file, content) in [
+            ("confirmation.subject.txt", "Welcome"),
+            ("confirmation.html", "{{ confirmation_link }}"),
+            ("confirmation.txt", "{{ unknown_variable }}"),
+        ] {
+            std::fs::write(dir.join(file), content).unwrap();
+        }
+        let err = EmailTemplates::load(dir.to_str().unwrap(), &[]).unwrap_err();
+        assert!(err.to_string().contains("confirmation.txt"), "{err}");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn localized_templates_fall_back_per_file() {
+        let dir = std::env::temp_dir().join(format!("templates-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        for (file, content) in [
+            ("confirmation.subject.txt", "Welcome"),
+            ("confirmation.ja.subject.txt", "ようこそ"),
+            ("confirmation.html", "{{ confirmation_link }}"),
+            ("confirmation.txt", "{{ name }}"),
+            ("manage_subscription.subject.txt", "Links"),
+            ("manage_subscription.html", "{{ preferences_link }} {{ data_link }}"),
+            ("layout.html", "{{ content|safe }}"),
+        ] {
+            std::fs::write(dir.join(file), content).unwrap();
+        }
+        let locales = ["en".to_string(), "ja".to_string()];
+        let templates = EmailTemplates::load(dir.to_str().unwrap(), &locales).unwrap();
+        let context = ConfirmationEmail::example();
+        assert_eq!(templates.render(Some("ja"), &context).unwrap().subject, "ようこそ");
+        assert_eq!(templates.render(Some("en"), &context).unwrap().subject, "Welcome");
+        assert_eq!(templates.render(Some("ja"), &context).unwrap().text.as_deref(), Some("subscriber"));
+
+        std::fs::write(dir.join("confirmation.ja.txt"), "{{ missing }}").unwrap();
+        assert!(EmailTemplates::load(dir.to_str().unwrap(), &locales).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,85 @@
+//! Tokens mailed to subscribers in confirmation and preference links.
+//!
+//! A token is 256 bits from the OS random source. Only its SHA-256 is stored, so the
+//! `subscription_tokens` table cannot be used to act on behalf of a subscriber. It is stored
+//! with the [`LinkAction`] it was mailed for and an expiry, and accepted for nothing else.
+//!
+//! [`link_query`] issues a link in the configured `links.mode`: a stored token, or a signed
+//! link (see [`crate::signed_link`]).
+
+use crate::configuration::{LinkMode, LinkSettings};
+use crate::signed_link::{self, LinkAction};
+use anyhow::Context;
+use rand::TryRngCore;
+use rand::rngs::OsRng;
+use sha2::{Digest, Sha256};
+use sqlx::PgExecutor;
+use sqlx::types::chrono::Utc;
+use uuid::Uuid;
+
+const TOKEN_BYTES: usize = 32;
+
+/// A new random token, hex encoded.
+pub fn generate() -> String {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    OsRng
+        .try_fill_bytes(&mut bytes)
+        .expect("error reading from the OS random source");
+    hex::encode(bytes)
+}
+
+/// The form a token is stored and looked up in.
+pub fn hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// The query string, without the leading `?`, of a link for `action` on `subscriber_uuid`
+/// and `list_id`. In token mode `executor` stores the token.
+pub async fn link_query(
+    links: &LinkSettings,
+    executor: impl PgExecutor<'_>,
+    action: LinkAction,
+    subscriber_uuid: Uuid,
+    list_id: Option<Uuid>,
+) -> anyhow::Result<String> {
+    let expires_at = Utc::now() + links.expiry_of(action);
+    match links.mode {
+        LinkMode::Token => {
+            let token = generate();
+            sqlx::query!(
+                r#"INSERT INTO subscription_tokens (subscription_token, subscriber_uuid, list_id, action, expires_at)
+                VALUES ($1, $2, $3, $4, $5)"#,
+                hash(&token),
+                subscriber_uuid,
+                list_id,
+                action.as_str(),
+                expires_at,
+            )
+            .execute(executor)
+            .await
+            .with_context(|| format!("error storing {} token", action.as_str()))?;
+            Ok(format!("token={token}"))
+        }
+        LinkMode::Signed => Ok(signed_link::sign(
+            links,
+            action,
+            subscriber_uuid,
+            list_id,
+            expires_at,
+        )?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_are_random_and_hashed_deterministically() {
+        let token = generate();
+        assert_eq!(token.len(), 2 * TOKEN_BYTES);
+        assert_ne!(token, generate());
+        assert_eq!(hash(&token), hash(&token));
+        assert_ne!(hash(&token), token);
+    }
+}
@@ -0,0 +1,447 @@
+//! Engagement tracking of scheduled issues.
+//!
+//! With `tracking.opens` on, every delivery's HTML body ends with a 1x1 image at
+//! `/t/o/<token>.gif`. The token names the delivery and is signed with one of `links.keys`,
+//! so opens cannot be recorded for deliveries that were never sent. Tokens do not expire.
+//!
+//! Loading the image records an open in `engagement_events`, at most once per delivery.
+//! Loads that look automated are recorded separately with the reason, see [`machine_open`]:
+//! Apple Mail Privacy Protection fetches every image when the message arrives, security
+//! scanners and link checkers fetch them within seconds of delivery, and crawlers say so in
+//! their user agent. Open counts only include opens by people.
+//!
+//! With `tracking.clicks` on, links in the HTML body to other sites point to
+//! `/t/c/<token>` instead, see [`rewrite_links`]. The token carries the destination and is
+//! signed the same way, so the redirect cannot be used to send people anywhere else. Every
+//! click is recorded with the link, then the reader is sent on, with `tracking.utm` added.
+
+use crate::configuration::{LinkSettings, UtmSettings};
+use crate::signed_link::LinkError;
+use ammonia::Url;
+use anyhow::Context;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use hmac::{Hmac, Mac};
+use regex::Regex;
+use serde::Serialize;
+use sha2::Sha256;
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool};
+use std::net::IpAddr;
+use std::sync::LazyLock;
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// A transparent 1x1 GIF.
+pub const PIXEL: [u8; 43] = [
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00,
+    0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+/// Loads this soon after the message was handed to the email API are taken for scanners.
+pub const PREFETCH_SECONDS: i64 = 5;
+
+/// User agent fragments of crawlers and HTTP libraries, compared in lowercase.
+const BOT_AGENTS: [&str; 10] = [
+    "bot", "crawler", "spider", "curl/", "wget/", "python-requests", "go-http-client",
+    "headlesschrome", "java/", "okhttp",
+];
+
+/// `href` attributes of links, with the value in the second or third group.
+static LINK_HREF: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?i)<a\s[^>]*?\bhref\s*=\s*(?:"([^"]*)"|'([^']*)')"#).expect("valid regex")
+});
+
+/// A delivery events are recorded for.
+#[derive(Debug, Clone, Copy)]
+pub struct TrackedDelivery {
+    pub id: Uuid,
+    pub issue_id: Uuid,
+    pub sent_at: Option<DateTime<Utc>>,
+}
+
+/// Opens of one issue's deliveries.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenCounts {
+    pub sent: i64,
+    /// deliveries opened by a person at least once
+    pub opened: i64,
+    /// deliveries with only automated opens
+    pub machine_opened: i64,
+}
+
+/// Clicks of one issue's links by people.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ClickCounts {
+    /// deliveries with at least one click
+    pub clicked: i64,
+    /// most clicked first
+    pub links: Vec<LinkClicks>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct LinkClicks {
+    pub url: String,
+    pub clicks: i64,
+    /// deliveries the link was clicked in
+    pub recipients: i64,
+}
+
+/// The signed token naming `delivery_id` in pixel URLs.
+pub fn open_token(links: &LinkSettings, delivery_id: Uuid) -> Result<String, LinkError> {
+    let secret = links.signing_secret().ok_or(LinkError::NoSigningKey)?;
+    let sig = hex::encode(mac(secret, &format!("open\n{delivery_id}")).finalize().into_bytes());
+    Ok(format!("{}.{sig}.{}", delivery_id.simple(), links.signing_key))
+}
+
+/// The delivery an open token was issued for.
+pub fn verify_open_token(links: &LinkSettings, token: &str) -> Result<Uuid, LinkError> {
+    let (id, rest) = token.split_once('.').ok_or(LinkError::Malformed)?;
+    let (sig, kid) = rest.split_once('.').ok_or(LinkError::Malformed)?;
+    let delivery_id = Uuid::parse_str(id).map_err(|_| LinkError::Malformed)?;
+    let sig = hex::decode(sig).map_err(|_| LinkError::Malformed)?;
+    let secret = links.keys.get(kid).ok_or(LinkError::UnknownKey)?;
+    mac(secret, &format!("open\n{delivery_id}"))
+        .verify_slice(&sig)
+        .map_err(|_| LinkError::InvalidSignature)?;
+    Ok(delivery_id)
+}
+
+/// The signed token naming `delivery_id` and the destination `url` in click URLs.
+pub fn click_token(links: &LinkSettings, delivery_id: Uuid, url: &str) -> Result<String, LinkError> {
+    let secret = links.signing_secret().ok_or(LinkError::NoSigningKey)?;
+    let sig = mac(secret, &format!("click\n{delivery_id}\n{url}")).finalize().into_bytes();
+    Ok(format!(
+        "{}.{}.{}.{}",
+        delivery_id.simple(),
+        URL_SAFE_NO_PAD.encode(url),
+        hex::encode(sig),
+        links.signing_key
+    ))
+}
+
+/// The delivery and destination a click token was issued for.
+pub fn verify_click_token(links: &LinkSettings, token: &str) -> Result<(Uuid, String), LinkError> {
+    let mut parts = token.splitn(4, '.');
+    let (Some(id), Some(url), Some(sig), Some(kid)) = (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(LinkError::Malformed);
+    };
+    let delivery_id = Uuid::parse_str(id).map_err(|_| LinkError::Malformed)?;
+    let url = URL_SAFE_NO_PAD
+        .decode(url)
+        .ok()
+        .and_then(|url| String::from_utf8(url).ok())
+        .ok_or(LinkError::Malformed)?;
+    let sig = hex::decode(sig).map_err(|_| LinkError::Malformed)?;
+    let secret = links.keys.get(kid).ok_or(LinkError::UnknownKey)?;
+    mac(secret, &format!("click\n{delivery_id}\n{url}"))
+        .verify_slice(&sig)
+        .map_err(|_| LinkError::InvalidSignature)?;
+    Ok((delivery_id, url))
+}
+
+fn mac(secret: &str, message: &str) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(message.as_bytes());
+    mac
+}
+
+/// `html` with an image loading `pixel_url` at the end of its body.
+pub fn add_pixel(html: &str, pixel_url: &str) -> String {
+    let pixel = format!(
+        r#"<img src="{pixel_url}" width="1" height="1" alt="" style="display:block;border:0">"#
+    );
+    match html.rfind("</body>") {
+        Some(end) => format!("{}{pixel}{}", &html[..end], &html[end..]),
+        None => format!("{html}{pixel}"),
+    }
+}
+
+/// `html` with the destination of every link to an http(s) URL outside `base_url` replaced by
+/// `redirect(url)`. Links to the app itself, such as unsubscribe links, are kept.
+pub fn rewrite_links(
+    html: &str,
+    base_url: &str,
+    mut redirect: impl FnMut(&str) -> Result<String, LinkError>,
+) -> Result<String, LinkError> {
+    let base_url = base_url.trim_end_matches('/');
+    let mut rewritten = String::with_capacity(html.len());
+    let mut copied = 0;
+    for captures in LINK_HREF.captures_iter(html) {
+        let href = captures.get(1).or(captures.get(2)).expect("one of the groups matches");
+        let url = href.as_str().trim().replace("&amp;", "&");
+        let scheme = url.split_once("://").map(|(scheme, _)| scheme.to_ascii_lowercase());
+        let own = url
+            .strip_prefix(base_url)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with(['/', '?', '#']));
+        if own || !matches!(scheme.as_deref(), Some("http" | "https")) {
+            continue;
+        }
+        rewritten.push_str(&html[copied..href.start()]);
+        rewritten.push_str(&redirect(&url)?);
+        copied = href.end();
+    }
+    rewritten.push_str(&html[copied..]);
+    Ok(rewritten)
+}
+
+/// `url` with the `utm_*` parameters of `utm` it does not have yet. The campaign defaults to
+/// `issue_id`. URLs that cannot be parsed are returned as they are.
+pub fn with_utm(url: &str, utm: &UtmSettings, issue_id: Uuid) -> String {
+    let Ok(mut parsed) = Url::parse(url) else {
+        return url.to_string();
+    };
+    let campaign = utm.campaign.clone().unwrap_or_else(|| issue_id.to_string());
+    let missing: Vec<(&str, &str)> = [
+        ("utm_source", utm.source.as_str()),
+        ("utm_medium", utm.medium.as_str()),
+        ("utm_campaign", campaign.as_str()),
+    ]
+    .into_iter()
+    .filter(|(name, _)| !parsed.query_pairs().any(|(existing, _)| existing == *name))
+    .collect();
+    if !missing.is_empty() {
+        parsed.query_pairs_mut().extend_pairs(missing);
+    }
+    parsed.to_string()
+}
+
+/// Why an image load or click looks automated, or `None` if a person likely caused it.
+pub fn machine_reason(
+    user_agent: Option<&str>,
+    ip_address: Option<&str>,
+    sent_at: Option<DateTime<Utc>>,
+    opened_at: DateTime<Utc>,
+) -> Option<&'static str> {
+    let user_agent = user_agent.map(str::trim).unwrap_or_default();
+    // Apple's proxy sends a bare user agent and fetches from Apple's 17.0.0.0/8
+    let apple_network = ip_address
+        .and_then(|ip| ip.parse::<IpAddr>().ok())
+        .is_some_and(|ip| matches!(ip, IpAddr::V4(v4) if v4.octets()[0] == 17));
+    if user_agent == "Mozilla/5.0" || apple_network {
+        return Some("apple_mpp");
+    }
+    let lowercase = user_agent.to_lowercase();
+    if user_agent.is_empty() || BOT_AGENTS.iter().any(|bot| lowercase.contains(bot)) {
+        return Some("bot");
+    }
+    if sent_at.is_some_and(|sent_at| (opened_at - sent_at).num_seconds() < PREFETCH_SECONDS) {
+        return Some("prefetch");
+    }
+    None
+}
+
+pub async fn find_delivery(pool: &PgPool, delivery_id: Uuid) -> anyhow::Result<Option<TrackedDelivery>> {
+    sqlx::query_as!(
+        TrackedDelivery,
+        "SELECT id, issue_id, sent_at FROM issue_deliveries WHERE id = $1",
+        delivery_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("error fetching delivery")
+}
+
+/// Records an open of `delivery` unless one of the same kind, by a person or automated, is
+/// already recorded.
+pub async fn record_open(
+    pool: &PgPool,
+    delivery: &TrackedDelivery,
+    user_agent: Option<&str>,
+    ip_address: Option<&str>,
+) -> anyhow::Result<()> {
+    let now = Utc::now();
+    let reason = machine_reason(user_agent, ip_address, delivery.sent_at, now);
+    sqlx::query!(
+        r#"INSERT INTO engagement_events (delivery_id, kind, occurred_at, user_agent, machine_reason)
+        VALUES ($1, 'open', $2, $3, $4)
+        ON CONFLICT (delivery_id, (machine_reason IS NULL)) WHERE kind = 'open' DO NOTHING"#,
+        delivery.id,
+        now,
+        user_agent,
+        reason,
+    )
+    .execute(pool)
+    .await
+    .context("error recording open")?;
+    Ok(())
+}
+
+/// Records a click of `url` in the email of `delivery`. Every click is kept.
+pub async fn record_click(
+    pool: &PgPool,
+    delivery: &TrackedDelivery,
+    url: &str,
+    user_agent: Option<&str>,
+    ip_address: Option<&str>,
+) -> anyhow::Result<()> {
+    let now = Utc::now();
+    let reason = machine_reason(user_agent, ip_address, delivery.sent_at, now);
+    sqlx::query!(
+        r#"INSERT INTO engagement_events (delivery_id, kind, occurred_at, user_agent, machine_reason, url)
+        VALUES ($1, 'click', $2, $3, $4, $5)"#,
+        delivery.id,
+        now,
+        user_agent,
+        reason,
+        url,
+    )
+    .execute(pool)
+    .await
+    .context("error recording click")?;
+    Ok(())
+}
+
+pub async fn open_counts(executor: impl PgExecutor<'_>, issue_id: Uuid) -> anyhow::Result<OpenCounts> {
+    let counts = sqlx::query!(
+        r#"SELECT
+            COUNT(*) FILTER (WHERE d.status = 'sent') AS "sent!",
+            COUNT(*) FILTER (WHERE o.human) AS "opened!",
+            COUNT(*) FILTER (WHERE NOT o.human) AS "machine_opened!"
+        FROM issue_deliveries d
+        LEFT JOIN LATERAL (
+            SELECT bool_or(e.machine_reason IS NULL) AS human FROM engagement_events e
+            WHERE e.delivery_id = d.id AND e.kind = 'open'
+        ) o ON true
+        WHERE d.issue_id = $1"#,
+        issue_id
+    )
+    .fetch_one(executor)
+    .await
+    .context("error counting opens")?;
+    Ok(OpenCounts {
+        sent: counts.sent,
+        opened: counts.opened,
+        machine_opened: counts.machine_opened,
+    })
+}
+
+pub async fn click_counts(pool: &PgPool, issue_id: Uuid) -> anyhow::Result<ClickCounts> {
+    let links = sqlx::query_as!(
+        LinkClicks,
+        r#"SELECT e.url AS "url!", COUNT(*) AS "clicks!", COUNT(DISTINCT e.delivery_id) AS "recipients!"
+        FROM engagement_events e JOIN issue_deliveries d ON d.id = e.delivery_id
+        WHERE d.issue_id = $1 AND e.kind = 'click' AND e.machine_reason IS NULL AND e.url IS NOT NULL
+        GROUP BY e.url
+        ORDER BY 2 DESC, 1"#,
+        issue_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("error counting clicks")?;
+    let clicked = sqlx::query_scalar!(
+        r#"SELECT COUNT(DISTINCT e.delivery_id) AS "count!"
+        FROM engagement_events e JOIN issue_deliveries d ON d.id = e.delivery_id
+        WHERE d.issue_id = $1 AND e.kind = 'click' AND e.machine_reason IS NULL"#,
+        issue_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("error counting clicks")?;
+    Ok(ClickCounts { clicked, links })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use std::collections::HashMap;
+
+    fn links() -> LinkSettings {
+        LinkSettings {
+            mode: crate::configuration::LinkMode::Token,
+            signing_key: "k2".to_string(),
+            keys: HashMap::from([
+                ("k1".to_string(), "old secret".to_string()),
+                ("k2".to_string(), "new secret".to_string()),
+            ]),
+            expiry_hours: 1,
+            unsubscribe_expiry_days: 1,
+            request_interval_minutes: 10,
+        }
+    }
+
+    #[test]
+    fn open_tokens_are_signed() {
+        let links = links();
+        let delivery_id = Uuid::new_v4();
+        let token = open_token(&links, delivery_id).unwrap();
+        assert_eq!(verify_open_token(&links, &token).unwrap(), delivery_id);
+
+        let forged = token.replacen(&delivery_id.simple().to_string(), &Uuid::new_v4().simple().to_string(), 1);
+        assert!(matches!(verify_open_token(&links, &forged), Err(LinkError::InvalidSignature)));
+        let other_key = token.replace(".k2", ".k1");
+        assert!(matches!(verify_open_token(&links, &other_key), Err(LinkError::InvalidSignature)));
+        assert!(matches!(verify_open_token(&links, "x"), Err(LinkError::Malformed)));
+    }
+
+    #[test]
+    fn click_tokens_carry_the_signed_destination() {
+        let links = links();
+        let delivery_id = Uuid::new_v4();
+        let url = "https://example.org/a?b=c&d=e#f";
+        let token = click_token(&links, delivery_id, url).unwrap();
+        assert_eq!(verify_click_token(&links, &token).unwrap(), (delivery_id, url.to_string()));
+
+        let elsewhere = URL_SAFE_NO_PAD.encode("https://evil.example");
+        let parts: Vec<&str> = token.split('.').collect();
+        let forged = [parts[0], &elsewhere, parts[2], parts[3]].join(".");
+        assert!(matches!(verify_click_token(&links, &forged), Err(LinkError::InvalidSignature)));
+        assert!(matches!(verify_click_token(&links, "x.y"), Err(LinkError::Malformed)));
+    }
+
+    #[test]
+    fn outside_links_are_sent_through_the_redirect() {
+        let html = r#"<p><a href="https://example.org/?a=1&amp;b=2">x</a> <A class='c' HREF='http://example.org'>y</A>
+            <a href="https://app.test/unsubscribe?token=t">u</a> <a href="mailto:a@example.org">m</a></p>"#;
+        let rewritten =
+            rewrite_links(html, "https://app.test/", |url| Ok(format!("https://app.test/t/c/[{url}]"))).unwrap();
+        assert_eq!(
+            rewritten,
+            r#"<p><a href="https://app.test/t/c/[https://example.org/?a=1&b=2]">x</a> <A class='c' HREF='https://app.test/t/c/[http://example.org]'>y</A>
+            <a href="https://app.test/unsubscribe?token=t">u</a> <a href="mailto:a@example.org">m</a></p>"#
+        );
+    }
+
+    #[test]
+    fn utm_parameters_do_not_replace_the_links_own() {
+        let utm = UtmSettings { source: "newsletter".to_string(), medium: "email".to_string(), campaign: None };
+        let issue_id = Uuid::new_v4();
+        assert_eq!(
+            with_utm("https://example.org/p?utm_source=blog#top", &utm, issue_id),
+            format!("https://example.org/p?utm_source=blog&utm_medium=email&utm_campaign={issue_id}#top")
+        );
+        let utm = UtmSettings { campaign: Some("weekly".to_string()), ..utm };
+        assert_eq!(
+            with_utm("https://example.org", &utm, issue_id),
+            "https://example.org/?utm_source=newsletter&utm_medium=email&utm_campaign=weekly"
+        );
+    }
+
+    #[test]
+    fn automated_opens_are_told_apart() {
+        let sent_at = Utc::now() - Duration::hours(1);
+        let now = Utc::now();
+        let mail = Some("Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko)");
+        assert_eq!(machine_reason(mail, Some("203.0.113.9"), Some(sent_at), now), None);
+        assert_eq!(machine_reason(Some("Mozilla/5.0"), Some("203.0.113.9"), Some(sent_at), now), Some("apple_mpp"));
+        assert_eq!(machine_reason(mail, Some("17.58.1.2"), Some(sent_at), now), Some("apple_mpp"));
+        assert_eq!(machine_reason(Some("Googlebot-Image/1.0"), None, Some(sent_at), now), Some("bot"));
+        assert_eq!(machine_reason(None, None, Some(sent_at), now), Some("bot"));
+        assert_eq!(machine_reason(mail, None, Some(now - Duration::seconds(1)), now), Some("prefetch"));
+    }
+
+    #[test]
+    fn the_pixel_goes_at_the_end_of_the_body() {
+        let pixel = r#"<img src="u" width="1" height="1" alt="" style="display:block;border:0">"#;
+        assert_eq!(add_pixel("<p>hi</p>", "u"), format!("<p>hi</p>{pixel}"));
+        assert_eq!(
+            add_pixel("<html><body><p>hi</p></body></html>", "u"),
+            format!("<html><body><p>hi</p>{pixel}</body></html>")
+        );
+    }
+}
@@ -1,6 +1,5 @@
 use garde::Validate;
-use serde::Serialize;
-use crate::errors::AppError;
+use serde::{Deserialize, Deserializer, Serialize};
 
 
 #[derive(Validate, Clone, Serialize, Debug)]
@@ -15,4 +14,55 @@ impl ValidatedEmail {
         email.validate()?; // todo 7/17
         Ok(email)
     }
-}
\ No newline at end of file
+}
+
+/// A subscriber name, following the same rules as the subscription form.
+#[derive(Validate, Clone, Serialize, Debug)]
+pub struct ValidatedName(
+    #[garde(length(min = 1), alphanumeric)]
+    pub(crate) String
+);
+
+impl ValidatedName {
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        let name = ValidatedName(s.to_string());
+        name.validate()?;
+        Ok(name)
+    }
+}
+
+/// A subscriber tag such as `beta` or `plan-pro`.
+#[derive(Validate, Clone, Serialize, Debug)]
+pub struct ValidatedTag(
+    #[garde(length(min = 1, max = 64), pattern(r"^[A-Za-z0-9_\-]+$"))]
+    pub(crate) String
+);
+
+impl ValidatedTag {
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        let tag = ValidatedTag(s.to_string());
+        tag.validate()?;
+        Ok(tag)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Deserializes a form field holding a JSON object, e.g. `attributes={"plan":"pro"}`.
+/// An empty field is treated as absent.
+pub fn deserialize_json_object<'de, D>(
+    deserializer: D,
+) -> Result<Option<serde_json::Map<String, serde_json::Value>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let raw = Option::<String>::deserialize(deserializer)?;
+    match raw.as_deref().map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(raw) => serde_json::from_str(raw)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+    }
+}
@@ -0,0 +1,90 @@
+mod utils;
+
+use crate::utils::{TestAppInfo, spawn_app};
+use chrono::{Duration, Utc};
+use email_sender::cleanup::{PENDING_CLEANUP_LOCK, delete_stale_pending};
+use uuid::Uuid;
+
+async fn insert_subscriber(app: &TestAppInfo, email: &str, status: &str, days_ago: i64) -> Uuid {
+    let id = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO subscriptions (id, email, name, subscribed_at, status) VALUES ($1, $2, 'name', $3, $4)",
+        id,
+        email,
+        Utc::now() - Duration::days(days_ago),
+        status,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"INSERT INTO subscription_tokens (subscription_token, subscriber_uuid, action, expires_at)
+        VALUES ($1, $2, 'confirm', now() + interval '1 day')"#,
+        Uuid::new_v4().to_string(),
+        id,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    id
+}
+
+#[tokio::test]
+async fn cleanup_deletes_only_stale_pending_subscribers() {
+    let app = spawn_app().await.unwrap();
+    insert_subscriber(&app, "stale@example.com", "not-confirmed", 10).await;
+    insert_subscriber(&app, "fresh@example.com", "not-confirmed", 1).await;
+    insert_subscriber(&app, "confirmed@example.com", "confirmed", 10).await;
+
+    let removed = delete_stale_pending(&app.db_pool, Duration::days(7)).await.unwrap();
+    assert_eq!(removed, Some(1));
+
+    let remaining: Vec<_> = sqlx::query!("SELECT email FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.email)
+        .collect();
+    assert_eq!(remaining, ["confirmed@example.com", "fresh@example.com"]);
+    let tokens = sqlx::query!(r#"SELECT count(*) AS "count!" FROM subscription_tokens"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tokens.count, 2);
+}
+
+#[tokio::test]
+async fn cleanup_skips_while_another_replica_holds_the_lock() {
+    let app = spawn_app().await.unwrap();
+    insert_subscriber(&app, "stale@example.com", "not-confirmed", 10).await;
+
+    let mut other = app.db_pool.begin().await.unwrap();
+    sqlx::query!("SELECT pg_advisory_xact_lock($1)", PENDING_CLEANUP_LOCK)
+        .fetch_one(&mut *other)
+        .await
+        .unwrap();
+    let removed = delete_stale_pending(&app.db_pool, Duration::days(7)).await.unwrap();
+    assert_eq!(removed, None);
+    other.rollback().await.unwrap();
+
+    let removed = delete_stale_pending(&app.db_pool, Duration::days(7)).await.unwrap();
+    assert_eq!(removed, Some(1));
+}
+
+#[tokio::test]
+async fn cleanup_keeps_pending_subscribers_with_a_confirmed_membership() {
+    let app = spawn_app().await.unwrap();
+    let id = insert_subscriber(&app, "member@example.com", "not-confirmed", 10).await;
+    sqlx::query!(
+        r#"INSERT INTO list_memberships (list_id, subscriber_uuid, status, subscribed_at)
+        SELECT id, $1, 'confirmed', now() - interval '10 days' FROM lists WHERE name = 'default'"#,
+        id,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let removed = delete_stale_pending(&app.db_pool, Duration::days(7)).await.unwrap();
+    assert_eq!(removed, Some(0));
+}
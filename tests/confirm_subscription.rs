@@ -1,18 +1,7 @@
 mod utils;
 
-use std::cell::{Cell, RefCell};
-use std::rc::Rc;
-use std::sync::{Arc, Mutex};
-// use config::ValueKind::String;
-use std::string::String;
-use axum::routing::get;
-use regex::Regex;
-use serde_json::Value;
-use email_sender::handlers::subscription::subscribe;
 use crate::utils::spawn_app;
 
-use tower::ServiceExt;
-
 
 #[tokio::test]
 async fn confirmation_without_token_are_rejected_with_a_400() {
@@ -31,27 +20,16 @@ async fn confirmation_without_token_are_rejected_with_a_400() {
 async fn valid_confirmation_process() {
     let mut app = spawn_app().await.unwrap();
 
-    let mock_server = app.email_server.mock("POST", "/email")
-        .with_status(200)
-        .match_body(
-            mockito::Matcher::Regex("http://127.0.0.1/subscription/confirm".to_string())
-        )
-        .expect(1)
-        .create();
+    let emails = app.capture_emails();
     
     let form_body = "username=username&email=username%40example.com";
-    let resp = reqwest::Client::new()
+    reqwest::Client::new()
         .post(format!("http://{}/subscription", app.socket_addr))
         .body(form_body)
         .header("Content-Type", "application/x-www-form-urlencoded")
         .send().await.unwrap();
     
-    let confirmation_token = sqlx::query!(
-        "SELECT subscription_token, subscriber_uuid FROM subscription_tokens"
-    )
-        .fetch_one(&app.db_pool)
-        .await
-        .unwrap().subscription_token;
+    let confirmation_token = emails.last_token();
     
     let confirmation_url = format!("http://{}/subscription/confirm?token={}", app.socket_addr, confirmation_token);
     let resp = reqwest::Client::new()
@@ -60,8 +38,9 @@ async fn valid_confirmation_process() {
         .await.unwrap();
     
     assert_eq!(resp.status(), 200);
-    
-    mock_server.assert_async().await;
+    let bodies = emails.bodies();
+    assert_eq!(bodies.len(), 1);
+    assert!(bodies[0].contains("http://127.0.0.1:8080/subscription/confirm?token="));
 }
 
 #[tokio::test]
@@ -70,8 +49,6 @@ async fn the_link_returned_by_subscribe_returns_a_200_if_called() {
     let mut app = spawn_app().await.unwrap();
     let body = "username=username&email=username%40example.com";
     
-    let mut tmp = std::string::String::new();
-    
     let mock = app.email_server.mock("POST", "/email")
         .with_status(200)
         .expect_at_least(1)
@@ -90,4 +67,25 @@ async fn the_link_returned_by_subscribe_returns_a_200_if_called() {
     
     mock.assert_async().await;
 }
-    
\ No newline at end of file
+    
+#[tokio::test]
+async fn only_the_hash_of_the_mailed_token_is_stored() {
+    let mut app = spawn_app().await.unwrap();
+    let emails = app.capture_emails();
+
+    reqwest::Client::new()
+        .post(format!("http://{}/subscription", app.socket_addr))
+        .form(&[("username", "username"), ("email", "username@example.com")])
+        .send()
+        .await
+        .unwrap();
+
+    let mailed = emails.last_token();
+    let stored = sqlx::query!("SELECT subscription_token FROM subscription_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .subscription_token;
+    assert_ne!(stored, mailed);
+    assert_eq!(stored, email_sender::token::hash(&mailed));
+}
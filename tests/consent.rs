@@ -0,0 +1,75 @@
+mod utils;
+
+use crate::utils::spawn_app;
+use serde_json::Value;
+use uuid::Uuid;
+
+#[tokio::test]
+async fn subscribing_and_confirming_record_consent_events() {
+    let mut app = spawn_app().await.unwrap();
+    let emails = app.capture_emails();
+
+    let resp = reqwest::Client::new()
+        .post(format!("http://{}/subscription", app.socket_addr))
+        .header("User-Agent", "consent-test/1.0")
+        .form(&[
+            ("username", "username"),
+            ("email", "username@example.com"),
+            ("source", "footer-form"),
+            ("consent_version", "2026-10"),
+        ])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+
+    let subscriber = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let resp = reqwest::get(format!(
+        "http://{}/subscription/confirm?token={}",
+        app.socket_addr,
+        emails.last_token()
+    ))
+    .await
+    .unwrap();
+    assert_eq!(resp.status(), 200);
+
+    let events: Value = reqwest::Client::new()
+        .get(format!("http://{}/admin/subscribers/{}/consent", app.socket_addr, subscriber.id))
+        .bearer_auth(&app.admin_token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let subscribed = &events[0];
+    assert_eq!(subscribed["event"], "subscribed");
+    assert_eq!(subscribed["ip_address"], "127.0.0.1");
+    assert_eq!(subscribed["user_agent"], "consent-test/1.0");
+    assert_eq!(subscribed["source"], "footer-form");
+    assert_eq!(subscribed["consent_text_version"], "2026-10");
+    let confirmed = &events[1];
+    assert_eq!(confirmed["event"], "confirmed");
+    assert_eq!(confirmed["ip_address"], "127.0.0.1");
+    assert!(confirmed["occurred_at"].is_string());
+}
+
+#[tokio::test]
+async fn consent_events_of_an_unknown_subscriber_are_not_found() {
+    let app = spawn_app().await.unwrap();
+
+    let resp = reqwest::Client::new()
+        .get(format!(
+            "http://{}/admin/subscribers/{}/consent",
+            app.socket_addr,
+            Uuid::new_v4()
+        ))
+        .bearer_auth(&app.admin_token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 404);
+}
@@ -0,0 +1,173 @@
+mod utils;
+
+use crate::utils::{TestAppInfo, spawn_app};
+use serde_json::{Value, json};
+
+async fn admin(app: &TestAppInfo, method: reqwest::Method, path: &str, body: Value) -> reqwest::Response {
+    reqwest::Client::new()
+        .request(method, format!("http://{}{}", app.socket_addr, path))
+        .bearer_auth(&app.admin_token)
+        .json(&body)
+        .send()
+        .await
+        .unwrap()
+}
+
+fn confirmation_source(greeting: &str) -> Value {
+    json!({
+        "name": "confirmation",
+        "subject": format!("{greeting}, {{{{ name }}}}"),
+        "html": format!("<p>{greeting} {{{{ name }}}}</p><a href=\"{{{{ confirmation_link }}}}\">confirm</a>"),
+        "text": format!("{greeting} {{{{ name }}}}: {{{{ confirmation_link }}}}"),
+    })
+}
+
+#[tokio::test]
+async fn templates_require_an_admin_token() {
+    let app = spawn_app().await.unwrap();
+
+    let resp = reqwest::get(format!("http://{}/admin/templates", app.socket_addr))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 401);
+}
+
+#[tokio::test]
+async fn invalid_templates_are_rejected() {
+    let app = spawn_app().await.unwrap();
+
+    let mut unknown_kind = confirmation_source("Hi");
+    unknown_kind["name"] = "newsletter".into();
+    let resp = admin(&app, reqwest::Method::POST, "/admin/templates", unknown_kind).await;
+    assert_eq!(resp.status(), 400);
+
+    let mut unknown_variable = confirmation_source("Hi");
+    unknown_variable["text"] = "{{ unsubscribe_link }}".into();
+    let resp = admin(&app, reqwest::Method::POST, "/admin/templates", unknown_variable).await;
+    assert_eq!(resp.status(), 400);
+
+    let mut syntax_error = confirmation_source("Hi");
+    syntax_error["html"] = "{% if %}".into();
+    let resp = admin(&app, reqwest::Method::POST, "/admin/templates", syntax_error).await;
+    assert_eq!(resp.status(), 400);
+}
+
+#[tokio::test]
+async fn versions_can_be_added_rolled_back_and_previewed() {
+    let app = spawn_app().await.unwrap();
+
+    let resp = admin(&app, reqwest::Method::POST, "/admin/templates", confirmation_source("Hi")).await;
+    assert_eq!(resp.status(), 201);
+    let template: Value = resp.json().await.unwrap();
+    let id = template["id"].as_str().unwrap().to_string();
+    assert_eq!(template["active_version"], 1);
+
+    let resp = admin(&app, reqwest::Method::POST, "/admin/templates", confirmation_source("Hi")).await;
+    assert_eq!(resp.status(), 409);
+
+    let resp = admin(
+        &app,
+        reqwest::Method::POST,
+        &format!("/admin/templates/{id}/versions"),
+        confirmation_source("Hello"),
+    )
+    .await;
+    assert_eq!(resp.status(), 201);
+    let template: Value = resp.json().await.unwrap();
+    assert_eq!(template["active_version"], 2);
+    assert_eq!(template["versions"].as_array().unwrap().len(), 2);
+
+    let preview: Value = admin(&app, reqwest::Method::POST, &format!("/admin/templates/{id}/preview"), json!({}))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(preview["subject"], "Hello, subscriber");
+
+    let resp = admin(
+        &app,
+        reqwest::Method::PUT,
+        &format!("/admin/templates/{id}/active"),
+        json!({ "version": 1 }),
+    )
+    .await;
+    assert_eq!(resp.status(), 200);
+    let resp = admin(
+        &app,
+        reqwest::Method::PUT,
+        &format!("/admin/templates/{id}/active"),
+        json!({ "version": 7 }),
+    )
+    .await;
+    assert_eq!(resp.status(), 404);
+
+    let subscriber_id = uuid::Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO subscriptions (id, email, name, subscribed_at, status) VALUES ($1, 'ada@example.com', '<Ada>', now(), 'confirmed')",
+        subscriber_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let preview: Value = admin(
+        &app,
+        reqwest::Method::POST,
+        &format!("/admin/templates/{id}/preview"),
+        json!({ "version": 2, "subscriber_id": subscriber_id }),
+    )
+    .await
+    .json()
+    .await
+    .unwrap();
+    assert_eq!(preview["subject"], "Hello, <Ada>");
+    assert!(preview["html"].as_str().unwrap().contains("Hello &lt;Ada&gt;"));
+    assert_eq!(
+        preview["text"],
+        "Hello <Ada>: https://example.com/subscription/confirm?token=token"
+    );
+}
+
+#[tokio::test]
+async fn the_active_stored_template_is_used_for_confirmation_emails() {
+    let mut app = spawn_app().await.unwrap();
+    let resp = admin(&app, reqwest::Method::POST, "/admin/templates", confirmation_source("Howdy")).await;
+    assert_eq!(resp.status(), 201);
+    let emails = app.capture_emails();
+
+    reqwest::Client::new()
+        .post(format!("http://{}/subscription", app.socket_addr))
+        .form(&[("username", "username"), ("email", "username@example.com")])
+        .send()
+        .await
+        .unwrap();
+
+    let body: Value = serde_json::from_str(&emails.bodies()[0]).unwrap();
+    assert_eq!(body["subject"], "Howdy, username");
+    assert!(body["text_body"].as_str().unwrap().starts_with("Howdy username: http://127.0.0.1:8080/subscription/confirm?token="));
+    assert_eq!(emails.tokens().len(), 1);
+}
+
+#[tokio::test]
+async fn sent_html_is_inlined_sanitized_and_absolute() {
+    let mut app = spawn_app().await.unwrap();
+    let mut source = confirmation_source("Hi");
+    source["html"] = "<style>p { color: red }</style><p>Hi {{ name }}</p><script>x()</script>\
+        <a href=\"{{ confirmation_link }}\">confirm</a> <a href=\"/about\">about</a>"
+        .into();
+    let resp = admin(&app, reqwest::Method::POST, "/admin/templates", source).await;
+    assert_eq!(resp.status(), 201);
+    let emails = app.capture_emails();
+
+    reqwest::Client::new()
+        .post(format!("http://{}/subscription", app.socket_addr))
+        .form(&[("username", "username"), ("email", "username@example.com")])
+        .send()
+        .await
+        .unwrap();
+
+    let body: Value = serde_json::from_str(&emails.bodies()[0]).unwrap();
+    let html = body["html_body"].as_str().unwrap();
+    assert!(html.contains(r#"<p style="color: red;">Hi username</p>"#), "{html}");
+    assert!(!html.contains("<script"), "{html}");
+    assert!(html.contains(r#"href="http://127.0.0.1:8080/about""#), "{html}");
+}
@@ -0,0 +1,72 @@
+mod utils;
+
+use crate::utils::{TestAppInfo, spawn_app};
+use serde_json::Value;
+
+async fn seed(app: &TestAppInfo) {
+    sqlx::query!(
+        r#"INSERT INTO subscriptions (id, email, name, subscribed_at, status, attributes) VALUES
+        (gen_random_uuid(), 'first@example.com', 'first', now() - interval '1 day', 'confirmed', '{"note": "a, \"quoted\" value"}'),
+        (gen_random_uuid(), 'second@example.com', 'second', now(), 'not-confirmed', '{}')"#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn export(app: &TestAppInfo, query: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!(
+            "http://{}/admin/subscribers/export?{query}",
+            app.socket_addr
+        ))
+        .bearer_auth(&app.admin_token)
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn export_requires_an_admin_token() {
+    let app = spawn_app().await.unwrap();
+
+    let resp = reqwest::get(format!("http://{}/admin/subscribers/export", app.socket_addr))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 401);
+}
+
+#[tokio::test]
+async fn export_as_csv() {
+    let app = spawn_app().await.unwrap();
+    seed(&app).await;
+
+    let resp = export(&app, "format=csv").await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()["content-type"], "text/csv; charset=utf-8");
+    let body = resp.text().await.unwrap();
+    let lines: Vec<_> = body.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("id,email,name,status"));
+    assert!(lines[1].contains(",first@example.com,first,confirmed,"));
+    assert!(lines[1].ends_with(r#","{""note"":""a, \""quoted\"" value""}""#));
+    assert!(lines[2].contains(",second@example.com,second,not-confirmed,"));
+}
+
+#[tokio::test]
+async fn export_as_ndjson_filtered_by_status() {
+    let app = spawn_app().await.unwrap();
+    seed(&app).await;
+
+    let resp = export(&app, "format=ndjson&status=confirmed").await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()["content-type"], "application/x-ndjson");
+    let body = resp.text().await.unwrap();
+    let rows: Vec<Value> = body
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0]["email"], "first@example.com");
+    assert_eq!(rows[0]["attributes"]["note"], r#"a, "quoted" value"#);
+}
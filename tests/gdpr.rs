@@ -0,0 +1,111 @@
+mod utils;
+
+use crate::utils::{TestAppInfo, spawn_app};
+use serde_json::{Value, json};
+
+/// subscribes `email` and returns the token of its confirmation link
+async fn subscribe(app: &mut TestAppInfo, email: &str) -> String {
+    let emails = app.capture_emails();
+    let resp = reqwest::Client::new()
+        .post(format!("http://{}/subscription", app.socket_addr))
+        .form(&[("username", "username"), ("email", email)])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    emails.last_token()
+}
+
+async fn admin_post(app: &TestAppInfo, path: &str, body: Value) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("http://{}{}", app.socket_addr, path))
+        .bearer_auth(&app.admin_token)
+        .json(&body)
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn subscriber_count(app: &TestAppInfo) -> i64 {
+    sqlx::query!(r#"SELECT count(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+async fn admin_access_request_returns_everything_held() {
+    let mut app = spawn_app().await.unwrap();
+    let token = subscribe(&mut app, "someone@example.com").await;
+
+    let resp = admin_post(&app, "/admin/gdpr/access", json!({ "email": "SomeOne@example.com" })).await;
+    assert_eq!(resp.status(), 200);
+    let bundle: Value = resp.json().await.unwrap();
+    let subscription = &bundle["subscriptions"][0];
+    assert_eq!(subscription["email"], "someone@example.com");
+    assert_eq!(subscription["lists"][0]["list_name"], "default");
+    assert_eq!(subscription["tokens"][0]["token_hash"], email_sender::token::hash(&token));
+    assert_eq!(subscription["tokens"][0]["action"], "confirm");
+    assert!(bundle["erased_at"].is_null());
+
+    let resp = reqwest::Client::new()
+        .post(format!("http://{}/admin/gdpr/access", app.socket_addr))
+        .json(&json!({ "email": "someone@example.com" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 401);
+}
+
+#[tokio::test]
+async fn admin_erasure_deletes_the_subscriber_and_blocks_reimport() {
+    let mut app = spawn_app().await.unwrap();
+    subscribe(&mut app, "someone@example.com").await;
+
+    let resp = admin_post(&app, "/admin/gdpr/erasure", json!({ "email": "someone@example.com" })).await;
+    assert_eq!(resp.status(), 200);
+    let result: Value = resp.json().await.unwrap();
+    assert_eq!(result["erased"], 1);
+    assert_eq!(subscriber_count(&app).await, 0);
+
+    let tombstone = sqlx::query!("SELECT email_hash, erased_by FROM erased_subscribers")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(!tombstone.email_hash.contains("someone"));
+
+    let resp = reqwest::Client::new()
+        .post(format!(
+            "http://{}/admin/subscribers/import?status=confirmed",
+            app.socket_addr
+        ))
+        .bearer_auth(&app.admin_token)
+        .body("email,name\nSomeone@Example.com,someone\nother@example.com,other\n")
+        .send()
+        .await
+        .unwrap();
+    let report: Value = resp.json().await.unwrap();
+    assert_eq!(report["imported"], 1);
+    assert_eq!(report["failed"], 1);
+    assert_eq!(report["errors"][0]["line"], 2);
+    assert_eq!(subscriber_count(&app).await, 1);
+}
+
+#[tokio::test]
+async fn subscribers_can_export_and_erase_their_own_data() {
+    let mut app = spawn_app().await.unwrap();
+    subscribe(&mut app, "someone@example.com").await;
+    let (_, url) = app.management_links("someone@example.com").await;
+
+    let bundle: Value = reqwest::get(&url).await.unwrap().json().await.unwrap();
+    assert_eq!(bundle["subscriptions"][0]["email"], "someone@example.com");
+
+    let resp = reqwest::Client::new().delete(&url).send().await.unwrap();
+    assert_eq!(resp.status(), 204);
+    assert_eq!(subscriber_count(&app).await, 0);
+
+    // the token went with the rest of the data
+    let resp = reqwest::get(&url).await.unwrap();
+    assert_eq!(resp.status(), 401);
+}
@@ -0,0 +1,215 @@
+mod utils;
+
+use crate::utils::{TestAppInfo, spawn_app};
+use email_sender::import;
+use serde_json::Value;
+
+async fn import(app: &TestAppInfo, status: &str, csv: &'static str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!(
+            "http://{}/admin/subscribers/import?status={status}",
+            app.socket_addr
+        ))
+        .bearer_auth(&app.admin_token)
+        .header("Content-Type", "text/csv")
+        .body(csv)
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn import_requires_an_admin_token() {
+    let app = spawn_app().await.unwrap();
+
+    let resp = reqwest::Client::new()
+        .post(format!(
+            "http://{}/admin/subscribers/import?status=confirmed",
+            app.socket_addr
+        ))
+        .body("email,name\n")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 401);
+}
+
+#[tokio::test]
+async fn import_stores_valid_rows_and_reports_invalid_ones() {
+    let app = spawn_app().await.unwrap();
+
+    let resp = import(
+        &app,
+        "confirmed",
+        "email,name,plan\n\
+        first@example.com,first,pro\n\
+        not-an-email,second,\n\
+        third@example.com,thi rd,\n\
+        fourth@example.com,fourth,\n\
+        fifth@example.com\n",
+    )
+    .await;
+    assert_eq!(resp.status(), 200);
+    let report: Value = resp.json().await.unwrap();
+    assert_eq!(report["imported"], 2);
+    assert_eq!(report["failed"], 3);
+    let lines: Vec<_> = report["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["line"].as_u64().unwrap())
+        .collect();
+    assert_eq!(lines, [3, 4, 6]);
+
+    let saved = sqlx::query!(
+        "SELECT s.email, s.status, s.attributes, m.status AS membership FROM subscriptions s JOIN list_memberships m ON m.subscriber_uuid = s.id ORDER BY s.email"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.len(), 2);
+    assert_eq!(saved[0].email, "first@example.com");
+    assert_eq!(saved[0].attributes, serde_json::json!({ "plan": "pro" }));
+    assert!(saved.iter().all(|s| s.status == "confirmed" && s.membership == "confirmed"));
+}
+
+#[tokio::test]
+async fn import_reports_rows_repeating_a_later_address() {
+    let app = spawn_app().await.unwrap();
+
+    let resp = import(
+        &app,
+        "confirmed",
+        "email,name\n\
+        first@example.com,old\n\
+        second@example.com,second\n\
+        first@example.com,new\n",
+    )
+    .await;
+    assert_eq!(resp.status(), 200);
+    let report: Value = resp.json().await.unwrap();
+    assert_eq!(report["imported"], 2);
+    assert_eq!(report["failed"], 1);
+    assert_eq!(report["errors"][0]["line"], 2);
+    assert_eq!(report["errors"][0]["error"], "duplicate of row 4");
+
+    let name = sqlx::query_scalar!("SELECT name FROM subscriptions WHERE email = 'first@example.com'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(name, "new");
+}
+
+#[tokio::test]
+async fn pending_import_does_not_resubscribe_unsubscribed_subscribers() {
+    let app = spawn_app().await.unwrap();
+    import(&app, "confirmed", "email,name\nfirst@example.com,first\n").await;
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let resp = import(
+        &app,
+        "pending",
+        "name,email\nfirst,first@example.com\nsecond,second@example.com\n",
+    )
+    .await;
+    assert_eq!(resp.status(), 200);
+
+    let saved = sqlx::query!("SELECT email, status FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved[0].status, "unsubscribed");
+    assert_eq!(saved[1].status, "not-confirmed");
+}
+
+#[tokio::test]
+async fn pending_imports_are_mailed_a_confirmation_link() {
+    let mut app = spawn_app().await.unwrap();
+    import(&app, "confirmed", "email,name\nfirst@example.com,first\n").await;
+    let resp = import(
+        &app,
+        "pending",
+        "email,name\nfirst@example.com,first\nsecond@example.com,second\n",
+    )
+    .await;
+    assert_eq!(resp.status(), 200);
+
+    let emails = app.capture_emails();
+    let sent = import::send_queued_confirmations(&app.app_state, 10).await.unwrap();
+    assert_eq!(sent, 1);
+    assert!(emails.bodies()[0].contains("second@example.com"));
+    assert_eq!(import::send_queued_confirmations(&app.app_state, 10).await.unwrap(), 0);
+
+    let list_id = sqlx::query_scalar!("SELECT id FROM lists WHERE name = 'default'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let resp = reqwest::get(format!(
+        "http://{}/lists/{list_id}/subscription/confirm?token={}",
+        app.socket_addr,
+        emails.last_token()
+    ))
+    .await
+    .unwrap();
+    assert_eq!(resp.status(), 200);
+    let status = sqlx::query_scalar!(
+        "SELECT m.status FROM list_memberships m JOIN subscriptions s ON s.id = m.subscriber_uuid WHERE s.email = 'second@example.com'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(status, "confirmed");
+}
+
+#[tokio::test]
+async fn a_rejected_confirmation_email_does_not_hold_up_the_queue() {
+    let mut app = spawn_app().await.unwrap();
+    import(&app, "pending", "email,name\nbad@example.com,bad\n").await;
+    import(&app, "pending", "email,name\ngood@example.com,good\n").await;
+
+    let _rejected = app
+        .email_server
+        .mock("POST", "/email")
+        .match_body(mockito::Matcher::Regex("bad@example.com".into()))
+        .with_status(422)
+        .create();
+    let emails = app.capture_emails();
+    let sent = import::send_queued_confirmations(&app.app_state, 10).await.unwrap();
+    assert_eq!(sent, 1);
+    assert_eq!(emails.bodies().len(), 1);
+    assert!(emails.bodies()[0].contains("good@example.com"));
+    let queued = sqlx::query_scalar!("SELECT count(*) FROM pending_confirmations")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued, Some(0));
+}
+
+#[tokio::test]
+async fn transient_confirmation_errors_are_retried_later() {
+    let mut app = spawn_app().await.unwrap();
+    import(&app, "pending", "email,name\nfirst@example.com,first\n").await;
+    let _unavailable = app.email_server.mock("POST", "/email").with_status(503).create();
+
+    assert_eq!(import::send_queued_confirmations(&app.app_state, 10).await.unwrap(), 0);
+    let queued = sqlx::query!("SELECT attempts, next_attempt_at, last_error FROM pending_confirmations")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.attempts, 1);
+    assert!(queued.next_attempt_at.unwrap() > chrono::Utc::now());
+    assert!(queued.last_error.unwrap().contains("503"));
+    // not due for another try yet
+    assert_eq!(import::send_queued_confirmations(&app.app_state, 10).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn import_without_required_columns_is_rejected() {
+    let app = spawn_app().await.unwrap();
+
+    let resp = import(&app, "confirmed", "mail,username\nfirst@example.com,first\n").await;
+    assert_eq!(resp.status(), 400);
+}
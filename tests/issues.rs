@@ -0,0 +1,745 @@
+mod utils;
+
+use crate::utils::{TestAppInfo, spawn_app, spawn_app_with};
+use chrono::{Duration, DurationRound, Utc};
+use email_sender::ab_test;
+use email_sender::scheduler::{self, DeliveryRun};
+use serde_json::{Value, json};
+use uuid::Uuid;
+
+async fn insert_subscriber(app: &TestAppInfo, email: &str, status: &str) -> Uuid {
+    let id = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO subscriptions (id, email, name, subscribed_at, status) VALUES ($1, $2, 'reader', now(), $3)",
+        id,
+        email,
+        status,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    id
+}
+
+/// An app with a second admin, whose token [`reviewer_token`] returns.
+async fn spawn_app_with_reviewer() -> TestAppInfo {
+    spawn_app_with(|conf| {
+        conf.admin
+            .api_tokens
+            .insert("reviewer".to_string(), "reviewer-token".to_string());
+    })
+    .await
+    .unwrap()
+}
+
+fn reviewer_token(app: &TestAppInfo) -> String {
+    app.app_state
+        .conf
+        .admin
+        .api_tokens
+        .values()
+        .find(|token| **token != app.admin_token)
+        .expect("no second admin")
+        .clone()
+}
+
+async fn admin_request(app: &TestAppInfo, method: reqwest::Method, path: &str, body: Option<Value>) -> reqwest::Response {
+    request_as(app, &app.admin_token, method, path, body).await
+}
+
+async fn request_as(
+    app: &TestAppInfo,
+    token: &str,
+    method: reqwest::Method,
+    path: &str,
+    body: Option<Value>,
+) -> reqwest::Response {
+    let mut request = reqwest::Client::new()
+        .request(method, format!("http://{}{path}", app.socket_addr))
+        .bearer_auth(token);
+    if let Some(body) = body {
+        request = request.json(&body);
+    }
+    request.send().await.unwrap()
+}
+
+async fn create_issue(app: &TestAppInfo, body: Value) -> Value {
+    let resp = admin_request(app, reqwest::Method::POST, "/admin/issues", Some(body)).await;
+    assert_eq!(resp.status(), 201);
+    resp.json().await.unwrap()
+}
+
+async fn approve(app: &TestAppInfo, id: &str) -> Value {
+    let path = format!("/admin/issues/{id}/approve");
+    let resp = request_as(app, &reviewer_token(app), reqwest::Method::POST, &path, None).await;
+    assert_eq!(resp.status(), 200);
+    resp.json().await.unwrap()
+}
+
+#[tokio::test]
+async fn issues_are_scheduled_in_the_editors_timezone() {
+    let app = spawn_app().await.unwrap();
+    let issue = create_issue(
+        &app,
+        json!({
+            "subject": "Weekly",
+            "html": "<p>hi</p>",
+            "scheduled_at": "2026-10-26T09:00:00",
+            "timezone": "Asia/Tokyo",
+        }),
+    )
+    .await;
+    assert_eq!(issue["status"], "draft");
+    assert_eq!(issue["scheduled_at"], "2026-10-26T00:00:00Z");
+    assert_eq!(issue["local_scheduled_at"], "2026-10-26T09:00:00");
+
+    // 02:30 does not exist on the night clocks go forward in New York
+    for (body, status) in [
+        (json!({ "scheduled_at": "2027-03-14T02:30:00", "timezone": "America/New_York" }), 400),
+        (json!({ "scheduled_at": "2026-10-26T09:00:00", "timezone": "Mars/Olympus" }), 400),
+        (json!({ "scheduled_at": "2026-10-26T09:00:00", "subject": "Hi {{ nickname }}" }), 400),
+        (json!({ "scheduled_at": "2026-10-26T09:00:00", "list_id": Uuid::new_v4() }), 404),
+    ] {
+        let mut body = body;
+        body.as_object_mut().unwrap().entry("subject").or_insert(json!("Weekly"));
+        body["html"] = json!("<p>hi</p>");
+        let resp = admin_request(&app, reqwest::Method::POST, "/admin/issues", Some(body.clone())).await;
+        assert_eq!(resp.status(), status, "{body}");
+    }
+}
+
+#[tokio::test]
+async fn issues_can_be_rescheduled_or_cancelled_until_they_start() {
+    let mut app = spawn_app_with_reviewer().await;
+    insert_subscriber(&app, "ada@example.com", "confirmed").await;
+    let emails = app.capture_emails();
+    let issue = create_issue(
+        &app,
+        json!({ "subject": "Weekly", "html": "<p>hi</p>", "scheduled_at": "2026-10-26T09:00:00" }),
+    )
+    .await;
+    let id = issue["id"].as_str().unwrap();
+    approve(&app, id).await;
+
+    let resp = admin_request(
+        &app,
+        reqwest::Method::PUT,
+        &format!("/admin/issues/{id}/schedule"),
+        Some(json!({ "scheduled_at": "2099-01-01T09:00:00", "timezone": "Europe/Paris" })),
+    )
+    .await;
+    assert_eq!(resp.status(), 200);
+    let issue: Value = resp.json().await.unwrap();
+    assert_eq!(issue["scheduled_at"], "2099-01-01T08:00:00Z");
+    assert_eq!(issue["timezone"], "Europe/Paris");
+    assert_eq!(issue["status"], "approved");
+
+    let now = Utc::now();
+    assert!(scheduler::promote_due_issues(&app.db_pool, now).await.unwrap().is_empty());
+    let later: chrono::DateTime<Utc> = "2099-01-01T08:00:00Z".parse().unwrap();
+
+    let resp = admin_request(&app, reqwest::Method::POST, &format!("/admin/issues/{id}/cancel"), None).await;
+    assert_eq!(resp.status(), 200);
+    assert!(scheduler::promote_due_issues(&app.db_pool, later).await.unwrap().is_empty());
+    let resp = admin_request(&app, reqwest::Method::POST, &format!("/admin/issues/{id}/cancel"), None).await;
+    assert_eq!(resp.status(), 409);
+    scheduler::deliver_queued(&app.app_state, 10).await.unwrap();
+    assert!(emails.bodies().is_empty());
+
+    let resp = admin_request(&app, reqwest::Method::POST, &format!("/admin/issues/{}/cancel", Uuid::new_v4()), None).await;
+    assert_eq!(resp.status(), 404);
+}
+
+#[tokio::test]
+async fn due_issues_are_sent_once_even_when_the_scheduler_restarts() {
+    let mut app = spawn_app_with_reviewer().await;
+    insert_subscriber(&app, "ada@example.com", "confirmed").await;
+    let bob = insert_subscriber(&app, "bob@example.com", "confirmed").await;
+    insert_subscriber(&app, "carol@example.com", "confirmed").await;
+    insert_subscriber(&app, "dave@example.com", "not-confirmed").await;
+    let emails = app.capture_emails();
+    let scheduled_at = (Utc::now() - Duration::minutes(1)).naive_utc();
+    let issue = create_issue(
+        &app,
+        json!({ "subject": "Weekly", "html": "<p>hi {{ subscriber.email }}</p>", "scheduled_at": scheduled_at }),
+    )
+    .await;
+    let id: Uuid = issue["id"].as_str().unwrap().parse().unwrap();
+    approve(&app, &id.to_string()).await;
+
+    let promoted = scheduler::promote_due_issues(&app.db_pool, Utc::now()).await.unwrap();
+    assert_eq!(promoted, [id]);
+    assert!(scheduler::promote_due_issues(&app.db_pool, Utc::now()).await.unwrap().is_empty());
+    let resp = admin_request(&app, reqwest::Method::POST, &format!("/admin/issues/{id}/cancel"), None).await;
+    assert_eq!(resp.status(), 409);
+
+    // recipients who unsubscribe after the issue started are skipped
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed' WHERE id = $1", bob)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    // a replica stopping after one delivery leaves the others queued for the next run
+    let first = scheduler::deliver_queued(&app.app_state, 1).await.unwrap();
+    assert_eq!(first.claimed(), 1);
+    let rest = scheduler::deliver_queued(&app.app_state, 10).await.unwrap();
+    assert_eq!(rest.claimed(), 2);
+    assert_eq!((first.sent + rest.sent, first.skipped + rest.skipped), (2, 1));
+    let run = scheduler::deliver_queued(&app.app_state, 10).await.unwrap();
+    assert_eq!(run, DeliveryRun::default());
+
+    let mut recipients: Vec<String> = emails
+        .bodies()
+        .iter()
+        .map(|b| serde_json::from_str::<Value>(b).unwrap()["to"].as_str().unwrap().to_string())
+        .collect();
+    recipients.sort();
+    assert_eq!(recipients, ["ada@example.com", "carol@example.com"]);
+
+    let resp = admin_request(&app, reqwest::Method::GET, &format!("/admin/issues/{id}"), None).await;
+    let issue: Value = resp.json().await.unwrap();
+    assert_eq!(issue["status"], "sent");
+    assert!(issue["completed_at"].is_string());
+}
+
+#[tokio::test]
+async fn issues_with_a_segment_go_to_its_confirmed_matches() {
+    let mut app = spawn_app_with_reviewer().await;
+    let ada = insert_subscriber(&app, "ada@example.com", "confirmed").await;
+    insert_subscriber(&app, "bob@example.com", "confirmed").await;
+    let carol = insert_subscriber(&app, "carol@example.com", "not-confirmed").await;
+    sqlx::query!(
+        "INSERT INTO subscriber_tags (subscriber_uuid, tag, created_at) SELECT UNNEST($1::uuid[]), 'beta', now()",
+        &[ada, carol],
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let resp = admin_request(
+        &app,
+        reqwest::Method::POST,
+        "/admin/segments",
+        Some(json!({ "name": "beta", "expression": r#"tag = "beta""# })),
+    )
+    .await;
+    assert_eq!(resp.status(), 201);
+    let segment_id = resp.json::<Value>().await.unwrap()["id"].clone();
+    let emails = app.capture_emails();
+
+    let scheduled_at = (Utc::now() - Duration::minutes(1)).naive_utc();
+    let resp = admin_request(
+        &app,
+        reqwest::Method::POST,
+        "/admin/issues",
+        Some(json!({ "subject": "Beta", "html": "<p>hi</p>", "scheduled_at": scheduled_at, "segment_id": Uuid::new_v4() })),
+    )
+    .await;
+    assert_eq!(resp.status(), 404);
+    let issue = create_issue(
+        &app,
+        json!({ "subject": "Beta", "html": "<p>hi</p>", "scheduled_at": scheduled_at, "segment_id": segment_id }),
+    )
+    .await;
+    assert_eq!(issue["segment_id"], segment_id);
+    let id = issue["id"].as_str().unwrap();
+    approve(&app, id).await;
+    scheduler::promote_due_issues(&app.db_pool, Utc::now()).await.unwrap();
+    let run = scheduler::deliver_queued(&app.app_state, 10).await.unwrap();
+    assert_eq!(run.sent, 1);
+    let body: Value = serde_json::from_str(&emails.bodies()[0]).unwrap();
+    assert_eq!(body["to"], "ada@example.com");
+
+    let path = format!("/admin/segments/{}", segment_id.as_str().unwrap());
+    let resp = admin_request(&app, reqwest::Method::DELETE, &path, None).await;
+    assert_eq!(resp.status(), 409);
+}
+
+#[tokio::test]
+async fn an_issue_that_cannot_start_fails_without_holding_up_the_others() {
+    let app = spawn_app_with_reviewer().await;
+    insert_subscriber(&app, "ada@example.com", "confirmed").await;
+    let resp = admin_request(
+        &app,
+        reqwest::Method::POST,
+        "/admin/segments",
+        Some(json!({ "name": "beta", "expression": r#"tag = "beta""# })),
+    )
+    .await;
+    let segment_id = resp.json::<Value>().await.unwrap()["id"].clone();
+    let scheduled_at = (Utc::now() - Duration::minutes(2)).naive_utc();
+    let broken = create_issue(
+        &app,
+        json!({ "subject": "Beta", "html": "<p>hi</p>", "scheduled_at": scheduled_at, "segment_id": segment_id }),
+    )
+    .await;
+    let broken = broken["id"].as_str().unwrap();
+    approve(&app, broken).await;
+    let scheduled_at = (Utc::now() - Duration::minutes(1)).naive_utc();
+    let issue = create_issue(&app, json!({ "subject": "Weekly", "html": "<p>hi</p>", "scheduled_at": scheduled_at })).await;
+    let id: Uuid = issue["id"].as_str().unwrap().parse().unwrap();
+    approve(&app, &id.to_string()).await;
+    sqlx::query!("UPDATE segments SET expression = 'tag ~ beta'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let started = scheduler::promote_due_issues(&app.db_pool, Utc::now()).await.unwrap();
+    assert_eq!(started, [id]);
+    let resp = admin_request(&app, reqwest::Method::GET, &format!("/admin/issues/{broken}"), None).await;
+    let issue: Value = resp.json().await.unwrap();
+    assert_eq!(issue["status"], "failed");
+    assert!(issue["error"].as_str().unwrap().contains("segment"));
+    let queued = sqlx::query_scalar!("SELECT count(*) FROM issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued, Some(1));
+    assert!(scheduler::promote_due_issues(&app.db_pool, Utc::now()).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn transient_send_errors_are_retried_until_max_attempts() {
+    let mut app = spawn_app_with(|conf| {
+        conf.admin
+            .api_tokens
+            .insert("reviewer".to_string(), "reviewer-token".to_string());
+        conf.scheduler.max_attempts = 2;
+    })
+    .await
+    .unwrap();
+    insert_subscriber(&app, "ada@example.com", "confirmed").await;
+    let _unavailable = app.email_server.mock("POST", "/email").with_status(503).create();
+    let scheduled_at = (Utc::now() - Duration::minutes(1)).naive_utc();
+    let issue = create_issue(&app, json!({ "subject": "Weekly", "html": "<p>hi</p>", "scheduled_at": scheduled_at })).await;
+    let id: Uuid = issue["id"].as_str().unwrap().parse().unwrap();
+    approve(&app, &id.to_string()).await;
+    scheduler::promote_due_issues(&app.db_pool, Utc::now()).await.unwrap();
+
+    let run = scheduler::deliver_queued(&app.app_state, 10).await.unwrap();
+    assert_eq!(run, DeliveryRun { retried: 1, ..Default::default() });
+    let delivery = sqlx::query!("SELECT status, attempts, next_attempt_at FROM issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!((delivery.status.as_str(), delivery.attempts), ("queued", 1));
+    assert!(delivery.next_attempt_at.unwrap() > Utc::now());
+    // not due for another try yet
+    let run = scheduler::deliver_queued(&app.app_state, 10).await.unwrap();
+    assert_eq!(run, DeliveryRun::default());
+
+    sqlx::query!("UPDATE issue_deliveries SET next_attempt_at = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let run = scheduler::deliver_queued(&app.app_state, 10).await.unwrap();
+    assert_eq!(run, DeliveryRun { failed: 1, ..Default::default() });
+    let delivery = sqlx::query!("SELECT status, attempts, error FROM issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!((delivery.status.as_str(), delivery.attempts), ("failed", 2));
+    assert!(delivery.error.unwrap().contains("503"));
+    let resp = admin_request(&app, reqwest::Method::GET, &format!("/admin/issues/{id}"), None).await;
+    let issue: Value = resp.json().await.unwrap();
+    assert_eq!(issue["status"], "sent");
+}
+
+#[tokio::test]
+async fn drafts_are_only_sent_after_another_admin_approves_them() {
+    let mut app = spawn_app_with_reviewer().await;
+    insert_subscriber(&app, "ada@example.com", "confirmed").await;
+    let emails = app.capture_emails();
+    let scheduled_at = (Utc::now() - Duration::minutes(1)).naive_utc();
+    let issue = create_issue(&app, json!({ "subject": "Weekly", "html": "<p>hi</p>" })).await;
+    let id = issue["id"].as_str().unwrap();
+    assert!(issue["scheduled_at"].is_null());
+
+    // a draft without a send time cannot be approved, nor can its author approve it
+    let approve_path = format!("/admin/issues/{id}/approve");
+    let resp = request_as(&app, &reviewer_token(&app), reqwest::Method::POST, &approve_path, None).await;
+    assert_eq!(resp.status(), 409);
+    let body = json!({ "subject": "Weekly", "html": "<p>hello</p>", "scheduled_at": scheduled_at });
+    let resp = admin_request(&app, reqwest::Method::PUT, &format!("/admin/issues/{id}"), Some(body)).await;
+    assert_eq!(resp.status(), 200);
+    let resp = admin_request(&app, reqwest::Method::POST, &approve_path, None).await;
+    assert_eq!(resp.status(), 403);
+    assert!(scheduler::promote_due_issues(&app.db_pool, Utc::now()).await.unwrap().is_empty());
+
+    // editing an approved issue takes the approval back, and the editor cannot approve it
+    let issue = approve(&app, id).await;
+    assert_eq!(issue["status"], "approved");
+    assert!(issue["approved_by"].is_string());
+    let body = json!({ "subject": "Weekly!", "html": "<p>hello</p>", "scheduled_at": scheduled_at });
+    let resp = request_as(&app, &reviewer_token(&app), reqwest::Method::PUT, &format!("/admin/issues/{id}"), Some(body)).await;
+    let issue: Value = resp.json().await.unwrap();
+    assert_eq!(issue["status"], "draft");
+    assert!(issue["approved_by"].is_null());
+    let resp = request_as(&app, &reviewer_token(&app), reqwest::Method::POST, &approve_path, None).await;
+    assert_eq!(resp.status(), 403);
+    let resp = admin_request(&app, reqwest::Method::POST, &approve_path, None).await;
+    assert_eq!(resp.status(), 403);
+    assert!(scheduler::promote_due_issues(&app.db_pool, Utc::now()).await.unwrap().is_empty());
+    assert!(emails.bodies().is_empty());
+}
+
+#[tokio::test]
+async fn test_sends_only_go_to_internal_addresses() {
+    let mut app = spawn_app().await.unwrap();
+    insert_subscriber(&app, "ada@example.com", "confirmed").await;
+    let emails = app.capture_emails();
+    let issue = create_issue(&app, json!({ "subject": "Hi {{ subscriber.name }}", "html": "<p>hi</p>" })).await;
+    let path = format!("/admin/issues/{}/test", issue["id"].as_str().unwrap());
+
+    for recipients in [json!([]), json!(["editor@example.com", "someone@gmail.com"]), json!(["not an email"])] {
+        let resp = admin_request(&app, reqwest::Method::POST, &path, Some(json!({ "recipients": recipients }))).await;
+        assert_eq!(resp.status(), 400, "{recipients}");
+    }
+    assert!(emails.bodies().is_empty());
+
+    let body = json!({ "recipients": ["ada@example.com", "editor@EXAMPLE.com"] });
+    let resp = admin_request(&app, reqwest::Method::POST, &path, Some(body)).await;
+    assert_eq!(resp.status(), 200);
+    let report: Value = resp.json().await.unwrap();
+    assert_eq!(report["sent"].as_array().unwrap().len(), 2);
+    let subjects: Vec<String> = emails
+        .bodies()
+        .iter()
+        .map(|b| serde_json::from_str::<Value>(b).unwrap()["subject"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(subjects, ["[TEST] Hi reader", "[TEST] Hi"]);
+
+    let issue: Value = admin_request(&app, reqwest::Method::GET, &format!("/admin/issues/{}", issue["id"].as_str().unwrap()), None)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(issue["status"], "draft");
+}
+
+#[tokio::test]
+async fn ab_tests_need_their_metric_tracked() {
+    let app = spawn_app_with(|conf| conf.tracking.clicks = true).await.unwrap();
+    let ab_test = |metric| json!({ "subjects": ["A", "B"], "sample_percent": 20, "metric": metric, "window_minutes": 60 });
+
+    let resp = admin_request(
+        &app,
+        reqwest::Method::POST,
+        "/admin/issues",
+        Some(json!({ "subject": "Weekly", "html": "<p>hi</p>", "ab_test": ab_test("opens") })),
+    )
+    .await;
+    assert_eq!(resp.status(), 400);
+    assert!(resp.text().await.unwrap().contains("tracking.opens"));
+    create_issue(&app, json!({ "subject": "Weekly", "html": "<p>hi</p>", "ab_test": ab_test("clicks") })).await;
+}
+
+#[tokio::test]
+async fn the_winning_subject_of_an_ab_test_goes_to_the_rest() {
+    let mut app = spawn_app_with(|conf| {
+        conf.admin
+            .api_tokens
+            .insert("reviewer".to_string(), "reviewer-token".to_string());
+        conf.tracking.opens = true;
+    })
+    .await
+    .unwrap();
+    for i in 0..10 {
+        insert_subscriber(&app, &format!("reader{i}@example.com"), "confirmed").await;
+    }
+    let emails = app.capture_emails();
+    let ab_test = json!({ "subjects": ["A {{ subscriber.name }}", "B"], "sample_percent": 20, "metric": "opens", "window_minutes": 60 });
+    let resp = admin_request(
+        &app,
+        reqwest::Method::POST,
+        "/admin/issues",
+        Some(json!({ "subject": "Weekly", "html": "<p>hi</p>", "ab_test": { "subjects": ["A"], "sample_percent": 20, "metric": "opens", "window_minutes": 60 } })),
+    )
+    .await;
+    assert_eq!(resp.status(), 400);
+    let scheduled_at = (Utc::now() - Duration::minutes(1)).naive_utc();
+    let issue = create_issue(
+        &app,
+        json!({ "subject": "Weekly", "html": "<p>hi</p>", "scheduled_at": scheduled_at, "ab_test": ab_test }),
+    )
+    .await;
+    let id = issue["id"].as_str().unwrap();
+    let issue_id: Uuid = id.parse().unwrap();
+    approve(&app, id).await;
+
+    let started = Utc::now();
+    scheduler::promote_due_issues(&app.db_pool, started).await.unwrap();
+    let run = scheduler::deliver_queued(&app.app_state, 100).await.unwrap();
+    assert_eq!(run.sent, 2);
+    let subjects = |emails: &utils::SentEmails| -> Vec<String> {
+        let mut subjects: Vec<String> = emails
+            .bodies()
+            .iter()
+            .map(|b| serde_json::from_str::<Value>(b).unwrap()["subject"].as_str().unwrap().to_string())
+            .collect();
+        subjects.sort();
+        subjects
+    };
+    assert_eq!(subjects(&emails), ["A reader", "B"]);
+
+    // B's reader opens it; nothing is decided before the window is over
+    sqlx::query!(
+        r#"INSERT INTO engagement_events (delivery_id, kind, occurred_at)
+        SELECT id, 'open', now() FROM issue_deliveries WHERE issue_id = $1 AND variant = 1"#,
+        issue_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(ab_test::decide_due_tests(&app.db_pool, Utc::now()).await.unwrap().is_empty());
+    let run = scheduler::deliver_queued(&app.app_state, 100).await.unwrap();
+    assert_eq!(run, DeliveryRun::default());
+
+    let decided = ab_test::decide_due_tests(&app.db_pool, started + Duration::minutes(61)).await.unwrap();
+    assert_eq!(decided, [issue_id]);
+    let run = scheduler::deliver_queued(&app.app_state, 100).await.unwrap();
+    assert_eq!(run.sent, 8);
+    let subjects = subjects(&emails);
+    assert_eq!(subjects.iter().filter(|s| *s == "B").count(), 9);
+
+    let issue: Value = admin_request(&app, reqwest::Method::GET, &format!("/admin/issues/{id}"), None)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(issue["status"], "sent");
+    assert_eq!(issue["ab_winner"], 1);
+    assert_eq!(issue["ab_results"][1]["engaged"], 1);
+    assert_eq!(issue["ab_results"][0]["rate"], 0.0);
+    let in_sample = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM issue_deliveries WHERE issue_id = $1 AND in_sample"#,
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(in_sample, 2);
+}
+
+#[tokio::test]
+async fn opens_are_counted_once_per_reader_and_machine_opens_apart() {
+    let mut app = spawn_app_with(|conf| {
+        conf.tracking.opens = true;
+        conf.admin
+            .api_tokens
+            .insert("reviewer".to_string(), "reviewer-token".to_string());
+    })
+    .await
+    .unwrap();
+    insert_subscriber(&app, "reader@example.com", "confirmed").await;
+    insert_subscriber(&app, "other@example.com", "confirmed").await;
+    let emails = app.capture_emails();
+    let scheduled_at = (Utc::now() - Duration::minutes(1)).naive_utc();
+    let issue = create_issue(&app, json!({ "subject": "Weekly", "html": "<p>hi</p>", "scheduled_at": scheduled_at })).await;
+    let id = issue["id"].as_str().unwrap();
+    approve(&app, id).await;
+    scheduler::promote_due_issues(&app.db_pool, Utc::now()).await.unwrap();
+    scheduler::deliver_queued(&app.app_state, 100).await.unwrap();
+
+    let re = regex::Regex::new(r#"/t/o/[^"]+\.gif"#).unwrap();
+    let pixels: Vec<String> = emails
+        .bodies()
+        .iter()
+        .map(|b| {
+            let body: Value = serde_json::from_str(b).unwrap();
+            re.find(body["html_body"].as_str().unwrap()).expect("no pixel").as_str().to_string()
+        })
+        .collect();
+    assert_eq!(pixels.len(), 2);
+    // loads right after sending are taken for scanners
+    sqlx::query!("UPDATE issue_deliveries SET sent_at = now() - interval '1 hour'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let load = |path: String, user_agent: &'static str| {
+        let url = format!("http://{}{path}", app.socket_addr);
+        async move {
+            reqwest::Client::new()
+                .get(url)
+                .header(reqwest::header::USER_AGENT, user_agent)
+                .send()
+                .await
+                .unwrap()
+        }
+    };
+    let mail = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko)";
+    for _ in 0..2 {
+        let resp = load(pixels[0].clone(), mail).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()["content-type"], "image/gif");
+    }
+    assert_eq!(load(pixels[1].clone(), "Mozilla/5.0").await.status(), 200);
+    let forged = pixels[0].replacen("/t/o/", "/t/o/0", 1);
+    assert_eq!(load(forged, mail).await.status(), 404);
+
+    let issue: Value = admin_request(&app, reqwest::Method::GET, &format!("/admin/issues/{id}"), None)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(issue["opens"], json!({ "sent": 2, "opened": 1, "machine_opened": 1 }));
+}
+
+#[tokio::test]
+async fn clicks_are_recorded_and_redirect_to_the_signed_link() {
+    let mut app = spawn_app_with(|conf| {
+        conf.tracking.clicks = true;
+        conf.admin
+            .api_tokens
+            .insert("reviewer".to_string(), "reviewer-token".to_string());
+    })
+    .await
+    .unwrap();
+    insert_subscriber(&app, "reader@example.com", "confirmed").await;
+    let emails = app.capture_emails();
+    let scheduled_at = (Utc::now() - Duration::minutes(1)).naive_utc();
+    let html = r#"<p><a href="https://example.org/post?id=1">post</a> <a href="{{ unsubscribe_url }}">unsubscribe</a></p>"#;
+    let issue = create_issue(&app, json!({ "subject": "Weekly", "html": html, "scheduled_at": scheduled_at })).await;
+    let id = issue["id"].as_str().unwrap();
+    approve(&app, id).await;
+    scheduler::promote_due_issues(&app.db_pool, Utc::now()).await.unwrap();
+    scheduler::deliver_queued(&app.app_state, 100).await.unwrap();
+    sqlx::query!("UPDATE issue_deliveries SET sent_at = now() - interval '1 hour'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let body: Value = serde_json::from_str(&emails.bodies()[0]).unwrap();
+    let html = body["html_body"].as_str().unwrap();
+    assert!(!html.contains("https://example.org"), "{html}");
+    assert!(html.contains("/subscription/unsubscribe?token="), "the app's own links are kept");
+    let link = regex::Regex::new(r#"/t/c/[^"]+"#).unwrap().find(html).expect("no tracked link").as_str().to_string();
+
+    let client = reqwest::Client::builder().redirect(reqwest::redirect::Policy::none()).build().unwrap();
+    let click = |path: String| {
+        let request = client
+            .get(format!("http://{}{path}", app.socket_addr))
+            .header(reqwest::header::USER_AGENT, "Mozilla/5.0 (X11; Linux x86_64; rv:140.0) Gecko/20100101 Firefox/140.0");
+        async move { request.send().await.unwrap() }
+    };
+    for _ in 0..2 {
+        let resp = click(link.clone()).await;
+        assert_eq!(resp.status(), 303);
+        assert_eq!(
+            resp.headers()["location"],
+            format!("https://example.org/post?id=1&utm_source=newsletter&utm_medium=email&utm_campaign={id}").as_str()
+        );
+    }
+    // another destination under the same signature is refused
+    let parts: Vec<&str> = link.trim_start_matches("/t/c/").split('.').collect();
+    let forged = format!("/t/c/{}.aHR0cHM6Ly9ldmlsLmV4YW1wbGU.{}.{}", parts[0], parts[2], parts[3]);
+    assert_eq!(click(forged).await.status(), 404);
+
+    let issue: Value = admin_request(&app, reqwest::Method::GET, &format!("/admin/issues/{id}"), None)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(
+        issue["clicks"],
+        json!({ "clicked": 1, "links": [{ "url": "https://example.org/post?id=1", "clicks": 2, "recipients": 1 }] })
+    );
+}
+
+#[tokio::test]
+async fn issue_stats_count_deliveries_and_what_readers_did() {
+    let mut app = spawn_app_with(|conf| {
+        conf.tracking.opens = true;
+        conf.tracking.clicks = true;
+        conf.admin
+            .api_tokens
+            .insert("reviewer".to_string(), "reviewer-token".to_string());
+    })
+    .await
+    .unwrap();
+    for email in ["a@example.com", "b@example.com", "c@example.com"] {
+        insert_subscriber(&app, email, "confirmed").await;
+    }
+    let emails = app.capture_emails();
+    let scheduled_at = (Utc::now() - Duration::minutes(1)).naive_utc();
+    let html = r#"<p><a href="https://example.org/post">post</a> <a href="{{ unsubscribe_url }}">unsubscribe</a></p>"#;
+    let issue = create_issue(&app, json!({ "subject": "Weekly", "html": html, "scheduled_at": scheduled_at })).await;
+    let id = issue["id"].as_str().unwrap();
+    approve(&app, id).await;
+    scheduler::promote_due_issues(&app.db_pool, Utc::now()).await.unwrap();
+    let stats_path = format!("/admin/issues/{id}/stats");
+    let stats: Value = admin_request(&app, reqwest::Method::GET, &stats_path, None).await.json().await.unwrap();
+    assert_eq!(stats["counts"]["queued"], 3);
+    scheduler::deliver_queued(&app.app_state, 100).await.unwrap();
+    sqlx::query!("UPDATE issue_deliveries SET sent_at = now() - interval '1 hour'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let mut mails: Vec<Value> = emails.bodies().iter().map(|b| serde_json::from_str(b).unwrap()).collect();
+    mails.sort_by_key(|mail| mail["to"].as_str().unwrap().to_string());
+    let path_of = |mail: &Value, pattern: &str| {
+        let html = mail["html_body"].as_str().unwrap();
+        let path = regex::Regex::new(pattern).unwrap().find(html).unwrap().as_str().replace("&amp;", "&");
+        format!("http://{}{path}", app.socket_addr)
+    };
+    let client = reqwest::Client::builder().redirect(reqwest::redirect::Policy::none()).build().unwrap();
+    let browser = "Mozilla/5.0 (X11; Linux x86_64; rv:140.0) Gecko/20100101 Firefox/140.0";
+    // a opens twice and clicks
+    for pattern in [r#"/t/o/[^"]+"#, r#"/t/o/[^"]+"#, r#"/t/c/[^"]+"#] {
+        let resp = client.get(path_of(&mails[0], pattern)).header(reqwest::header::USER_AGENT, browser).send().await.unwrap();
+        assert!(resp.status().is_success() || resp.status().is_redirection());
+    }
+    // b bounces, then complains
+    let delivery_id = mails[1]["metadata"]["delivery_id"].as_str().unwrap();
+    let webhook = |token: &'static str, record_type: &'static str| {
+        client
+            .post(format!("http://{}/webhooks/email", app.socket_addr))
+            .bearer_auth(token)
+            .json(&json!({ "record_type": record_type, "metadata": { "delivery_id": delivery_id } }))
+            .send()
+    };
+    assert_eq!(webhook("wrong", "Bounce").await.unwrap().status(), 401);
+    assert_eq!(webhook("my-webhook-token", "Bounce").await.unwrap().status(), 204);
+    assert_eq!(webhook("my-webhook-token", "SpamComplaint").await.unwrap().status(), 204);
+    assert_eq!(webhook("my-webhook-token", "Delivery").await.unwrap().status(), 204);
+    // c unsubscribes through the issue
+    let unsubscribe = path_of(&mails[2], r#"/subscription/unsubscribe\?[^"]+"#);
+    assert!(unsubscribe.contains("&delivery="), "{unsubscribe}");
+    assert_eq!(client.get(unsubscribe).send().await.unwrap().status(), 200);
+
+    let stats: Value = admin_request(&app, reqwest::Method::GET, &stats_path, None).await.json().await.unwrap();
+    assert_eq!(
+        stats["counts"],
+        json!({ "queued": 0, "sent": 3, "failed": 0, "bounced": 1, "complained": 1, "opened": 1, "clicked": 1, "unsubscribed": 1 })
+    );
+    assert_eq!(stats["interval"], "hour");
+    assert_eq!(stats["timezone"], "UTC");
+    let sent: i64 = stats["series"].as_array().unwrap().iter().map(|b| b["sent"].as_i64().unwrap()).sum();
+    assert_eq!(sent, 3);
+
+    let path = format!("{stats_path}?interval=day&timezone=Asia/Tokyo");
+    let stats: Value = admin_request(&app, reqwest::Method::GET, &path, None).await.json().await.unwrap();
+    assert_eq!(stats["series"].as_array().unwrap().len(), 1);
+    assert_eq!(stats["series"][0]["opened"], 1);
+    // c read it at 40 past an hour, which is 10 past in a timezone half an hour off UTC
+    let read_at = Utc::now().duration_trunc(Duration::hours(1)).unwrap() - Duration::minutes(140);
+    let c_delivery: Uuid = mails[2]["metadata"]["delivery_id"].as_str().unwrap().parse().unwrap();
+    sqlx::query!(
+        "INSERT INTO engagement_events (delivery_id, kind, occurred_at) VALUES ($1, 'open', $2)",
+        c_delivery,
+        read_at,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let path = format!("{stats_path}?timezone=Asia/Kolkata");
+    let stats: Value = admin_request(&app, reqwest::Method::GET, &path, None).await.json().await.unwrap();
+    let series = stats["series"].as_array().unwrap();
+    assert!(series.iter().all(|b| b["start"].as_str().unwrap().contains(":30:00")), "{series:?}");
+    let bucket = series.iter().find(|b| b["opened"] == 1 && b["sent"] == 0).unwrap();
+    assert_eq!(bucket["start"], json!(read_at - Duration::minutes(10)));
+    let path = format!("{stats_path}?timezone=Nowhere");
+    assert_eq!(admin_request(&app, reqwest::Method::GET, &path, None).await.status(), 400);
+    let path = format!("/admin/issues/{}/stats", Uuid::new_v4());
+    assert_eq!(admin_request(&app, reqwest::Method::GET, &path, None).await.status(), 404);
+}
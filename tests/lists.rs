@@ -0,0 +1,182 @@
+mod utils;
+
+use crate::utils::spawn_app;
+use serde_json::Value;
+
+async fn create_list(app: &utils::TestAppInfo, name: &str) -> String {
+    let resp = reqwest::Client::new()
+        .post(format!("http://{}/admin/lists", app.socket_addr))
+        .bearer_auth(&app.admin_token)
+        .json(&serde_json::json!({ "name": name }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 201);
+    let body: Value = resp.json().await.unwrap();
+    body["id"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn creating_a_list_requires_an_admin_token() {
+    let app = spawn_app().await.unwrap();
+
+    let resp = reqwest::Client::new()
+        .post(format!("http://{}/admin/lists", app.socket_addr))
+        .bearer_auth("wrong-token")
+        .json(&serde_json::json!({ "name": "weekly" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(resp.status(), 401);
+}
+
+#[tokio::test]
+async fn existing_subscribers_are_members_of_the_default_list() {
+    let app = spawn_app().await.unwrap();
+
+    let resp = reqwest::get(format!("http://{}/lists", app.socket_addr))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let lists: Value = resp.json().await.unwrap();
+    assert_eq!(lists.as_array().unwrap().len(), 1);
+    assert_eq!(lists[0]["name"], "default");
+}
+
+#[tokio::test]
+async fn subscribing_to_an_unknown_list_returns_404() {
+    let app = spawn_app().await.unwrap();
+
+    let resp = reqwest::Client::new()
+        .post(format!(
+            "http://{}/lists/{}/subscription",
+            app.socket_addr,
+            uuid::Uuid::new_v4()
+        ))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("username=username&email=username%40example.com")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(resp.status(), 404);
+}
+
+#[tokio::test]
+async fn a_subscriber_can_join_confirm_and_leave_a_list() {
+    let mut app = spawn_app().await.unwrap();
+    let list_id = create_list(&app, "weekly").await;
+
+    let emails = app.capture_emails();
+
+    let client = reqwest::Client::new();
+    for url in [
+        format!("http://{}/subscription", app.socket_addr),
+        format!("http://{}/lists/{list_id}/subscription", app.socket_addr),
+    ] {
+        let resp = client
+            .post(url)
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body("username=username&email=username%40example.com")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+    }
+
+    // one subscriber, two pending memberships
+    let subscribers = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscribers.len(), 1);
+
+    let bodies = emails.bodies();
+    assert!(bodies[0].contains("http://127.0.0.1:8080/subscription/confirm?token="));
+    assert!(bodies[1].contains(&format!("/lists/{list_id}/subscription/confirm?token=")));
+    let token = emails.last_token();
+
+    let resp = client
+        .get(format!(
+            "http://{}/lists/{list_id}/subscription/confirm?token={token}",
+            app.socket_addr
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+
+    let statuses = sqlx::query!(
+        "SELECT l.name, m.status FROM list_memberships m JOIN lists l ON l.id = m.list_id ORDER BY l.name"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(statuses[0].name, "default");
+    assert_eq!(statuses[0].status, "not-confirmed");
+    assert_eq!(statuses[1].name, "weekly");
+    assert_eq!(statuses[1].status, "confirmed");
+
+    // the confirmation token cannot unsubscribe; newsletters carry their own links
+    let resp = client
+        .get(format!(
+            "http://{}/lists/{list_id}/subscription/unsubscribe?token={token}",
+            app.socket_addr
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 400);
+    let unsubscribe_url = email_sender::delivery::unsubscribe_url(
+        &app.app_state,
+        &app.db_pool,
+        subscribers[0].id,
+        Some(list_id.parse().unwrap()),
+    )
+    .await
+    .unwrap();
+    let base_url = app.app_state.conf.application.base_url.trim_end_matches('/');
+    let resp = client
+        .get(unsubscribe_url.replace(base_url, &format!("http://{}", app.socket_addr)))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+
+    let status = sqlx::query!(
+        "SELECT status FROM list_memberships WHERE list_id = $1",
+        uuid::Uuid::parse_str(&list_id).unwrap()
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .status;
+    assert_eq!(status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn a_token_cannot_confirm_another_list() {
+    let mut app = spawn_app().await.unwrap();
+    let list_id = create_list(&app, "weekly").await;
+
+    let emails = app.capture_emails();
+
+    reqwest::Client::new()
+        .post(format!("http://{}/subscription", app.socket_addr))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("username=username&email=username%40example.com")
+        .send()
+        .await
+        .unwrap();
+
+    let token = emails.last_token();
+
+    let resp = reqwest::get(format!(
+        "http://{}/lists/{list_id}/subscription/confirm?token={token}",
+        app.socket_addr
+    ))
+    .await
+    .unwrap();
+    assert_eq!(resp.status(), 400);
+}
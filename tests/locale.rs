@@ -0,0 +1,103 @@
+mod utils;
+
+use crate::utils::{TestAppInfo, spawn_app, spawn_app_with};
+use serde_json::{Value, json};
+
+async fn subscribe(app: &TestAppInfo, email: &str, locale: Option<&str>, accept_language: Option<&str>) -> reqwest::Response {
+    let mut form = vec![("username", "username"), ("email", email)];
+    if let Some(locale) = locale {
+        form.push(("locale", locale));
+    }
+    let mut request = reqwest::Client::new()
+        .post(format!("http://{}/subscription", app.socket_addr))
+        .form(&form);
+    if let Some(accept_language) = accept_language {
+        request = request.header("Accept-Language", accept_language);
+    }
+    request.send().await.unwrap()
+}
+
+async fn stored_locale(app: &TestAppInfo, email: &str) -> Option<String> {
+    sqlx::query_scalar!("SELECT locale FROM subscriptions WHERE email = $1", email)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+fn subjects(bodies: Vec<String>) -> Vec<String> {
+    bodies
+        .iter()
+        .map(|body| {
+            let body: Value = serde_json::from_str(body).unwrap();
+            body["subject"].as_str().unwrap().to_string()
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn the_locale_is_taken_from_the_form_or_accept_language() {
+    let mut app = spawn_app().await.unwrap();
+    let emails = app.capture_emails();
+
+    let resp = subscribe(&app, "form@example.com", Some("ja"), Some("en")).await;
+    assert_eq!(resp.status(), 200);
+    let resp = subscribe(&app, "header@example.com", None, Some("fr;q=0.9, ja-JP;q=0.8, en;q=0.1")).await;
+    assert_eq!(resp.status(), 200);
+    let resp = subscribe(&app, "default@example.com", None, Some("fr")).await;
+    assert_eq!(resp.status(), 200);
+
+    assert_eq!(stored_locale(&app, "form@example.com").await.as_deref(), Some("ja"));
+    assert_eq!(stored_locale(&app, "header@example.com").await.as_deref(), Some("ja"));
+    assert_eq!(stored_locale(&app, "default@example.com").await, None);
+    assert_eq!(subjects(emails.bodies()), ["ようこそ！", "ようこそ！", "Welcome!"]);
+}
+
+#[tokio::test]
+async fn subscribers_without_a_locale_get_the_default_locale() {
+    let mut app = spawn_app_with(|conf| conf.application.default_locale = "ja".to_string())
+        .await
+        .unwrap();
+    let emails = app.capture_emails();
+
+    let resp = subscribe(&app, "default@example.com", None, Some("fr")).await;
+    assert_eq!(resp.status(), 200);
+
+    assert_eq!(stored_locale(&app, "default@example.com").await, None);
+    assert_eq!(subjects(emails.bodies()), ["ようこそ！"]);
+}
+
+#[tokio::test]
+async fn unsupported_locales_are_rejected() {
+    let app = spawn_app().await.unwrap();
+
+    let resp = subscribe(&app, "username@example.com", Some("xx"), None).await;
+    assert_eq!(resp.status(), 400);
+}
+
+#[tokio::test]
+async fn stored_templates_are_picked_per_locale() {
+    let mut app = spawn_app().await.unwrap();
+    let client = reqwest::Client::new();
+    for (locale, subject) in [(Value::Null, "Hi {{ name }}"), ("ja".into(), "こんにちは {{ name }}")] {
+        let resp = client
+            .post(format!("http://{}/admin/templates", app.socket_addr))
+            .bearer_auth(&app.admin_token)
+            .json(&json!({
+                "name": "confirmation",
+                "locale": locale,
+                "subject": subject,
+                "html": "<a href=\"{{ confirmation_link }}\">confirm</a>",
+                "text": "{{ confirmation_link }}",
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 201);
+    }
+    let emails = app.capture_emails();
+
+    subscribe(&app, "ja@example.com", Some("ja"), None).await;
+    subscribe(&app, "en@example.com", Some("en"), None).await;
+
+    assert_eq!(subjects(emails.bodies()), ["こんにちは username", "Hi username"]);
+}
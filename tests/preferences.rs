@@ -0,0 +1,277 @@
+mod utils;
+
+use crate::utils::{TestAppInfo, spawn_app};
+use serde_json::{Value, json};
+
+/// subscribes `username@example.com` to the default list and returns the token of its
+/// preference center link
+async fn subscribe(app: &mut TestAppInfo) -> String {
+    let _emails = app.capture_emails();
+    reqwest::Client::new()
+        .post(format!("http://{}/subscription", app.socket_addr))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("username=username&email=username%40example.com")
+        .send()
+        .await
+        .unwrap();
+    let (preferences, _) = app.management_links("username@example.com").await;
+    token_of(&preferences)
+}
+
+fn token_of(link: &str) -> String {
+    link.split_once("token=").unwrap().1.to_string()
+}
+
+#[tokio::test]
+async fn preferences_with_an_unknown_token_are_rejected() {
+    let app = spawn_app().await.unwrap();
+
+    for path in ["preferences", "preferences/api"] {
+        let resp = reqwest::get(format!("http://{}/{path}?token=unknown", app.socket_addr))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 401, "{path} accepted an unknown token");
+    }
+}
+
+#[tokio::test]
+async fn tokens_only_work_for_the_action_they_were_mailed_for() {
+    let mut app = spawn_app().await.unwrap();
+    let emails = app.capture_emails();
+    reqwest::Client::new()
+        .post(format!("http://{}/subscription", app.socket_addr))
+        .form(&[("username", "username"), ("email", "username@example.com")])
+        .send()
+        .await
+        .unwrap();
+    let confirmation = emails.last_token();
+    let (preferences, data) = app.management_links("username@example.com").await;
+    let (preferences, data) = (token_of(&preferences), token_of(&data));
+
+    let status = |path: String| async move {
+        reqwest::get(format!("http://{}/{path}", app.socket_addr)).await.unwrap().status()
+    };
+    for token in [&confirmation, &data] {
+        assert_eq!(status(format!("preferences/api?token={token}")).await, 401);
+    }
+    for token in [&confirmation, &preferences] {
+        assert_eq!(status(format!("gdpr/data?token={token}")).await, 401);
+    }
+    for token in [&preferences, &data] {
+        assert_eq!(status(format!("subscription/confirm?token={token}")).await, 400);
+    }
+    assert_eq!(status(format!("preferences/api?token={preferences}")).await, 200);
+    assert_eq!(status(format!("gdpr/data?token={data}")).await, 200);
+
+    // unknown addresses get the same answer and no email
+    let emails = app.capture_emails();
+    let resp = reqwest::Client::new()
+        .post(format!("http://{}/preferences", app.socket_addr))
+        .form(&[("email", "nobody@example.com")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 202);
+    assert!(emails.bodies().is_empty());
+}
+
+async fn request_links(app: &TestAppInfo, email: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("http://{}/preferences", app.socket_addr))
+        .form(&[("email", email)])
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn links_are_mailed_at_most_once_per_interval_whatever_the_case() {
+    let mut app = spawn_app().await.unwrap();
+    subscribe(&mut app).await;
+
+    let emails = app.capture_emails();
+    let resp = request_links(&app, "UserName@Example.com").await;
+    assert_eq!(resp.status(), 202);
+    assert!(emails.bodies().is_empty());
+
+    sqlx::query!("UPDATE subscriptions SET links_requested_at = now() - interval '11 minutes'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let resp = request_links(&app, " UserName@Example.com").await;
+    assert_eq!(resp.status(), 202);
+    let body: Value = serde_json::from_str(&emails.bodies()[0]).unwrap();
+    assert_eq!(body["to"], "username@example.com");
+}
+
+#[tokio::test]
+async fn failed_sends_get_the_same_answer_and_are_not_rate_limited() {
+    let mut app = spawn_app().await.unwrap();
+    subscribe(&mut app).await;
+    sqlx::query!("UPDATE subscriptions SET links_requested_at = NULL")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let unavailable = app.email_server.mock("POST", "/email").with_status(500).create();
+    let resp = request_links(&app, "username@example.com").await;
+    assert_eq!(resp.status(), 202);
+    unavailable.remove();
+
+    let emails = app.capture_emails();
+    let resp = request_links(&app, "username@example.com").await;
+    assert_eq!(resp.status(), 202);
+    assert_eq!(emails.bodies().len(), 1);
+}
+
+#[tokio::test]
+async fn expired_tokens_are_rejected() {
+    let mut app = spawn_app().await.unwrap();
+    let token = subscribe(&mut app).await;
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let resp = reqwest::get(format!("http://{}/preferences/api?token={token}", app.socket_addr))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 401);
+}
+
+#[tokio::test]
+async fn preferences_page_is_served_for_a_valid_token() {
+    let mut app = spawn_app().await.unwrap();
+    let token = subscribe(&mut app).await;
+
+    let resp = reqwest::get(format!("http://{}/preferences?token={token}", app.socket_addr))
+        .await
+        .unwrap();
+
+    assert_eq!(resp.status(), 200);
+    assert!(resp.text().await.unwrap().contains("/preferences/api"));
+}
+
+#[tokio::test]
+async fn subscriber_can_update_their_preferences() {
+    let mut app = spawn_app().await.unwrap();
+    let token = subscribe(&mut app).await;
+    let url = format!("http://{}/preferences/api?token={token}", app.socket_addr);
+    let client = reqwest::Client::new();
+
+    let prefs: Value = client.get(&url).send().await.unwrap().json().await.unwrap();
+    assert_eq!(prefs["email"], "username@example.com");
+    assert_eq!(prefs["digest_frequency"], "immediate");
+    assert_eq!(prefs["lists"][0]["subscribed"], true);
+
+    let resp = client
+        .put(&url)
+        .json(&json!({ "name": "newname", "digest_frequency": "weekly", "lists": [] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let prefs: Value = resp.json().await.unwrap();
+    assert_eq!(prefs["lists"][0]["subscribed"], false);
+
+    let saved = sqlx::query!("SELECT name, digest_frequency FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.name, "newname");
+    assert_eq!(saved.digest_frequency, "weekly");
+}
+
+#[tokio::test]
+async fn choosing_lists_confirms_like_a_confirmation_link() {
+    let mut app = spawn_app().await.unwrap();
+    let token = subscribe(&mut app).await;
+    let url = format!("http://{}/preferences/api?token={token}", app.socket_addr);
+    let client = reqwest::Client::new();
+    let prefs: Value = client.get(&url).send().await.unwrap().json().await.unwrap();
+    assert_eq!(prefs["status"], "not-confirmed");
+
+    let resp = client
+        .put(&url)
+        .header("User-Agent", "preferences-test/1.0")
+        .json(&json!({ "lists": [prefs["lists"][0]["id"]] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let prefs: Value = resp.json().await.unwrap();
+    assert_eq!(prefs["status"], "confirmed");
+    let membership = sqlx::query!("SELECT status FROM list_memberships")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(membership.status, "confirmed");
+
+    let subscriber = sqlx::query!("SELECT id FROM subscriptions").fetch_one(&app.db_pool).await.unwrap();
+    let events: Value = client
+        .get(format!("http://{}/admin/subscribers/{}/consent", app.socket_addr, subscriber.id))
+        .bearer_auth(&app.admin_token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let confirmed = &events[1];
+    assert_eq!(confirmed["event"], "confirmed");
+    assert_eq!(confirmed["list_id"], prefs["lists"][0]["id"]);
+    assert_eq!(confirmed["user_agent"], "preferences-test/1.0");
+
+    // choosing the same list again records nothing new
+    client.put(&url).json(&json!({ "lists": [prefs["lists"][0]["id"]] })).send().await.unwrap();
+    let events: Value = client
+        .get(format!("http://{}/admin/subscribers/{}/consent", app.socket_addr, subscriber.id))
+        .bearer_auth(&app.admin_token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(events.as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn subscriber_can_unsubscribe_from_everything() {
+    let mut app = spawn_app().await.unwrap();
+    let token = subscribe(&mut app).await;
+
+    let resp = reqwest::Client::new()
+        .put(format!("http://{}/preferences/api?token={token}", app.socket_addr))
+        .json(&json!({ "unsubscribe_all": true }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+
+    let status = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status;
+    assert_eq!(status, "unsubscribed");
+    let memberships = sqlx::query!("SELECT status FROM list_memberships")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(memberships.iter().all(|m| m.status == "unsubscribed"));
+}
+
+#[tokio::test]
+async fn invalid_names_are_rejected() {
+    let mut app = spawn_app().await.unwrap();
+    let token = subscribe(&mut app).await;
+
+    let resp = reqwest::Client::new()
+        .put(format!("http://{}/preferences/api?token={token}", app.socket_addr))
+        .json(&json!({ "name": "" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 400);
+}
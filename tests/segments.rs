@@ -0,0 +1,118 @@
+mod utils;
+
+use crate::utils::{TestAppInfo, spawn_app};
+use serde_json::{Value, json};
+
+async fn subscribe(app: &mut TestAppInfo, name: &str, attributes: &str) {
+    let _mock = app
+        .email_server
+        .mock("POST", "/email")
+        .with_status(200)
+        .create();
+    let email = format!("{name}@example.com");
+    reqwest::Client::new()
+        .post(format!("http://{}/subscription", app.socket_addr))
+        .form(&[("username", name), ("email", &email), ("attributes", attributes)])
+        .send()
+        .await
+        .unwrap();
+}
+
+/// three subscribers: `ja` (confirmed, beta), `en` (confirmed) and `pending` (ja, not confirmed)
+async fn seed(app: &mut TestAppInfo) {
+    subscribe(app, "ja", r#"{"locale":"ja"}"#).await;
+    subscribe(app, "en", r#"{"locale":"en"}"#).await;
+    subscribe(app, "pending", r#"{"locale":"ja"}"#).await;
+    sqlx::query!("UPDATE subscriptions SET status = 'confirmed' WHERE name <> 'pending'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    sqlx::query!(
+        "INSERT INTO subscriber_tags (subscriber_uuid, tag, created_at) SELECT id, 'beta', now() FROM subscriptions WHERE name = 'ja'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn preview(app: &TestAppInfo, expression: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("http://{}/admin/segments/preview", app.socket_addr))
+        .bearer_auth(&app.admin_token)
+        .json(&json!({ "expression": expression }))
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn preview_counts_matching_subscribers() {
+    let mut app = spawn_app().await.unwrap();
+    seed(&mut app).await;
+
+    for (expression, expected) in [
+        (r#"status = "confirmed""#, 2),
+        (r#"attributes.locale = "ja""#, 2),
+        (r#"status = "confirmed" and tag = "beta" and attributes.locale = "ja" and subscribed_at > now - 90d"#, 1),
+        (r#"tag != "beta" and status = "confirmed""#, 1),
+        (r#"list = "default""#, 0),
+        (r#"email ~ "EXAMPLE.com" or name = "nobody""#, 3),
+    ] {
+        let resp = preview(&app, expression).await;
+        assert_eq!(resp.status(), 200, "{expression}");
+        let body: Value = resp.json().await.unwrap();
+        assert_eq!(body["count"], expected, "{expression}");
+        assert_eq!(body["sample"].as_array().unwrap().len(), expected, "{expression}");
+    }
+}
+
+#[tokio::test]
+async fn invalid_expressions_are_rejected() {
+    let app = spawn_app().await.unwrap();
+
+    let resp = preview(&app, "status = confirmed; drop table subscriptions").await;
+    assert_eq!(resp.status(), 400);
+}
+
+#[tokio::test]
+async fn saved_segments_can_be_previewed() {
+    let mut app = spawn_app().await.unwrap();
+    seed(&mut app).await;
+    let client = reqwest::Client::new();
+
+    let resp = client
+        .post(format!("http://{}/admin/segments", app.socket_addr))
+        .bearer_auth(&app.admin_token)
+        .json(&json!({ "name": "japanese beta", "expression": r#"tag = "beta" and attributes.locale = "ja""# }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 201);
+    let segment: Value = resp.json().await.unwrap();
+    assert_eq!(segment["created_by"], "admin");
+
+    let preview: Value = client
+        .get(format!(
+            "http://{}/admin/segments/{}/preview",
+            app.socket_addr,
+            segment["id"].as_str().unwrap()
+        ))
+        .bearer_auth(&app.admin_token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(preview["count"], 1);
+    assert_eq!(preview["sample"][0]["email"], "ja@example.com");
+
+    let resp = client
+        .post(format!("http://{}/admin/segments", app.socket_addr))
+        .bearer_auth(&app.admin_token)
+        .json(&json!({ "name": "broken", "expression": "tag =" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 400);
+}
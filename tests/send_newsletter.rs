@@ -0,0 +1,191 @@
+mod utils;
+
+use crate::utils::{TestAppInfo, spawn_app_with};
+use chrono::Utc;
+use email_sender::scheduler;
+use serde_json::{Value, json};
+use uuid::Uuid;
+
+/// An app with a second admin, whose token [`reviewer_token`] returns.
+async fn spawn_app_with_reviewer() -> TestAppInfo {
+    spawn_app_with(|conf| {
+        conf.admin
+            .api_tokens
+            .insert("reviewer".to_string(), "reviewer-token".to_string());
+    })
+    .await
+    .unwrap()
+}
+
+fn reviewer_token(app: &TestAppInfo) -> String {
+    app.app_state
+        .conf
+        .admin
+        .api_tokens
+        .values()
+        .find(|token| **token != app.admin_token)
+        .expect("no second admin")
+        .clone()
+}
+
+async fn insert_subscriber(app: &TestAppInfo, email: &str, name: &str, status: &str, attributes: Value) -> Uuid {
+    let id = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO subscriptions (id, email, name, subscribed_at, status, attributes) VALUES ($1, $2, $3, now(), $4, $5)",
+        id,
+        email,
+        name,
+        status,
+        attributes,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    id
+}
+
+async fn create(app: &TestAppInfo, body: Value) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("http://{}/admin/newsletters", app.socket_addr))
+        .bearer_auth(&app.admin_token)
+        .json(&body)
+        .send()
+        .await
+        .unwrap()
+}
+
+/// Creates a newsletter, has the second admin approve it and runs the scheduler.
+async fn send(app: &TestAppInfo, body: Value) -> scheduler::DeliveryRun {
+    let resp = create(app, body).await;
+    assert_eq!(resp.status(), 201);
+    let issue: Value = resp.json().await.unwrap();
+    assert_eq!(issue["status"], "draft");
+    let resp = reqwest::Client::new()
+        .post(format!("http://{}/admin/issues/{}/approve", app.socket_addr, issue["id"].as_str().unwrap()))
+        .bearer_auth(reviewer_token(app))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    scheduler::promote_due_issues(&app.db_pool, Utc::now()).await.unwrap();
+    scheduler::deliver_queued(&app.app_state, 100).await.unwrap()
+}
+
+#[tokio::test]
+async fn each_confirmed_subscriber_gets_a_personalized_newsletter() {
+    let mut app = spawn_app_with_reviewer().await;
+    let ada = insert_subscriber(&app, "ada@example.com", "Ada", "confirmed", json!({ "city": "Osaka" })).await;
+    insert_subscriber(&app, "bob@example.com", "", "confirmed", json!({})).await;
+    insert_subscriber(&app, "carol@example.com", "Carol", "not-confirmed", json!({})).await;
+    let emails = app.capture_emails();
+
+    let run = send(
+        &app,
+        json!({
+            "subject": "Hi {{ subscriber.name }}",
+            "html": "<a href=\"{{ unsubscribe_url }}\">unsubscribe</a><p>News for {{ subscriber.attributes.city }}</p>",
+            "defaults": { "name": "reader", "attributes": { "city": "Tokyo" } },
+        }),
+    )
+    .await;
+    assert_eq!(run, scheduler::DeliveryRun { sent: 2, ..Default::default() });
+
+    let bodies: Vec<Value> = emails.bodies().iter().map(|b| serde_json::from_str(b).unwrap()).collect();
+    assert_eq!(bodies[0]["to"], "ada@example.com");
+    assert_eq!(bodies[0]["subject"], "Hi Ada");
+    assert!(bodies[0]["html_body"].as_str().unwrap().contains("News for Osaka"));
+    assert_eq!(bodies[1]["subject"], "Hi reader");
+    assert!(bodies[1]["html_body"].as_str().unwrap().contains("News for Tokyo"));
+
+    let unsubscribe = &emails.links()[0];
+    assert!(unsubscribe.starts_with("http://127.0.0.1:8080/subscription/unsubscribe?token="), "{unsubscribe}");
+    let path = unsubscribe.trim_start_matches("http://127.0.0.1:8080");
+    let resp = reqwest::get(format!("http://{}{path}", app.socket_addr)).await.unwrap();
+    assert_eq!(resp.status(), 200);
+    let status = sqlx::query_scalar!("SELECT status FROM subscriptions WHERE id = $1", ada)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn newsletters_with_unknown_variables_are_rejected_before_sending() {
+    let mut app = spawn_app_with_reviewer().await;
+    insert_subscriber(&app, "ada@example.com", "Ada", "confirmed", json!({})).await;
+    let emails = app.capture_emails();
+
+    let resp = create(&app, json!({ "subject": "Hi {{ subscriber.nickname }}", "html": "<p>hi</p>" })).await;
+    assert_eq!(resp.status(), 400);
+    assert!(resp.text().await.unwrap().contains("subscriber.nickname"));
+
+    let resp = create(&app, json!({ "markdown": "---\nsubject: Hi\n---\n{{ unsubscribe }}" })).await;
+    assert_eq!(resp.status(), 400);
+
+    let resp = create(&app, json!({ "subject": "Hi", "html": "<p>hi</p>", "list_id": Uuid::new_v4() })).await;
+    assert_eq!(resp.status(), 404);
+    assert!(emails.bodies().is_empty());
+}
+
+#[tokio::test]
+async fn newsletters_are_not_sent_until_a_second_admin_approves() {
+    let mut app = spawn_app_with_reviewer().await;
+    insert_subscriber(&app, "ada@example.com", "Ada", "confirmed", json!({})).await;
+    let emails = app.capture_emails();
+
+    let resp = create(&app, json!({ "subject": "Hi", "html": "<p>hi</p>" })).await;
+    assert_eq!(resp.status(), 201);
+    let issue: Value = resp.json().await.unwrap();
+    let resp = reqwest::Client::new()
+        .post(format!("http://{}/admin/issues/{}/approve", app.socket_addr, issue["id"].as_str().unwrap()))
+        .bearer_auth(&app.admin_token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 403);
+
+    assert!(scheduler::promote_due_issues(&app.db_pool, Utc::now()).await.unwrap().is_empty());
+    scheduler::deliver_queued(&app.app_state, 100).await.unwrap();
+    assert!(emails.bodies().is_empty());
+}
+
+#[tokio::test]
+async fn markdown_newsletters_are_sent_to_list_members() {
+    let mut app = spawn_app_with_reviewer().await;
+    let ada = insert_subscriber(&app, "ada@example.com", "Ada", "confirmed", json!({})).await;
+    insert_subscriber(&app, "bob@example.com", "Bob", "confirmed", json!({})).await;
+    let list_id = Uuid::new_v4();
+    sqlx::query!("INSERT INTO lists (id, name, description, created_at) VALUES ($1, 'weekly', '', now())", list_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    sqlx::query!(
+        "INSERT INTO list_memberships (list_id, subscriber_uuid, status, subscribed_at) VALUES ($1, $2, 'confirmed', now())",
+        list_id,
+        ada
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let emails = app.capture_emails();
+
+    let run = send(
+        &app,
+        json!({
+            "markdown": "---\nsubject: Weekly for {{ subscriber.name }}\n---\n[Unsubscribe]({{ unsubscribe_url }})\n",
+            "list_id": list_id,
+        }),
+    )
+    .await;
+    assert_eq!(run.sent, 1);
+
+    let bodies = emails.bodies();
+    assert_eq!(bodies.len(), 1);
+    let body: Value = serde_json::from_str(&bodies[0]).unwrap();
+    assert_eq!(body["subject"], "Weekly for Ada");
+    assert!(
+        emails.links()[0].starts_with(&format!("http://127.0.0.1:8080/lists/{list_id}/subscription/unsubscribe?token=")),
+        "{:?}",
+        emails.links()
+    );
+}
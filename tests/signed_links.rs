@@ -0,0 +1,145 @@
+mod utils;
+
+use crate::utils::{TestAppInfo, spawn_app_with};
+use chrono::{Duration, Utc};
+use email_sender::configuration::LinkMode;
+use email_sender::signed_link::{self, LinkAction};
+
+async fn spawn_signed_app() -> TestAppInfo {
+    spawn_app_with(|conf| conf.links.mode = LinkMode::Signed).await.unwrap()
+}
+
+#[tokio::test]
+async fn signed_confirmation_links_confirm_without_a_stored_token() {
+    let mut app = spawn_signed_app().await;
+    let emails = app.capture_emails();
+
+    let resp = reqwest::Client::new()
+        .post(format!("http://{}/subscription", app.socket_addr))
+        .form(&[("username", "username"), ("email", "username@example.com")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+
+    let tokens = sqlx::query!(r#"SELECT count(*) AS "count!" FROM subscription_tokens"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tokens.count, 0);
+
+    let link = emails.links().pop().unwrap();
+    assert!(link.contains("sig="), "{link}");
+    let base_url = app.app_state.conf.application.base_url.trim_end_matches('/');
+    let link = link.replace(base_url, &format!("http://{}", app.socket_addr));
+
+    // any change to the signed parameters invalidates the link
+    let tampered = link.replace("exp=", "exp=1");
+    assert_eq!(reqwest::get(&tampered).await.unwrap().status(), 400);
+
+    assert_eq!(reqwest::get(&link).await.unwrap().status(), 200);
+    let status = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status;
+    assert_eq!(status, "confirmed");
+}
+
+#[tokio::test]
+async fn signed_unsubscribe_links_only_work_for_their_action() {
+    let mut app = spawn_signed_app().await;
+    let emails = app.capture_emails();
+    reqwest::Client::new()
+        .post(format!("http://{}/subscription", app.socket_addr))
+        .form(&[("username", "username"), ("email", "username@example.com")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(emails.links().len(), 1);
+
+    let membership = sqlx::query!("SELECT list_id, subscriber_uuid FROM list_memberships")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let conf = email_sender::configuration::get_configuration().unwrap();
+    let query = signed_link::sign(
+        &conf.links,
+        LinkAction::Unsubscribe,
+        membership.subscriber_uuid,
+        Some(membership.list_id),
+        Utc::now() + Duration::hours(1),
+    )
+    .unwrap();
+    let base = format!("http://{}/lists/{}/subscription", app.socket_addr, membership.list_id);
+
+    let resp = reqwest::get(format!("{base}/confirm?{query}")).await.unwrap();
+    assert_eq!(resp.status(), 400);
+    let resp = reqwest::get(format!("{base}/unsubscribe?{query}")).await.unwrap();
+    assert_eq!(resp.status(), 200);
+
+    let status = sqlx::query!("SELECT status FROM list_memberships")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status;
+    assert_eq!(status, "unsubscribed");
+}
+
+/// subscribes in signed mode and returns the mailed preference and data links
+async fn signed_management_links(app: &mut TestAppInfo) -> (String, String) {
+    let _emails = app.capture_emails();
+    reqwest::Client::new()
+        .post(format!("http://{}/subscription", app.socket_addr))
+        .form(&[("username", "username"), ("email", "username@example.com")])
+        .send()
+        .await
+        .unwrap();
+    let (preferences, data) = app.management_links("username@example.com").await;
+    assert!(preferences.contains("sig=") && data.contains("sig="), "{preferences} {data}");
+    let tokens = sqlx::query!(r#"SELECT count(*) AS "count!" FROM subscription_tokens"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tokens.count, 0);
+    (preferences, data)
+}
+
+#[tokio::test]
+async fn signed_preference_links_open_the_preference_center() {
+    let mut app = spawn_signed_app().await;
+    let (preferences, data) = signed_management_links(&mut app).await;
+    let client = reqwest::Client::new();
+
+    assert_eq!(client.get(&preferences).send().await.unwrap().status(), 200);
+    let api = preferences.replace("/preferences?", "/preferences/api?");
+    let resp = client
+        .put(&api)
+        .json(&serde_json::json!({ "name": "newname" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let prefs: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(prefs["name"], "newname");
+
+    // the signature covers the action
+    let swapped = data.replace("/gdpr/data?", "/preferences/api?");
+    assert_eq!(client.get(&swapped).send().await.unwrap().status(), 401);
+}
+
+#[tokio::test]
+async fn signed_data_links_export_and_erase_the_subscriber() {
+    let mut app = spawn_signed_app().await;
+    let (preferences, data) = signed_management_links(&mut app).await;
+    let client = reqwest::Client::new();
+
+    let swapped = preferences.replace("/preferences?", "/gdpr/data?");
+    assert_eq!(client.get(&swapped).send().await.unwrap().status(), 401);
+
+    let bundle: serde_json::Value = client.get(&data).send().await.unwrap().json().await.unwrap();
+    assert_eq!(bundle["subscriptions"][0]["email"], "username@example.com");
+    assert_eq!(client.delete(&data).send().await.unwrap().status(), 204);
+    // the link is still signed correctly, but its subscriber is gone
+    assert_eq!(client.get(&data).send().await.unwrap().status(), 401);
+}
@@ -0,0 +1,187 @@
+mod utils;
+
+use crate::utils::{TestAppInfo, spawn_app};
+use serde_json::{Value, json};
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+
+async fn insert_subscriber(app: &TestAppInfo, email: &str, name: &str, status: &str, days_ago: i64) -> Uuid {
+    let id = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO subscriptions (id, email, name, subscribed_at, status) VALUES ($1, $2, $3, $4, $5)",
+        id,
+        email,
+        name,
+        Utc::now() - Duration::days(days_ago),
+        status,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    id
+}
+
+async fn get(app: &TestAppInfo, path: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("http://{}{}", app.socket_addr, path))
+        .bearer_auth(&app.admin_token)
+        .send()
+        .await
+        .unwrap()
+}
+
+fn emails(page: &Value) -> Vec<&str> {
+    page["subscribers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s["email"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn listing_subscribers_requires_an_admin_token() {
+    let app = spawn_app().await.unwrap();
+
+    let resp = reqwest::Client::new()
+        .get(format!("http://{}/admin/subscribers", app.socket_addr))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 401);
+}
+
+#[tokio::test]
+async fn listing_subscribers_pages_through_every_subscriber_once() {
+    let app = spawn_app().await.unwrap();
+    for i in 0..5 {
+        insert_subscriber(&app, &format!("user{i}@example.com"), "user", "confirmed", 10 - i).await;
+    }
+
+    let first: Value = get(&app, "/admin/subscribers?limit=2").await.json().await.unwrap();
+    assert_eq!(emails(&first), ["user0@example.com", "user1@example.com"]);
+    let cursor = first["next_cursor"].as_str().unwrap();
+
+    let second: Value = get(&app, &format!("/admin/subscribers?limit=2&cursor={cursor}"))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(emails(&second), ["user2@example.com", "user3@example.com"]);
+    let cursor = second["next_cursor"].as_str().unwrap();
+
+    let last: Value = get(&app, &format!("/admin/subscribers?limit=2&cursor={cursor}"))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(emails(&last), ["user4@example.com"]);
+    assert!(last["next_cursor"].is_null());
+
+    let resp = get(&app, "/admin/subscribers?cursor=garbage").await;
+    assert_eq!(resp.status(), 400);
+}
+
+#[tokio::test]
+async fn listing_subscribers_filters_by_status_date_and_search() {
+    let app = spawn_app().await.unwrap();
+    insert_subscriber(&app, "alice@example.com", "alice", "confirmed", 30).await;
+    insert_subscriber(&app, "bob@example.com", "bob", "confirmed", 1).await;
+    insert_subscriber(&app, "carol@example.com", "carol", "not-confirmed", 1).await;
+
+    let page: Value = get(&app, "/admin/subscribers?status=confirmed").await.json().await.unwrap();
+    assert_eq!(emails(&page), ["alice@example.com", "bob@example.com"]);
+
+    let from = (Utc::now() - Duration::days(7)).to_rfc3339();
+    let page: Value = reqwest::Client::new()
+        .get(format!("http://{}/admin/subscribers", app.socket_addr))
+        .query(&[("from", from.as_str())])
+        .bearer_auth(&app.admin_token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(emails(&page), ["bob@example.com", "carol@example.com"]);
+
+    let page: Value = get(&app, "/admin/subscribers?q=CAR").await.json().await.unwrap();
+    assert_eq!(emails(&page), ["carol@example.com"]);
+}
+
+#[tokio::test]
+async fn subscriber_detail_includes_tags_and_lists() {
+    let app = spawn_app().await.unwrap();
+    let id = insert_subscriber(&app, "alice@example.com", "alice", "confirmed", 1).await;
+    sqlx::query!(
+        "INSERT INTO subscriber_tags (subscriber_uuid, tag, created_at) VALUES ($1, 'beta', now())",
+        id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let detail: Value = get(&app, &format!("/admin/subscribers/{id}")).await.json().await.unwrap();
+    assert_eq!(detail["email"], "alice@example.com");
+    assert_eq!(detail["tags"], json!(["beta"]));
+    assert_eq!(detail["lists"], json!([]));
+
+    let resp = get(&app, &format!("/admin/subscribers/{}", Uuid::new_v4())).await;
+    assert_eq!(resp.status(), 404);
+}
+
+#[tokio::test]
+async fn patching_a_subscriber_updates_status_and_name() {
+    let app = spawn_app().await.unwrap();
+    let id = insert_subscriber(&app, "alice@example.com", "alice", "confirmed", 1).await;
+
+    let resp = reqwest::Client::new()
+        .patch(format!("http://{}/admin/subscribers/{id}", app.socket_addr))
+        .bearer_auth(&app.admin_token)
+        .json(&json!({ "status": "unsubscribed", "name": "alicia" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let detail: Value = resp.json().await.unwrap();
+    assert_eq!(detail["status"], "unsubscribed");
+    assert_eq!(detail["name"], "alicia");
+
+    let resp = reqwest::Client::new()
+        .patch(format!("http://{}/admin/subscribers/{id}", app.socket_addr))
+        .bearer_auth(&app.admin_token)
+        .json(&json!({ "status": "gone" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 422);
+}
+
+#[tokio::test]
+async fn deleting_a_subscriber_removes_it() {
+    let app = spawn_app().await.unwrap();
+    let id = insert_subscriber(&app, "alice@example.com", "alice", "confirmed", 1).await;
+    sqlx::query!(
+        r#"INSERT INTO subscription_tokens (subscription_token, subscriber_uuid, action, expires_at)
+        VALUES ('token', $1, 'unsubscribe', now() + interval '1 day')"#,
+        id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let delete = || {
+        reqwest::Client::new()
+            .delete(format!("http://{}/admin/subscribers/{id}", app.socket_addr))
+            .bearer_auth(&app.admin_token)
+            .send()
+    };
+    assert_eq!(delete().await.unwrap().status(), 204);
+    assert_eq!(delete().await.unwrap().status(), 404);
+
+    let remaining = sqlx::query!("SELECT count(*) AS \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(remaining.count, 0);
+}
@@ -1,8 +1,7 @@
 mod utils;
 
 use axum::http;
-use sqlx::{Connection, Executor, PgConnection, PgPool};
-use std::env::set_var;
+use sqlx::{Connection, PgConnection};
 use utils::spawn_app;
 
 #[tokio::test]
@@ -51,12 +50,6 @@ async fn subscribe_returns_400_with_invalid_data() {
     let url = format!("http://{}/subscription", test_info.socket_addr);
     let client = reqwest::Client::new();
 
-    let pg_option = test_info.db_pool.connect_options();
-
-    let con = PgConnection::connect_with(&pg_option)
-        .await
-        .expect("error connecting to postgres");
-
     let test_cases = vec![
         ("username=username", "need email address"),
         ("email=username@example.com", "need username"),
@@ -89,12 +82,6 @@ async fn subscribe_returns_400_with_invalid_individual_data() {
     let url = format!("http://{}/subscription", test_info.socket_addr);
     let client = reqwest::Client::new();
 
-    let pg_option = test_info.db_pool.connect_options();
-
-    let con = PgConnection::connect_with(&pg_option)
-        .await
-        .expect("error connecting to postgres");
-
     let test_cases = vec![
         (
             "username=username&email=testdataexample.com",
@@ -142,12 +129,37 @@ async fn subscribe_sends_a_confirmation_email() {
         .expect(1)
         .create();
 
-    let client = reqwest::Client::new()
+    reqwest::Client::new()
         .post(format!("http://{}/subscription", app_info.socket_addr))
         .header("Content-Type", "application/x-www-form-urlencoded")
         .body(body)
         .send()
-        .await;
+        .await
+        .unwrap();
 
     mock.assert_async().await;
 }
+
+#[tokio::test]
+async fn the_confirmation_text_part_is_derived_from_the_html() {
+    let mut app_info = spawn_app().await.unwrap();
+    let emails = app_info.capture_emails();
+
+    reqwest::Client::new()
+        .post(format!("http://{}/subscription", app_info.socket_addr))
+        .form(&[("username", "username"), ("email", "username@example.com")])
+        .send()
+        .await
+        .unwrap();
+
+    let body: serde_json::Value = serde_json::from_str(&emails.bodies()[0]).unwrap();
+    let text = body["text_body"].as_str().unwrap();
+    let link = &emails.links()[0];
+    assert_eq!(
+        text,
+        format!(
+            "Welcome to our newsletter, username!\n\n\
+            Click here [1] to confirm your subscription.\n\n[1] {link}\n"
+        )
+    );
+}
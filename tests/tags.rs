@@ -0,0 +1,190 @@
+mod utils;
+
+use crate::utils::{TestAppInfo, spawn_app};
+use serde_json::{Value, json};
+use uuid::Uuid;
+
+async fn subscribe(app: &mut TestAppInfo, form: &[(&str, &str)]) -> reqwest::Response {
+    let _mock = app
+        .email_server
+        .mock("POST", "/email")
+        .with_status(200)
+        .create();
+    reqwest::Client::new()
+        .post(format!("http://{}/subscription", app.socket_addr))
+        .form(form)
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn subscriber_id(app: &TestAppInfo, email: &str) -> Uuid {
+    sqlx::query!("SELECT id FROM subscriptions WHERE email = $1", email)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id
+}
+
+#[tokio::test]
+async fn subscribe_stores_optional_attributes() {
+    let mut app = spawn_app().await.unwrap();
+    let resp = subscribe(
+        &mut app,
+        &[
+            ("username", "username"),
+            ("email", "username@example.com"),
+            ("attributes", r#"{"plan":"pro","source":"landing"}"#),
+        ],
+    )
+    .await;
+    assert_eq!(resp.status(), 200);
+
+    let saved = sqlx::query!("SELECT attributes FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.attributes, json!({ "plan": "pro", "source": "landing" }));
+
+    // subscribing again cannot change what is stored about the address
+    let resp = subscribe(
+        &mut app,
+        &[
+            ("username", "username"),
+            ("email", "username@example.com"),
+            ("attributes", r#"{"plan":"free"}"#),
+            ("locale", "ja"),
+        ],
+    )
+    .await;
+    assert_eq!(resp.status(), 200);
+    let saved = sqlx::query!("SELECT attributes, locale FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.attributes, json!({ "plan": "pro", "source": "landing" }));
+    assert_eq!(saved.locale, None);
+}
+
+#[tokio::test]
+async fn subscribe_rejects_attributes_that_are_not_a_json_object() {
+    let mut app = spawn_app().await.unwrap();
+
+    let resp = subscribe(
+        &mut app,
+        &[
+            ("username", "username"),
+            ("email", "username@example.com"),
+            ("attributes", "plan"),
+        ],
+    )
+    .await;
+    assert_eq!(resp.status(), 422);
+}
+
+#[tokio::test]
+async fn tag_endpoints_require_an_admin_token() {
+    let app = spawn_app().await.unwrap();
+
+    let resp = reqwest::Client::new()
+        .put(format!(
+            "http://{}/admin/subscribers/{}/tags/beta",
+            app.socket_addr,
+            Uuid::new_v4()
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 401);
+}
+
+#[tokio::test]
+async fn admin_can_add_and_remove_tags() {
+    let mut app = spawn_app().await.unwrap();
+    subscribe(&mut app, &[("username", "username"), ("email", "username@example.com")]).await;
+    let id = subscriber_id(&app, "username@example.com").await;
+    let client = reqwest::Client::new();
+    let tag_url = |tag: &str| format!("http://{}/admin/subscribers/{id}/tags/{tag}", app.socket_addr);
+
+    for tag in ["beta", "plan-pro"] {
+        let resp = client
+            .put(tag_url(tag))
+            .bearer_auth(&app.admin_token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 204);
+    }
+    let resp = client
+        .delete(tag_url("beta"))
+        .bearer_auth(&app.admin_token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 204);
+
+    let tags: Value = client
+        .get(format!("http://{}/admin/subscribers/{id}/tags", app.socket_addr))
+        .bearer_auth(&app.admin_token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(tags["tags"], json!(["plan-pro"]));
+
+    let resp = client
+        .put(tag_url("not%20a%20tag"))
+        .bearer_auth(&app.admin_token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 400);
+}
+
+#[tokio::test]
+async fn tagging_an_unknown_subscriber_returns_404() {
+    let app = spawn_app().await.unwrap();
+
+    let resp = reqwest::Client::new()
+        .put(format!(
+            "http://{}/admin/subscribers/{}/tags/beta",
+            app.socket_addr,
+            Uuid::new_v4()
+        ))
+        .bearer_auth(&app.admin_token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 404);
+}
+
+#[tokio::test]
+async fn admin_can_bulk_apply_a_tag() {
+    let mut app = spawn_app().await.unwrap();
+    subscribe(&mut app, &[("username", "first"), ("email", "first@example.com")]).await;
+    subscribe(&mut app, &[("username", "second"), ("email", "second@example.com")]).await;
+    subscribe(&mut app, &[("username", "third"), ("email", "third@example.com")]).await;
+    let first = subscriber_id(&app, "first@example.com").await;
+
+    let resp = reqwest::Client::new()
+        .post(format!("http://{}/admin/tags/beta/bulk", app.socket_addr))
+        .bearer_auth(&app.admin_token)
+        .json(&json!({ "subscriber_ids": [first], "emails": ["second@example.com"] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["tagged"], 2);
+
+    let tagged = sqlx::query!(
+        "SELECT s.email FROM subscriber_tags t JOIN subscriptions s ON s.id = t.subscriber_uuid WHERE t.tag = 'beta' ORDER BY s.email"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    let emails: Vec<_> = tagged.into_iter().map(|r| r.email).collect();
+    assert_eq!(emails, ["first@example.com", "second@example.com"]);
+}
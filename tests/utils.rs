@@ -1,14 +1,15 @@
-use std::cell::Cell;
+#![allow(dead_code)]
+
 use std::net::SocketAddr;
-use std::sync::Arc;
-use axum::Router;
-use garde::rules::email;
+use std::sync::{Arc, Mutex};
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
 use email_sender::{app_internal, AppState};
-use email_sender::configuration::{get_configuration, DatabaseSettings};
+use email_sender::configuration::{get_configuration, DatabaseSettings, Settings};
 use email_sender::email_client::EmailClient;
 use email_sender::errors::AppError;
+use email_sender::html_email::HtmlProcessor;
+use email_sender::templates::EmailTemplates;
 use email_sender::validation::ValidatedEmail;
 
 pub async fn configure_database(conf: &DatabaseSettings) -> PgPool {
@@ -41,6 +42,11 @@ pub async fn configure_database(conf: &DatabaseSettings) -> PgPool {
 
 /// returns server_addr and pgpool
 pub async fn spawn_app() -> Result<TestAppInfo, AppError> {
+    spawn_app_with(|_| {}).await
+}
+
+/// Like [`spawn_app`], with `configure` applied to the configuration before the app starts.
+pub async fn spawn_app_with(configure: impl FnOnce(&mut Settings)) -> Result<TestAppInfo, AppError> {
     let email_server = mockito::Server::new_async().await;
     println!("mock addr {:?}", email_server.url());
     let addr = SocketAddr::from(([127, 0, 0, 1], 0)); // port-0はOSが自動でportを割り当てる
@@ -51,32 +57,48 @@ pub async fn spawn_app() -> Result<TestAppInfo, AppError> {
 
     let mut conf = get_configuration().expect("error getting configuration");
     conf.database.database_name = Uuid::new_v4().to_string();
+    configure(&mut conf);
     let connection_pool = configure_database(&conf.database).await;
     
+    let admin_token = conf
+        .admin
+        .api_tokens
+        .values()
+        .next()
+        .expect("no admin token configured")
+        .clone();
+
     let timeout = conf.email_client.timeout();
     let client = EmailClient::new(
         &email_server.url(),
         ValidatedEmail::parse(&conf.email_client.sender_email)?,
         &conf.email_client.authorization_token,
-        timeout
+        timeout,
+        HtmlProcessor::new(&conf.application.base_url).expect("invalid base url"),
     );
     
+    let templates = EmailTemplates::load(&conf.application.templates_dir, &conf.application.locales)
+        .map_err(|e| AppError::ConfigError(e.to_string()))?;
+
     let app_state = AppState {
         pg_pool: Arc::new(connection_pool.clone()),
         email_client: Arc::from(client),
+        templates: Arc::new(templates),
         conf: Arc::new(conf),
     };
     
-    let app = app_internal(app_state);
+    let app = app_internal(app_state.clone());
     
     let ret_val = TestAppInfo {
         socket_addr,
         db_pool: connection_pool,
-        email_server
+        email_server,
+        admin_token,
+        app_state,
     };
 
     tokio::spawn(async move {
-        axum::serve(listener, app).await.expect("Server failed");
+        axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await.expect("Server failed");
     });
 
     Ok(ret_val)
@@ -85,5 +107,90 @@ pub async fn spawn_app() -> Result<TestAppInfo, AppError> {
 pub struct TestAppInfo {
     pub socket_addr: SocketAddr,
     pub db_pool: PgPool,
-    pub email_server: mockito::ServerGuard
-}
\ No newline at end of file
+    pub email_server: mockito::ServerGuard,
+    pub admin_token: String,
+    /// for driving background jobs such as [`email_sender::scheduler`] from tests
+    pub app_state: AppState,
+}
+
+impl TestAppInfo {
+    /// Mocks the email API, keeping the request bodies so tests can follow the mailed links.
+    pub fn capture_emails(&mut self) -> SentEmails {
+        let bodies = Arc::new(Mutex::new(Vec::new()));
+        let sink = bodies.clone();
+        let mock = self
+            .email_server
+            .mock("POST", "/email")
+            .with_status(200)
+            .with_body_from_request(move |request| {
+                let body = request.utf8_lossy_body().unwrap().into_owned();
+                sink.lock().unwrap().push(body);
+                Vec::new()
+            })
+            .create();
+        SentEmails { _mock: mock, bodies }
+    }
+
+    /// Asks for the links to manage the subscription of `email` and returns them pointing
+    /// at this app: the preference center first, then the subscriber's data.
+    pub async fn management_links(&mut self, email: &str) -> (String, String) {
+        let emails = self.capture_emails();
+        let resp = reqwest::Client::new()
+            .post(format!("http://{}/preferences", self.socket_addr))
+            .form(&[("email", email)])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 202);
+        let body: serde_json::Value = serde_json::from_str(&emails.bodies().pop().unwrap()).unwrap();
+        let base_url = self.app_state.conf.application.base_url.trim_end_matches('/');
+        let mut links = regex::Regex::new(r"https?://\S+")
+            .unwrap()
+            .find_iter(body["text_body"].as_str().unwrap())
+            .map(|m| m.as_str().replace(base_url, &format!("http://{}", self.socket_addr)))
+            .collect::<Vec<_>>()
+            .into_iter();
+        (links.next().unwrap(), links.next().unwrap())
+    }
+}
+
+pub struct SentEmails {
+    _mock: mockito::Mock,
+    bodies: Arc<Mutex<Vec<String>>>,
+}
+
+impl SentEmails {
+    /// JSON request bodies of the mails sent so far, oldest first.
+    pub fn bodies(&self) -> Vec<String> {
+        self.bodies.lock().unwrap().clone()
+    }
+
+    /// The first link in the text part of each mail sent so far, oldest first.
+    pub fn links(&self) -> Vec<String> {
+        let re = regex::Regex::new(r"https?://\S+").unwrap();
+        self.bodies
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|body| {
+                let body: serde_json::Value = serde_json::from_str(body).unwrap();
+                let text = body["text_body"].as_str().unwrap_or_default();
+                re.find(text).map(|m| m.as_str().to_string())
+            })
+            .collect()
+    }
+
+    /// The `token` of the first link in each mail sent so far, oldest first.
+    pub fn tokens(&self) -> Vec<String> {
+        let re = regex::Regex::new(r"[?&]token=([0-9a-f]+)").unwrap();
+        self.links()
+            .iter()
+            .filter_map(|link| re.captures(link).map(|c| c[1].to_string()))
+            .collect()
+    }
+
+    /// The token mailed last.
+    pub fn last_token(&self) -> String {
+        self.tokens().pop().expect("no token was mailed")
+    }
+}